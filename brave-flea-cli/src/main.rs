@@ -20,13 +20,16 @@ use std::fs;
 use std::path;
 
 // use db::{Database,Result};
-use object_db::{ObjectDB, Result};
+use object_db::{ObjectDB, Redaction, Result};
 
 fn main() -> Result<()> {
     let file = fs::File::open(path::Path::new("Sample.root"))?;
     // let db = Database::open_file(file, false)?;
     let odb = ObjectDB::load_file(file)?;
-    println!("odb = {:#?}", odb);
+
+    for (name, node) in odb.root().iter() {
+        println!("{} = {:?}", name, node.value().dump(Redaction::Redact));
+    }
 
     Ok(())
 }
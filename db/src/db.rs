@@ -52,11 +52,21 @@ pub struct Database {
     long_version_minor: u16,
     avail_list_block: DBAddress,
     avail_list_shadow: Vec<AvailableNodeShadow>,
+    // Blocks released since the last flush. They aren't on the free list on
+    // disk until the next one.
+    released: Vec<AvailableNodeShadow>,
     is_read_only: bool,
+    // How many more writes succeed before they start failing, to test what
+    // the file looks like when a save is cut short.
+    #[cfg(test)]
+    writes_left: Option<usize>,
 }
 
 const AVAILABLE_NODE_SHADOW_SIZE: usize = 8;
 
+// A free block has to be big enough to hold the address of the next free block.
+const MIN_BLOCK_SIZE: u32 = DB_ADDRESS_SIZE as u32;
+
 #[derive(Debug)]
 struct AvailableNodeShadow {
     pub address: DBAddress,
@@ -77,7 +87,10 @@ impl Database {
             long_version_minor: 0,
             avail_list_block: 0,
             avail_list_shadow: vec![],
+            released: vec![],
             is_read_only: read_only,
+            #[cfg(test)]
+            writes_left: None,
         };
 
         let mut buffer: [u8; DATABASE_RECORD_SIZE as usize] = [0; DATABASE_RECORD_SIZE as usize];
@@ -126,10 +139,197 @@ impl Database {
         Ok(db)
     }
 
+    // Starts a new database with nothing in it in the file, replacing
+    // whatever was there.
+    pub fn create_file(mut file: fs::File, system_id: u8) -> Result<Self> {
+        let mut buffer: [u8; DATABASE_RECORD_SIZE as usize] = [0; DATABASE_RECORD_SIZE as usize];
+        buffer[0] = system_id;
+        buffer[1] = DB_VERSION_NUMBER;
+        buffer[30..34].copy_from_slice(&DATABASE_RECORD_SIZE.to_be_bytes());
+
+        file.set_len(0)?;
+        file.seek(io::SeekFrom::Start(0))?;
+        file.write_all(&buffer)?;
+
+        Self::open_file(file, false)
+    }
+
+    pub fn get_system_id(&self) -> u8 {
+        self.system_id
+    }
+
     pub fn get_view(&self, view_number: usize) -> DBAddress {
         self.views[view_number]
     }
 
+    pub fn set_view(&mut self, view_number: usize, address: DBAddress) {
+        self.views[view_number] = address;
+        self.is_dirty = true;
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.is_read_only
+    }
+
+    // Writes the data to a new block at the end of the file. Free blocks
+    // aren't reused, so nothing the header on disk refers to is ever
+    // overwritten before the next flush. The space they take up is only
+    // reclaimed when the database is copied to a new file.
+    pub fn allocate(&mut self, data: &[u8]) -> Result<DBAddress> {
+        self.check_writable()?;
+
+        let size = cmp::max(data.len() as u32, MIN_BLOCK_SIZE);
+        let variance = size - data.len() as u32;
+
+        let address = self.get_eof()?;
+        address
+            .checked_add(HEADER_SIZE + size + TRAILER_SIZE)
+            .filter(|end| *end <= i32::MAX as u32)
+            .ok_or(DBError::FileSize)?;
+
+        self.write_header(
+            address,
+            &DBHeader {
+                is_free: false,
+                size,
+                variance,
+            },
+        )?;
+        self.write(address + HEADER_SIZE, data)?;
+        self.is_dirty = true;
+
+        Ok(address)
+    }
+
+    // Marks a block free, merging it with any blocks on either side of it
+    // that were released since the last flush. It goes on the free list the
+    // next time the database is flushed. Blocks that are already on the free
+    // list on disk are left alone.
+    pub fn release_block(&mut self, address: DBAddress) -> Result<()> {
+        self.check_writable()?;
+
+        if address == NIL_DB_ADDRESS {
+            return Err(Error::from(DBError::ReleaseInvalidBlock));
+        }
+
+        let header = self.read_header(address)?;
+        if header.is_free {
+            return Err(Error::from(DBError::ReleaseFreeBlock));
+        }
+
+        let end = address + HEADER_SIZE + header.size + TRAILER_SIZE;
+        if end > self.get_eof()? {
+            return Err(Error::from(DBError::ReleaseInvalidBlock));
+        }
+
+        let mut node = AvailableNodeShadow {
+            address,
+            size: header.size,
+        };
+
+        if let Some(i) = self.released.iter().position(|next| next.address == end) {
+            let next = self.released.remove(i);
+            node.size += HEADER_SIZE + next.size + TRAILER_SIZE;
+        }
+
+        if let Some(i) = self
+            .released
+            .iter()
+            .position(|prev| prev.address + HEADER_SIZE + prev.size + TRAILER_SIZE == address)
+        {
+            let prev = self.released.remove(i);
+            node.address = prev.address;
+            node.size += HEADER_SIZE + prev.size + TRAILER_SIZE;
+        }
+
+        self.write_free_header(&node)?;
+        self.released.insert(0, node);
+        self.is_dirty = true;
+
+        Ok(())
+    }
+
+    // Blocks that the data on disk still refers to are released once the
+    // data that replaces them has been committed.
+    pub fn push_release_stack(&mut self, address: DBAddress) {
+        if address != NIL_DB_ADDRESS {
+            self.release_stack.push(address);
+        }
+    }
+
+    pub fn flush_release_stack(&mut self) -> Result<()> {
+        while let Some(address) = self.release_stack.pop() {
+            self.release_block(address)?;
+        }
+
+        Ok(())
+    }
+
+    // Commits everything written since the last flush by rewriting the
+    // database record at the start of the file. It's the only thing that's
+    // written in place, and it's written once everything it points at is on
+    // disk, so a flush that's cut short leaves the file as it was.
+    pub fn flush(&mut self) -> Result<()> {
+        self.check_writable()?;
+
+        // The cached copy of the free list would be out of date once the
+        // released blocks are added, so the header stops pointing at it. The
+        // list is rebuilt from the free blocks themselves the next time the
+        // file is opened, and the cache's own block is released once nothing
+        // refers to it.
+        let avail_list_block = self.avail_list_block;
+        self.avail_list_block = NIL_DB_ADDRESS;
+        self.write_database_record()?;
+
+        if avail_list_block != NIL_DB_ADDRESS {
+            self.release_block(avail_list_block)?;
+            self.write_database_record()?;
+        }
+
+        Ok(())
+    }
+
+    fn write_database_record(&mut self) -> Result<()> {
+        // Released blocks go on the front of the free list. Only their own
+        // next pointers are written; nothing on the list is touched.
+        let mut avail_list = self.avail_list;
+        for i in (0..self.released.len()).rev() {
+            let address = self.released[i].address;
+            self.write(address + HEADER_SIZE, &avail_list.to_be_bytes())?;
+            avail_list = address;
+        }
+
+        let mut buffer: [u8; DATABASE_RECORD_SIZE as usize] = [0; DATABASE_RECORD_SIZE as usize];
+        self.read(0, DATABASE_RECORD_SIZE, &mut buffer)?;
+
+        buffer[0] = self.system_id;
+        buffer[1] = self.version_number;
+        buffer[2..6].copy_from_slice(&avail_list.to_be_bytes());
+
+        let flags = u16::from_be_bytes(buffer[8..10].try_into()?) & !DIRTY_MASK;
+        buffer[8..10].copy_from_slice(&flags.to_be_bytes());
+
+        for i in 0..VIEW_COUNT {
+            buffer[10 + (i * 4)..14 + (i * 4)].copy_from_slice(&self.views[i].to_be_bytes());
+        }
+
+        buffer[38..42].copy_from_slice(&self.avail_list_block.to_be_bytes());
+
+        // Everything the header points at has to reach the disk before it
+        // does, and the header has to be there before any of the blocks it
+        // replaces are released.
+        self.file.sync_data()?;
+        self.write(0, &buffer)?;
+        self.file.sync_data()?;
+
+        self.avail_list = avail_list;
+        let released = std::mem::take(&mut self.released);
+        self.avail_list_shadow.splice(0..0, released);
+        self.is_dirty = false;
+
+        Ok(())
+    }
+
     pub fn read_block_into_buffer(
         &mut self,
         address: DBAddress,
@@ -152,10 +352,8 @@ impl Database {
     }
 
     fn shadow_avail_list(&mut self) -> Result<()> {
-        if self.avail_list_block != NIL_DB_ADDRESS {
-            if self.read_shadow_avail_list()? {
-                return Ok(());
-            }
+        if self.avail_list_block != NIL_DB_ADDRESS && self.read_shadow_avail_list()? {
+            return Ok(());
         }
 
         let db_eof = self.get_eof()?;
@@ -272,6 +470,47 @@ impl Database {
         DBHeader::new(&buffer)
     }
 
+    fn write_header(&mut self, address: DBAddress, header: &DBHeader) -> Result<()> {
+        self.write(address, &header.to_bytes())?;
+        self.write(
+            address + HEADER_SIZE + header.size,
+            &header.size_and_free().to_be_bytes(),
+        )
+    }
+
+    fn write_free_header(&mut self, node: &AvailableNodeShadow) -> Result<()> {
+        self.write_header(
+            node.address,
+            &DBHeader {
+                is_free: true,
+                size: node.size,
+                variance: 0,
+            },
+        )
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.is_read_only {
+            return Err(Error::from(DBError::ReadOnly));
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, address: DBAddress, buffer: &[u8]) -> Result<()> {
+        #[cfg(test)]
+        if let Some(writes_left) = self.writes_left.as_mut() {
+            if *writes_left == 0 {
+                return Err(Error::from(io::Error::from(io::ErrorKind::Interrupted)));
+            }
+            *writes_left -= 1;
+        }
+
+        self.seek(address)?;
+        self.file.write_all(buffer)?;
+        Ok(())
+    }
+
     fn read(&mut self, address: DBAddress, byte_count: u32, buffer: &mut [u8]) -> Result<()> {
         self.seek(address)?;
 
@@ -291,7 +530,7 @@ impl Database {
     }
 
     fn get_eof(&mut self) -> Result<DBAddress> {
-        let old_pos = self.file.seek(io::SeekFrom::Current(0))?;
+        let old_pos = self.file.stream_position()?;
         let eof = self.file.seek(io::SeekFrom::End(0))?;
 
         // Avoid seeking a third time when we were already at the end of the
@@ -303,3 +542,158 @@ impl Database {
         Ok(eof as DBAddress)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    // Sample.root has one free block, and a cached copy of the free list.
+    const SAMPLE_FREE_BLOCK: DBAddress = 0x24a;
+    const SAMPLE_FREE_BLOCK_SIZE: u32 = 0x53;
+    const SAMPLE_AVAIL_LIST_BLOCK: DBAddress = 0x58;
+
+    fn open_sample_copy(name: &str) -> (Database, std::path::PathBuf) {
+        let sample = concat!(env!("CARGO_MANIFEST_DIR"), "/../Sample.root");
+        let path = env::temp_dir().join(format!("brave-flea-db-{}-{}.root", name, process::id()));
+        fs::copy(sample, &path).unwrap();
+
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        (Database::open_file(file, false).unwrap(), path)
+    }
+
+    fn reopen(path: &std::path::Path) -> Database {
+        Database::open_file(fs::File::open(path).unwrap(), true).unwrap()
+    }
+
+    fn free_blocks(db: &Database) -> Vec<(DBAddress, u32)> {
+        let mut blocks: Vec<_> = db
+            .avail_list_shadow
+            .iter()
+            .map(|node| (node.address, node.size))
+            .collect();
+        blocks.sort_unstable();
+        blocks
+    }
+
+    #[test]
+    fn allocate_appends_and_released_blocks_wait_for_a_flush() {
+        let (mut db, path) = open_sample_copy("append");
+        let eof = db.get_eof().unwrap();
+
+        // The free block is big enough, but it's left alone.
+        let first = db.allocate(b"twenty bytes of data").unwrap();
+        assert_eq!(first, eof);
+        assert_eq!(db.read_block(first).unwrap(), b"twenty bytes of data");
+        let second = db.allocate(b"more data").unwrap();
+        assert_eq!(second, first + 32);
+
+        db.release_block(second).unwrap();
+        db.release_block(first).unwrap();
+        assert_eq!(
+            free_blocks(&db),
+            vec![(SAMPLE_FREE_BLOCK, SAMPLE_FREE_BLOCK_SIZE)]
+        );
+
+        db.flush().unwrap();
+        drop(db);
+
+        // The released blocks were merged, and the cached free list was
+        // given up and released too.
+        let db = reopen(&path);
+        assert_eq!(db.avail_list_block, NIL_DB_ADDRESS);
+        assert_eq!(
+            free_blocks(&db),
+            vec![
+                (SAMPLE_AVAIL_LIST_BLOCK, 32),
+                (SAMPLE_FREE_BLOCK, SAMPLE_FREE_BLOCK_SIZE),
+                (first, 20 + 12 + 9)
+            ]
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    // Saves the way ObjectDB does, stopping after the given number of writes.
+    fn save_new_view(db: &mut Database, data: &[u8], writes: usize) -> Result<()> {
+        db.writes_left = Some(writes);
+
+        let address = db.allocate(data)?;
+        db.push_release_stack(db.get_view(0));
+        db.set_view(0, address);
+        db.flush()?;
+        db.flush_release_stack()?;
+        db.flush()
+    }
+
+    #[test]
+    fn interrupted_saves_leave_the_old_view_or_the_new_one() {
+        for writes in 0.. {
+            let (mut db, path) = open_sample_copy("interrupted");
+            let old_view = db.get_view(0);
+            let old_data = db.read_block(old_view).unwrap();
+
+            let result = save_new_view(&mut db, b"new view", writes);
+            drop(db);
+
+            // Opening the file walks the free list, so it fails if the list
+            // was left pointing at something that isn't free.
+            let mut db = reopen(&path);
+            let view = db.get_view(0);
+            let data = db.read_block(view).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            if result.is_ok() {
+                assert_eq!(data, b"new view");
+                assert!(free_blocks(&db)
+                    .iter()
+                    .any(|(address, _)| *address == old_view));
+                break;
+            }
+
+            assert!(
+                (view == old_view && data == old_data) || data == b"new view",
+                "cut short after {} writes",
+                writes
+            );
+            assert!(free_blocks(&db).iter().all(|(address, _)| *address != view));
+        }
+    }
+
+    #[test]
+    fn released_blocks_wait_for_the_release_stack() {
+        let (mut db, path) = open_sample_copy("release-stack");
+
+        let address = db.allocate(b"old").unwrap();
+        db.push_release_stack(address);
+        assert!(db.read_block(address).is_ok());
+
+        db.flush_release_stack().unwrap();
+        assert!(matches!(
+            db.read_block(address),
+            Err(Error::DB(DBError::FreeBlock))
+        ));
+        assert!(matches!(
+            db.release_block(address),
+            Err(Error::DB(DBError::ReleaseFreeBlock))
+        ));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn read_only_databases_cant_be_changed() {
+        let sample = concat!(env!("CARGO_MANIFEST_DIR"), "/../Sample.root");
+        let mut db = reopen(std::path::Path::new(sample));
+
+        assert!(matches!(
+            db.allocate(b"data"),
+            Err(Error::DB(DBError::ReadOnly))
+        ));
+    }
+}
@@ -31,6 +31,7 @@ pub enum DBError {
     ReleaseInvalidBlock,
     MergeInvalidBlock,
     InvalidAddress,
+    ReadOnly,
 }
 
 impl DBError {
@@ -45,7 +46,8 @@ impl DBError {
             DBError::ReleaseFreeBlock => "Internal database error: attempted to release a free block.",
             DBError::ReleaseInvalidBlock => "Internal database error: attempted to release an invalid block.",
             DBError::MergeInvalidBlock => "Internal database error: attempted to merge with an invalid block.",
-            DBError::InvalidAddress => "Attempted to read from an invalid dbaddress."
+            DBError::InvalidAddress => "Attempted to read from an invalid dbaddress.",
+            DBError::ReadOnly => "The database can't be changed because it was opened read-only.",
         }
     }
 }
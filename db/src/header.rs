@@ -20,6 +20,10 @@ use crate::error::Result;
 use std::convert::TryInto;
 
 pub const HEADER_SIZE: u32 = 8;
+// Every block ends with a copy of the size and free bit from its header.
+pub const TRAILER_SIZE: u32 = 4;
+
+const FREE_MASK: u32 = 0x80000000;
 
 pub struct DBHeader {
     pub is_free: bool,
@@ -32,8 +36,8 @@ impl DBHeader {
         let size_and_free = u32::from_be_bytes(buffer[0..=3].try_into()?);
         let variance = u32::from_be_bytes(buffer[4..=7].try_into()?);
 
-        let is_free = (size_and_free & 0x80000000) == 0x80000000;
-        let size = size_and_free & 0x7FFFFFFF;
+        let is_free = (size_and_free & FREE_MASK) == FREE_MASK;
+        let size = size_and_free & !FREE_MASK;
 
         Ok(Self {
             is_free,
@@ -41,4 +45,19 @@ impl DBHeader {
             variance,
         })
     }
+
+    pub fn size_and_free(&self) -> u32 {
+        if self.is_free {
+            self.size | FREE_MASK
+        } else {
+            self.size
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE as usize] {
+        let mut bytes = [0; HEADER_SIZE as usize];
+        bytes[0..4].copy_from_slice(&self.size_and_free().to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.variance.to_be_bytes());
+        bytes
+    }
 }
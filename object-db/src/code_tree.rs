@@ -0,0 +1,960 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::error::*;
+use crate::obj_spec::*;
+use crate::odb_error::*;
+use crate::outline::*;
+use crate::table::*;
+use crate::text_encoding::*;
+use crate::variable::*;
+use std::cell::RefCell;
+use std::convert::{TryFrom, TryInto};
+
+const CODE_TREE_VERSION: u16 = 1;
+const NODE_HEADER_SIZE: usize = 6;
+const CONSTANT_HEADER_SIZE: usize = 5;
+// How deeply lists of nodes can be nested before the code is treated as
+// damaged, so a bad tree can't overflow the stack.
+const MAX_DEPTH: usize = 512;
+
+// A compiled UserTalk script: the statements of its top level, each one a
+// tree of nodes.
+//
+// Packed, it starts with a version number, followed by the statement list.
+// A list is a 4 byte count of its nodes, followed by the nodes. Each node is
+// its type, numbered in the order of Frontier's tree types, and the line and
+// character where it was compiled from. Then comes its value: a value type,
+// a 4 byte length and the value's bytes. Last is a count of the node's
+// parameters, followed by each parameter as a list. Parameters that are
+// expressions are lists of one node; parameters that are blocks hold one
+// node per statement.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CodeTree {
+    pub version: u16,
+    pub statements: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub value: Constant,
+    pub params: Vec<Vec<Node>>,
+    pub line: u16,
+    pub character: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Noop,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Identifier,
+    Constant,
+    Negate,
+    Not,
+    Assign,
+    FunctionCall,
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+    GreaterOrEqual,
+    LessOrEqual,
+    Or,
+    And,
+    PreIncrement,
+    PostIncrement,
+    PreDecrement,
+    PostDecrement,
+    Loop,
+    FileLoop,
+    ForLoop,
+    Break,
+    Return,
+    Bundle,
+    If,
+    Procedure,
+    Local,
+    Module,
+    Dot,
+    Array,
+    AddressOf,
+    Dereference,
+    AssignLocal,
+    Bracket,
+    Case,
+    CaseItem,
+    CaseBody,
+    Kernel,
+    Continue,
+    With,
+    ForDownLoop,
+    Try,
+    BeginsWith,
+    EndsWith,
+    Contains,
+    Range,
+    List,
+    Field,
+    Record,
+    ForInLoop,
+    Global,
+    OsaScript,
+    AddValue,
+    SubtractValue,
+    MultiplyValue,
+    DivideValue,
+    Unknown(i16),
+}
+
+// Frontier's tree types, in order.
+const NODE_KINDS: [NodeKind; 62] = [
+    NodeKind::Noop,
+    NodeKind::Add,
+    NodeKind::Subtract,
+    NodeKind::Multiply,
+    NodeKind::Divide,
+    NodeKind::Modulo,
+    NodeKind::Identifier,
+    NodeKind::Constant,
+    NodeKind::Negate,
+    NodeKind::Not,
+    NodeKind::Assign,
+    NodeKind::FunctionCall,
+    NodeKind::Equal,
+    NodeKind::NotEqual,
+    NodeKind::Greater,
+    NodeKind::Less,
+    NodeKind::GreaterOrEqual,
+    NodeKind::LessOrEqual,
+    NodeKind::Or,
+    NodeKind::And,
+    NodeKind::PreIncrement,
+    NodeKind::PostIncrement,
+    NodeKind::PreDecrement,
+    NodeKind::PostDecrement,
+    NodeKind::Loop,
+    NodeKind::FileLoop,
+    NodeKind::ForLoop,
+    NodeKind::Break,
+    NodeKind::Return,
+    NodeKind::Bundle,
+    NodeKind::If,
+    NodeKind::Procedure,
+    NodeKind::Local,
+    NodeKind::Module,
+    NodeKind::Dot,
+    NodeKind::Array,
+    NodeKind::AddressOf,
+    NodeKind::Dereference,
+    NodeKind::AssignLocal,
+    NodeKind::Bracket,
+    NodeKind::Case,
+    NodeKind::CaseItem,
+    NodeKind::CaseBody,
+    NodeKind::Kernel,
+    NodeKind::Continue,
+    NodeKind::With,
+    NodeKind::ForDownLoop,
+    NodeKind::Try,
+    NodeKind::BeginsWith,
+    NodeKind::EndsWith,
+    NodeKind::Contains,
+    NodeKind::Range,
+    NodeKind::List,
+    NodeKind::Field,
+    NodeKind::Record,
+    NodeKind::ForInLoop,
+    NodeKind::Global,
+    NodeKind::OsaScript,
+    NodeKind::AddValue,
+    NodeKind::SubtractValue,
+    NodeKind::MultiplyValue,
+    NodeKind::DivideValue,
+];
+
+// The value of an identifier or constant node. Identifiers hold their name
+// as a string.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    None,
+    Char(char),
+    Int(i16),
+    Long(i32),
+    Boolean(bool),
+    Date(u32),
+    Address(String),
+    Double(f64),
+    String(String),
+    Direction(u8),
+    OSType(OSType),
+    Other(u8, Vec<u8>),
+}
+
+impl CodeTree {
+    pub fn new(statements: Vec<Node>) -> Self {
+        Self {
+            version: CODE_TREE_VERSION,
+            statements,
+        }
+    }
+
+    pub fn unpack(bytes: &[u8], context: &LoadContext) -> Result<Self> {
+        let version_bytes = bytes.get(0..2).ok_or(ODBError::BadPackedValue)?;
+        let mut index = 2;
+
+        Ok(Self {
+            version: u16::from_be_bytes(version_bytes.try_into()?),
+            statements: unpack_list(bytes, &mut index, context.text_encoding, 0)?,
+        })
+    }
+
+    pub fn pack(&self, context: &LoadContext) -> Result<Vec<u8>> {
+        let mut bytes = self.version.to_be_bytes().to_vec();
+        pack_list(&self.statements, &mut bytes, context)?;
+        Ok(bytes)
+    }
+
+    // Turns the code back into a script outline, one statement per headline
+    // with blocks as subheads. Fails if the code has anything that can't be
+    // written as UserTalk, rather than leaving it out.
+    pub fn decompile(&self, context: &LoadContext) -> Result<Outline> {
+        let decompiler = Decompiler {
+            encoding: context.text_encoding,
+            unsupported: RefCell::new(None),
+        };
+
+        let mut outline = Outline::new();
+        outline.summits = decompiler.block(&self.statements);
+        match decompiler.unsupported.into_inner() {
+            Some(construct) => Err(Error::from(ODBError::CantDecompile { construct })),
+            None => Ok(outline),
+        }
+    }
+}
+
+impl Node {
+    pub fn new(kind: NodeKind, value: Constant, params: Vec<Vec<Node>>) -> Self {
+        Self {
+            kind,
+            value,
+            params,
+            line: 0,
+            character: 0,
+        }
+    }
+
+    pub fn identifier(name: &str) -> Self {
+        Self::new(
+            NodeKind::Identifier,
+            Constant::String(String::from(name)),
+            vec![],
+        )
+    }
+
+    pub fn constant(value: Constant) -> Self {
+        Self::new(NodeKind::Constant, value, vec![])
+    }
+
+    // A node whose parameters are all expressions.
+    pub fn operation(kind: NodeKind, params: Vec<Node>) -> Self {
+        Self::new(
+            kind,
+            Constant::None,
+            params.into_iter().map(|param| vec![param]).collect(),
+        )
+    }
+
+    fn param(&self, index: usize) -> &[Node] {
+        self.params.get(index).map_or(&[], |param| param.as_slice())
+    }
+}
+
+impl NodeKind {
+    pub fn from_code(code: i16) -> Self {
+        usize::try_from(code)
+            .ok()
+            .and_then(|index| NODE_KINDS.get(index).copied())
+            .unwrap_or(NodeKind::Unknown(code))
+    }
+
+    pub fn code(&self) -> i16 {
+        match self {
+            NodeKind::Unknown(code) => *code,
+            kind => NODE_KINDS
+                .iter()
+                .position(|known| known == kind)
+                .unwrap_or(0) as i16,
+        }
+    }
+
+    fn is_assignment(&self) -> bool {
+        matches!(
+            self,
+            NodeKind::Assign
+                | NodeKind::AssignLocal
+                | NodeKind::AddValue
+                | NodeKind::SubtractValue
+                | NodeKind::MultiplyValue
+                | NodeKind::DivideValue
+        )
+    }
+
+    // The infix operator of binary operations.
+    fn operator(&self) -> Option<&'static str> {
+        let operator = match self {
+            NodeKind::Add => "+",
+            NodeKind::Subtract => "-",
+            NodeKind::Multiply => "*",
+            NodeKind::Divide => "/",
+            NodeKind::Modulo => "%",
+            NodeKind::Assign | NodeKind::AssignLocal => "=",
+            NodeKind::Equal => "==",
+            NodeKind::NotEqual => "!=",
+            NodeKind::Greater => ">",
+            NodeKind::Less => "<",
+            NodeKind::GreaterOrEqual => ">=",
+            NodeKind::LessOrEqual => "<=",
+            NodeKind::Or => "||",
+            NodeKind::And => "&&",
+            NodeKind::BeginsWith => "beginsWith",
+            NodeKind::EndsWith => "endsWith",
+            NodeKind::Contains => "contains",
+            NodeKind::AddValue => "+=",
+            NodeKind::SubtractValue => "-=",
+            NodeKind::MultiplyValue => "*=",
+            NodeKind::DivideValue => "/=",
+            _ => return None,
+        };
+        Some(operator)
+    }
+}
+
+fn unpack_list(
+    bytes: &[u8],
+    index: &mut usize,
+    encoding: TextEncoding,
+    depth: usize,
+) -> Result<Vec<Node>> {
+    if depth > MAX_DEPTH {
+        return Err(Error::from(ODBError::BadPackedValue));
+    }
+
+    let count = read_u32(bytes, index)? as usize;
+
+    let mut nodes = vec![];
+    for _ in 0..count {
+        nodes.push(unpack_node(bytes, index, encoding, depth)?);
+    }
+    Ok(nodes)
+}
+
+fn unpack_node(
+    bytes: &[u8],
+    index: &mut usize,
+    encoding: TextEncoding,
+    depth: usize,
+) -> Result<Node> {
+    let header = bytes
+        .get(*index..(*index + NODE_HEADER_SIZE))
+        .ok_or(ODBError::BadPackedValue)?;
+    let kind = NodeKind::from_code(i16::from_be_bytes(header[0..2].try_into()?));
+    let line = u16::from_be_bytes(header[2..4].try_into()?);
+    let character = u16::from_be_bytes(header[4..6].try_into()?);
+    *index += NODE_HEADER_SIZE;
+
+    let constant_header = bytes
+        .get(*index..(*index + CONSTANT_HEADER_SIZE))
+        .ok_or(ODBError::BadPackedValue)?;
+    let value_type = constant_header[0];
+    let length = u32::from_be_bytes(constant_header[1..5].try_into()?) as usize;
+    *index += CONSTANT_HEADER_SIZE;
+    let value_bytes = bytes
+        .get(*index..(*index + length))
+        .ok_or(ODBError::BadPackedValue)?;
+    *index += length;
+    let value = unpack_constant(value_type, value_bytes, encoding)?;
+
+    let param_count = *bytes.get(*index).ok_or(ODBError::BadPackedValue)?;
+    *index += 1;
+    let mut params = vec![];
+    for _ in 0..param_count {
+        params.push(unpack_list(bytes, index, encoding, depth + 1)?);
+    }
+
+    Ok(Node {
+        kind,
+        value,
+        params,
+        line,
+        character,
+    })
+}
+
+fn unpack_constant(value_type: u8, bytes: &[u8], encoding: TextEncoding) -> Result<Constant> {
+    let fixed = |size: usize| -> Result<&[u8]> {
+        if bytes.len() != size {
+            return Err(Error::from(ODBError::BadPackedValue));
+        }
+        Ok(bytes)
+    };
+
+    let constant = match value_type {
+        x if x == DiskSymbolValueType::NoValue as u8 => Constant::None,
+        x if x == DiskSymbolValueType::CharValue as u8 => {
+            Constant::Char(encoding.decode_byte(fixed(1)?[0]))
+        }
+        x if x == DiskSymbolValueType::IntValue as u8 => {
+            Constant::Int(i16::from_be_bytes(fixed(2)?.try_into()?))
+        }
+        x if x == DiskSymbolValueType::LongValue as u8 => {
+            Constant::Long(i32::from_be_bytes(fixed(4)?.try_into()?))
+        }
+        x if x == DiskSymbolValueType::BooleanValue as u8 => Constant::Boolean(fixed(1)?[0] != 0),
+        x if x == DiskSymbolValueType::DateValue as u8 => {
+            Constant::Date(u32::from_be_bytes(fixed(4)?.try_into()?))
+        }
+        x if x == DiskSymbolValueType::AddressValye as u8 => {
+            Constant::Address(encoding.decode(bytes))
+        }
+        x if x == DiskSymbolValueType::DoubleValue as u8 => {
+            Constant::Double(f64::from_be_bytes(fixed(8)?.try_into()?))
+        }
+        x if x == DiskSymbolValueType::StringValue as u8 => {
+            Constant::String(encoding.decode(bytes))
+        }
+        x if x == DiskSymbolValueType::DirectionValue as u8 => Constant::Direction(fixed(1)?[0]),
+        x if x == DiskSymbolValueType::OSTypeValue as u8 => {
+            Constant::OSType(OSType(fixed(4)?.try_into()?))
+        }
+        _ => Constant::Other(value_type, bytes.to_vec()),
+    };
+
+    Ok(constant)
+}
+
+fn pack_list(nodes: &[Node], bytes: &mut Vec<u8>, context: &LoadContext) -> Result<()> {
+    bytes.extend_from_slice(&(nodes.len() as u32).to_be_bytes());
+    for node in nodes {
+        pack_node(node, bytes, context)?;
+    }
+
+    Ok(())
+}
+
+fn pack_node(node: &Node, bytes: &mut Vec<u8>, context: &LoadContext) -> Result<()> {
+    bytes.extend_from_slice(&node.kind.code().to_be_bytes());
+    bytes.extend_from_slice(&node.line.to_be_bytes());
+    bytes.extend_from_slice(&node.character.to_be_bytes());
+
+    let (value_type, value) = pack_constant(&node.value, context)?;
+    bytes.push(value_type);
+    bytes.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    bytes.extend(value);
+
+    bytes.push(node.params.len() as u8);
+    for param in node.params.iter() {
+        pack_list(param, bytes, context)?;
+    }
+
+    Ok(())
+}
+
+fn pack_constant(constant: &Constant, context: &LoadContext) -> Result<(u8, Vec<u8>)> {
+    let (value_type, bytes) = match constant {
+        Constant::None => (DiskSymbolValueType::NoValue, vec![]),
+        Constant::Char(c) => (
+            DiskSymbolValueType::CharValue,
+            vec![context.encode_byte(*c)?],
+        ),
+        Constant::Int(n) => (DiskSymbolValueType::IntValue, n.to_be_bytes().to_vec()),
+        Constant::Long(n) => (DiskSymbolValueType::LongValue, n.to_be_bytes().to_vec()),
+        Constant::Boolean(b) => (DiskSymbolValueType::BooleanValue, vec![*b as u8]),
+        Constant::Date(seconds) => (
+            DiskSymbolValueType::DateValue,
+            seconds.to_be_bytes().to_vec(),
+        ),
+        Constant::Address(s) => (DiskSymbolValueType::AddressValye, context.encode(s)?),
+        Constant::Double(d) => (DiskSymbolValueType::DoubleValue, d.to_be_bytes().to_vec()),
+        Constant::String(s) => (DiskSymbolValueType::StringValue, context.encode(s)?),
+        Constant::Direction(direction) => (DiskSymbolValueType::DirectionValue, vec![*direction]),
+        Constant::OSType(code) => (DiskSymbolValueType::OSTypeValue, code.0.to_vec()),
+        Constant::Other(value_type, bytes) => return Ok((*value_type, bytes.clone())),
+    };
+
+    Ok((value_type as u8, bytes))
+}
+
+fn read_u32(bytes: &[u8], index: &mut usize) -> Result<u32> {
+    let field = bytes
+        .get(*index..(*index + 4))
+        .ok_or(ODBError::BadPackedValue)?;
+    *index += 4;
+    Ok(u32::from_be_bytes(field.try_into()?))
+}
+
+// Writes nodes as UserTalk. Statements become headlines, and expressions are
+// written out in full, with binary operations inside other expressions
+// parenthesized.
+struct Decompiler {
+    encoding: TextEncoding,
+    // The first thing found that there's no UserTalk for.
+    unsupported: RefCell<Option<String>>,
+}
+
+impl Decompiler {
+    fn block(&self, statements: &[Node]) -> Vec<Headline> {
+        statements
+            .iter()
+            .flat_map(|statement| self.statement(statement))
+            .collect()
+    }
+
+    fn headline(&self, text: String, body: &[Node]) -> Headline {
+        let mut headline = Headline::new(&text);
+        headline.subheads = self.block(body);
+        headline
+    }
+
+    // Most statements are one headline, but if and try statements with else
+    // clauses are two.
+    fn statement(&self, node: &Node) -> Vec<Headline> {
+        let headline = match node.kind {
+            NodeKind::If => {
+                let mut headlines = vec![self.headline(
+                    format!("if {}", self.expression_list(node.param(0))),
+                    node.param(1),
+                )];
+                if !node.param(2).is_empty() {
+                    headlines.push(self.headline(String::from("else"), node.param(2)));
+                }
+                return headlines;
+            }
+            NodeKind::Try => {
+                let mut headlines = vec![self.headline(String::from("try"), node.param(0))];
+                if !node.param(1).is_empty() {
+                    headlines.push(self.headline(String::from("else"), node.param(1)));
+                }
+                return headlines;
+            }
+            NodeKind::Loop => {
+                let (start, condition, step) = (node.param(0), node.param(1), node.param(2));
+                let text = if start.is_empty() && step.is_empty() {
+                    if condition.is_empty() {
+                        String::from("loop")
+                    } else {
+                        format!("while {}", self.expression_list(condition))
+                    }
+                } else {
+                    format!(
+                        "loop ({}; {}; {})",
+                        self.expression_list(start),
+                        self.expression_list(condition),
+                        self.expression_list(step)
+                    )
+                };
+                self.headline(text, node.param(3))
+            }
+            NodeKind::ForLoop | NodeKind::ForDownLoop => {
+                let direction = if node.kind == NodeKind::ForLoop {
+                    "to"
+                } else {
+                    "downto"
+                };
+                self.headline(
+                    format!(
+                        "for {} = {} {} {}",
+                        self.expression_list(node.param(0)),
+                        self.expression_list(node.param(1)),
+                        direction,
+                        self.expression_list(node.param(2))
+                    ),
+                    node.param(3),
+                )
+            }
+            NodeKind::ForInLoop => self.headline(
+                format!(
+                    "for {} in {}",
+                    self.expression_list(node.param(0)),
+                    self.expression_list(node.param(1))
+                ),
+                node.param(2),
+            ),
+            NodeKind::FileLoop => self.headline(
+                format!(
+                    "fileloop ({} in {})",
+                    self.expression_list(node.param(0)),
+                    self.expression_list(node.param(1))
+                ),
+                node.param(2),
+            ),
+            NodeKind::Bundle => self.headline(String::from("bundle"), node.param(0)),
+            NodeKind::With => self.headline(
+                format!("with {}", self.expression_list(node.param(0))),
+                node.param(1),
+            ),
+            NodeKind::Procedure => self.headline(
+                format!("on {}", self.expression_list(node.param(0))),
+                node.param(1),
+            ),
+            NodeKind::Module => return self.block(node.param(0)),
+            NodeKind::Local => {
+                let text = format!("local ({})", self.expression_list(node.param(0)));
+                self.headline(text, &[])
+            }
+            NodeKind::Case => {
+                let mut headline =
+                    Headline::new(&format!("case {}", self.expression_list(node.param(0))));
+                for item in node.param(1) {
+                    headline
+                        .subheads
+                        .push(self.headline(self.expression_list(item.param(0)), item.param(1)));
+                }
+                if !node.param(2).is_empty() {
+                    headline
+                        .subheads
+                        .push(self.headline(String::from("else"), node.param(2)));
+                }
+                headline
+            }
+            NodeKind::Return => {
+                let text = if node.param(0).is_empty() {
+                    String::from("return")
+                } else {
+                    format!("return ({})", self.expression_list(node.param(0)))
+                };
+                self.headline(text, &[])
+            }
+            NodeKind::Break => self.headline(String::from("break"), &[]),
+            NodeKind::Continue => self.headline(String::from("continue"), &[]),
+            NodeKind::Noop => self.headline(String::new(), &[]),
+            _ => self.headline(self.expression(node), &[]),
+        };
+
+        vec![headline]
+    }
+
+    fn expression_list(&self, nodes: &[Node]) -> String {
+        nodes
+            .iter()
+            .map(|node| self.expression(node))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn operand(&self, nodes: &[Node]) -> String {
+        match nodes {
+            [node] if node.kind.operator().is_some() => format!("({})", self.expression(node)),
+            _ => self.expression_list(nodes),
+        }
+    }
+
+    fn expression(&self, node: &Node) -> String {
+        if let Some(operator) = node.kind.operator() {
+            // Assignments bind loosest, so their values need no parentheses.
+            let value = if node.kind.is_assignment() {
+                self.expression_list(node.param(1))
+            } else {
+                self.operand(node.param(1))
+            };
+            return format!("{} {} {}", self.operand(node.param(0)), operator, value);
+        }
+
+        match node.kind {
+            NodeKind::Identifier => match &node.value {
+                Constant::String(name) => name.clone(),
+                value => self.constant(value),
+            },
+            NodeKind::Constant => self.constant(&node.value),
+            NodeKind::Negate => format!("-{}", self.operand(node.param(0))),
+            NodeKind::Not => format!("not {}", self.operand(node.param(0))),
+            NodeKind::FunctionCall => format!(
+                "{} ({})",
+                self.expression_list(node.param(0)),
+                self.expression_list(node.param(1))
+            ),
+            NodeKind::PreIncrement => format!("++{}", self.operand(node.param(0))),
+            NodeKind::PostIncrement => format!("{}++", self.operand(node.param(0))),
+            NodeKind::PreDecrement => format!("--{}", self.operand(node.param(0))),
+            NodeKind::PostDecrement => format!("{}--", self.operand(node.param(0))),
+            NodeKind::Dot => format!(
+                "{}.{}",
+                self.operand(node.param(0)),
+                self.operand(node.param(1))
+            ),
+            NodeKind::Array => format!(
+                "{}[{}]",
+                self.operand(node.param(0)),
+                self.expression_list(node.param(1))
+            ),
+            NodeKind::AddressOf => format!("@{}", self.operand(node.param(0))),
+            NodeKind::Dereference => format!("{}^", self.operand(node.param(0))),
+            NodeKind::Bracket => format!("[{}]", self.expression_list(node.param(0))),
+            NodeKind::Kernel => format!("kernel ({})", self.constant(&node.value)),
+            NodeKind::Range => format!(
+                "{} to {}",
+                self.operand(node.param(0)),
+                self.operand(node.param(1))
+            ),
+            NodeKind::List | NodeKind::Record => {
+                format!("{{{}}}", self.expression_list(node.param(0)))
+            }
+            NodeKind::Field => format!(
+                "{}: {}",
+                self.operand(node.param(0)),
+                self.operand(node.param(1))
+            ),
+            _ => self.unsupported(format!("a {:?} node", node.kind)),
+        }
+    }
+
+    fn constant(&self, value: &Constant) -> String {
+        match value {
+            Constant::None => String::from("nil"),
+            Constant::Char(c) => format!("'{}'", escape(&c.to_string())),
+            Constant::Int(n) => n.to_string(),
+            Constant::Long(n) => n.to_string(),
+            Constant::Boolean(b) => b.to_string(),
+            Constant::Date(seconds) => format!("date ({})", seconds),
+            Constant::Address(address) => format!("@{}", address),
+            Constant::Double(d) => {
+                let text = d.to_string();
+                if text.contains(|c: char| !c.is_ascii_digit() && c != '-') {
+                    text
+                } else {
+                    format!("{}.0", text)
+                }
+            }
+            Constant::String(s) => format!("\"{}\"", escape(s)),
+            Constant::Direction(direction) => format!("direction ({})", direction),
+            Constant::OSType(code) => format!("'{}'", escape(&self.encoding.decode(&code.0))),
+            Constant::Other(value_type, _) => {
+                self.unsupported(format!("a constant of value type {}", value_type))
+            }
+        }
+    }
+
+    // Notes the construct so decompiling fails, and leaves a placeholder.
+    fn unsupported(&self, construct: String) -> String {
+        self.unsupported.borrow_mut().get_or_insert(construct);
+        String::new()
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\'' => escaped.push_str("\\'"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::*;
+
+    fn context() -> LoadContext {
+        LoadContext::for_platform(Platform::Mac)
+    }
+
+    fn string(s: &str) -> Node {
+        Node::constant(Constant::String(String::from(s)))
+    }
+
+    fn call(name: &str, args: Vec<Node>) -> Node {
+        Node::new(
+            NodeKind::FunctionCall,
+            Constant::None,
+            vec![vec![Node::identifier(name)], args],
+        )
+    }
+
+    // on greet (name)
+    //     local (greeting = "Hello, " + name)
+    //     if greeting != ""
+    //         msg (greeting)
+    //     else
+    //         return (false)
+    //     return (true)
+    fn greet() -> CodeTree {
+        let greeting = Node::operation(
+            NodeKind::AssignLocal,
+            vec![
+                Node::identifier("greeting"),
+                Node::operation(
+                    NodeKind::Add,
+                    vec![string("Hello, "), Node::identifier("name")],
+                ),
+            ],
+        );
+        let condition = Node::operation(
+            NodeKind::NotEqual,
+            vec![Node::identifier("greeting"), string("")],
+        );
+        let body = vec![
+            Node::operation(NodeKind::Local, vec![greeting]),
+            Node::new(
+                NodeKind::If,
+                Constant::None,
+                vec![
+                    vec![condition],
+                    vec![call("msg", vec![Node::identifier("greeting")])],
+                    vec![Node::operation(
+                        NodeKind::Return,
+                        vec![Node::constant(Constant::Boolean(false))],
+                    )],
+                ],
+            ),
+            Node::operation(
+                NodeKind::Return,
+                vec![Node::constant(Constant::Boolean(true))],
+            ),
+        ];
+
+        CodeTree::new(vec![Node::new(
+            NodeKind::Procedure,
+            Constant::None,
+            vec![vec![call("greet", vec![Node::identifier("name")])], body],
+        )])
+    }
+
+    #[test]
+    fn packs_what_it_unpacks() {
+        let mut tree = greet();
+        tree.statements[0].line = 1;
+        tree.statements.push(Node::constant(Constant::Double(2.5)));
+        tree.statements
+            .push(Node::constant(Constant::OSType(OSType(*b"TEXT"))));
+        tree.statements
+            .push(Node::constant(Constant::Other(99, vec![1, 2])));
+        tree.statements.push(Node::new(
+            NodeKind::Unknown(200),
+            Constant::Char('é'),
+            vec![],
+        ));
+
+        let bytes = tree.pack(&context()).unwrap();
+        assert_eq!(CodeTree::unpack(&bytes, &context()).unwrap(), tree);
+        assert!(CodeTree::unpack(&bytes[..(bytes.len() - 1)], &context()).is_err());
+
+        assert_eq!(NodeKind::from_code(11), NodeKind::FunctionCall);
+        assert_eq!(NodeKind::DivideValue.code(), 61);
+        assert_eq!(NodeKind::from_code(-1), NodeKind::Unknown(-1));
+    }
+
+    #[test]
+    fn decompiles_to_a_script() {
+        let mut script = Script::default();
+        assert!(script.decompile(&context()).unwrap().is_none());
+
+        script.set_code(greet().pack(&context()).unwrap());
+        assert!(script.recover_source(&context()).unwrap());
+        assert!(!script.recover_source(&context()).unwrap());
+        assert_eq!(
+            script.to_source_text(),
+            concat!(
+                "on greet (name)\n",
+                "\tlocal (greeting = \"Hello, \" + name)\n",
+                "\tif greeting != \"\"\n",
+                "\t\tmsg (greeting)\n",
+                "\telse\n",
+                "\t\treturn (false)\n",
+                "\treturn (true)\n"
+            )
+        );
+
+        let tree = CodeTree::new(vec![Node::operation(
+            NodeKind::Assign,
+            vec![
+                Node::identifier("x"),
+                Node::operation(
+                    NodeKind::Multiply,
+                    vec![
+                        Node::operation(
+                            NodeKind::Add,
+                            vec![
+                                Node::constant(Constant::Long(1)),
+                                Node::constant(Constant::Double(2.0)),
+                            ],
+                        ),
+                        Node::constant(Constant::String(String::from("say \"hi\""))),
+                    ],
+                ),
+            ],
+        )]);
+        assert_eq!(
+            tree.decompile(&context()).unwrap().summits[0].text,
+            "x = (1 + 2.0) * \"say \\\"hi\\\"\""
+        );
+    }
+
+    #[test]
+    fn unsupported_code_is_not_decompiled() {
+        let mut script = Script::default();
+        script.set_code(
+            CodeTree::new(vec![
+                call("msg", vec![string("hi")]),
+                Node::operation(
+                    NodeKind::Assign,
+                    vec![
+                        Node::identifier("x"),
+                        Node::constant(Constant::Other(99, vec![1, 2])),
+                    ],
+                ),
+                Node::new(NodeKind::Global, Constant::None, vec![]),
+            ])
+            .pack(&context())
+            .unwrap(),
+        );
+
+        match script.recover_source(&context()) {
+            Err(Error::ODB(ODBError::CantDecompile { construct })) => {
+                assert_eq!(construct, "a constant of value type 99")
+            }
+            result => panic!("{:?}", result),
+        }
+        assert!(script.source().is_empty());
+    }
+
+    #[test]
+    fn deeply_nested_code_is_damaged() {
+        let mut node = Node::identifier("x");
+        for _ in 0..(MAX_DEPTH + 1) {
+            node = Node::operation(NodeKind::Not, vec![node]);
+        }
+        let bytes = CodeTree::new(vec![node]).pack(&context()).unwrap();
+
+        assert!(matches!(
+            CodeTree::unpack(&bytes, &context()),
+            Err(Error::ODB(ODBError::BadPackedValue))
+        ));
+    }
+}
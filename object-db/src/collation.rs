@@ -0,0 +1,105 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::cmp::Ordering;
+
+// Frontier looks up table items without regard to case, so keys are stored
+// folded to lower case.
+pub fn fold_case(name: &str) -> String {
+    name.chars().flat_map(char::to_lowercase).collect()
+}
+
+// Orders names the way the Mac's Roman script sorts them, which is how
+// Frontier orders a table sorted by name. Letters are compared first without
+// regard to case or diacritical marks, with ligatures expanded into their
+// letters. Ties are broken by the marks, and then by case, upper case first.
+pub fn compare_names(a: &str, b: &str) -> Ordering {
+    primary_key(a)
+        .cmp(primary_key(b))
+        .then_with(|| secondary_key(a).cmp(secondary_key(b)))
+        .then_with(|| tertiary_key(a).cmp(tertiary_key(b)))
+}
+
+fn primary_key(name: &str) -> impl Iterator<Item = char> + '_ {
+    name.chars().flat_map(|c| {
+        let lower = c.to_lowercase().next().unwrap_or(c);
+        let expansion: Vec<char> = match expand_ligature(lower) {
+            Some(letters) => letters.chars().collect(),
+            None => vec![strip_diacritics(lower)],
+        };
+        expansion
+    })
+}
+
+fn secondary_key(name: &str) -> impl Iterator<Item = char> + '_ {
+    name.chars().flat_map(char::to_lowercase)
+}
+
+// Upper case letters sort before their lower case forms.
+fn tertiary_key(name: &str) -> impl Iterator<Item = bool> + '_ {
+    name.chars().map(|c| !c.is_uppercase())
+}
+
+fn expand_ligature(c: char) -> Option<&'static str> {
+    match c {
+        'æ' => Some("ae"),
+        'œ' => Some("oe"),
+        'ß' => Some("ss"),
+        '\u{FB01}' => Some("fi"),
+        '\u{FB02}' => Some("fl"),
+        _ => None,
+    }
+}
+
+// Covers the accented letters in the MacRoman character set.
+fn strip_diacritics(c: char) -> char {
+    match c {
+        'á' | 'à' | 'â' | 'ä' | 'ã' | 'å' | 'ª' => 'a',
+        'ç' => 'c',
+        'é' | 'è' | 'ê' | 'ë' => 'e',
+        'í' | 'ì' | 'î' | 'ï' | 'ı' => 'i',
+        'ñ' => 'n',
+        'ó' | 'ò' | 'ô' | 'ö' | 'õ' | 'ø' | 'º' => 'o',
+        'ú' | 'ù' | 'û' | 'ü' => 'u',
+        'ÿ' => 'y',
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_case() {
+        assert_eq!(fold_case("System"), "system");
+        assert_eq!(fold_case("ÉCOLE"), "école");
+    }
+
+    #[test]
+    fn sorts_like_frontier() {
+        let mut names = vec![
+            "zebra", "Éclair", "apple", "Apple", "eclair", "Æsop", "adz", "b",
+        ];
+        names.sort_by(|a, b| compare_names(a, b));
+        assert_eq!(
+            names,
+            vec!["adz", "Æsop", "Apple", "apple", "b", "eclair", "Éclair", "zebra"]
+        );
+    }
+}
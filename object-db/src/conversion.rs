@@ -0,0 +1,77 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+use crate::path::*;
+use crate::table::*;
+use crate::value_record::*;
+
+// The ODB record version written by Frontier 4.
+pub const FRONTIER_4_RECORD_VERSION: u16 = 1;
+
+// Something that was read in an old format and is saved in the current one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conversion {
+    // The ODB record was converted to the current version.
+    Record { version: u16 },
+    // A field of an old ODB record that couldn't be carried over, because the
+    // record was too short to hold it or it didn't decode. It's left the way
+    // a new root has it.
+    RecordField { field: String },
+    TableHeader { path: String, version: u16 },
+    // One of Frontier's standard tables was missing from the root table, and
+    // an empty one was added.
+    SystemTable { name: String },
+}
+
+// What was upgraded when a Frontier 4 root was opened, or repaired when a
+// root was missing some of its standard tables.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ConversionReport {
+    pub conversions: Vec<Conversion>,
+}
+
+impl ConversionReport {
+    pub fn is_empty(&self) -> bool {
+        self.conversions.is_empty()
+    }
+
+    // Adds every table at or below the root table that was unpacked from an
+    // old header. Only tables that have been loaded are seen.
+    pub(crate) fn add_tables(&mut self, root: &Table) {
+        self.add_table(root, &mut vec![]);
+    }
+
+    fn add_table(&mut self, table: &Table, path: &mut Vec<PathSegment>) {
+        if let Some(version) = table.converted_from() {
+            let path = if path.is_empty() {
+                String::from("root")
+            } else {
+                format_path(path)
+            };
+            self.conversions
+                .push(Conversion::TableHeader { path, version });
+        }
+
+        for (name, node) in table.iter() {
+            if let Value::TableValue(subtable) = node.value() {
+                path.push(PathSegment::Name(String::from(name)));
+                self.add_table(subtable, path);
+                path.pop();
+            }
+        }
+    }
+}
@@ -17,7 +17,6 @@ along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
 use crate::odb_error::ODBError;
-use db;
 use std::array;
 use std::error;
 use std::fmt;
@@ -34,6 +33,11 @@ pub enum Error {
     TryFromSlice(array::TryFromSliceError),
     Io(io::Error),
     SystemTime(time::SystemTimeError),
+    #[cfg(feature = "export")]
+    Json(serde_json::Error),
+    #[cfg(feature = "export")]
+    Yaml(serde_norway::Error),
+    Png(png::EncodingError),
 }
 
 impl fmt::Display for Error {
@@ -44,6 +48,11 @@ impl fmt::Display for Error {
             Error::TryFromSlice(ref err) => write!(f, "Array Error: {}", err),
             Error::Io(ref err) => write!(f, "IO error: {}", err),
             Error::SystemTime(ref err) => write!(f, "SystemTime error: {}", err),
+            #[cfg(feature = "export")]
+            Error::Json(ref err) => write!(f, "JSON error: {}", err),
+            #[cfg(feature = "export")]
+            Error::Yaml(ref err) => write!(f, "YAML error: {}", err),
+            Error::Png(ref err) => write!(f, "PNG error: {}", err),
         }
     }
 }
@@ -57,6 +66,11 @@ impl error::Error for Error {
             Error::TryFromSlice(ref err) => err.description(),
             Error::Io(ref err) => err.description(),
             Error::SystemTime(ref err) => err.description(),
+            #[cfg(feature = "export")]
+            Error::Json(ref err) => err.description(),
+            #[cfg(feature = "export")]
+            Error::Yaml(ref err) => err.description(),
+            Error::Png(ref err) => err.description(),
         }
     }
 
@@ -67,6 +81,11 @@ impl error::Error for Error {
             Error::TryFromSlice(ref err) => Some(err),
             Error::Io(ref err) => Some(err),
             Error::SystemTime(ref err) => Some(err),
+            #[cfg(feature = "export")]
+            Error::Json(ref err) => Some(err),
+            #[cfg(feature = "export")]
+            Error::Yaml(ref err) => Some(err),
+            Error::Png(ref err) => Some(err),
         }
     }
 }
@@ -100,3 +119,23 @@ impl From<time::SystemTimeError> for Error {
         Error::SystemTime(err)
     }
 }
+
+#[cfg(feature = "export")]
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+#[cfg(feature = "export")]
+impl From<serde_norway::Error> for Error {
+    fn from(err: serde_norway::Error) -> Self {
+        Error::Yaml(err)
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(err: png::EncodingError) -> Self {
+        Error::Png(err)
+    }
+}
@@ -0,0 +1,421 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::error::*;
+use crate::odb_error::*;
+use crate::string_utils::*;
+use crate::text_encoding::TextEncoding;
+use crate::variable::{LoadContext, Platform};
+use std::convert::TryInto;
+
+// struct FSSpec { short vRefNum; long parID; Str63 name; }
+const FILE_SPEC_NAME_OFFSET: usize = 6;
+const FILE_SPEC_MIN_SIZE: usize = FILE_SPEC_NAME_OFFSET + 1;
+
+// Offsets into a version 2 AliasRecord, as documented in Inside Macintosh:
+// Files and the Alias Manager.
+const ALIAS_VOLUME_NAME_OFFSET: usize = 10; // Str27
+const ALIAS_PARENT_DIR_ID_OFFSET: usize = 46;
+const ALIAS_FILE_NAME_OFFSET: usize = 50; // Str63
+const ALIAS_EXTRA_DATA_OFFSET: usize = 150;
+
+const ALIAS_TAG_PARENT_NAME: i16 = 0;
+const ALIAS_TAG_ABSOLUTE_PATH: i16 = 2;
+const ALIAS_TAG_POSIX_PATH: i16 = 18;
+const ALIAS_TAG_END: i16 = -1;
+
+// The directory ID of the root directory of an HFS volume.
+const ROOT_DIR_ID: i32 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Volume {
+    RefNum(i16),
+    Name(String),
+}
+
+// The paths embedded in the extra data of an alias record. The record itself
+// is kept so the alias can be written back unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AliasRecord {
+    pub parent_name: Option<String>,
+    pub hfs_path: Option<String>,
+    pub posix_path: Option<String>,
+    pub data: Vec<u8>,
+}
+
+// A file spec read from an alias is written back as the alias, so it's only
+// changed through the setters, which drop the alias.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileSpec {
+    volume: Volume,
+    parent_dir_id: i32,
+    name: String,
+    windows_path: Option<String>,
+    alias: Option<AliasRecord>,
+}
+
+impl FileSpec {
+    pub fn from_parts(ref_num: i16, parent_dir_id: i32, name: &str) -> Self {
+        Self {
+            volume: Volume::RefNum(ref_num),
+            parent_dir_id,
+            name: String::from(name),
+            windows_path: None,
+            alias: None,
+        }
+    }
+
+    // Windows builds of Frontier keep the Mac layout of the record, but the
+    // name field holds the full path to the file.
+    pub fn new(buffer: &[u8], context: &LoadContext) -> Result<Self> {
+        if buffer.len() < FILE_SPEC_MIN_SIZE {
+            return Err(Error::from(ODBError::BadPackedValue));
+        }
+
+        let volume = Volume::RefNum(i16::from_be_bytes(buffer[0..2].try_into()?));
+        let parent_dir_id = i32::from_be_bytes(buffer[2..6].try_into()?);
+        let name =
+            read_bounded_pascal_string(&buffer[FILE_SPEC_NAME_OFFSET..], context.text_encoding)?;
+
+        let (name, windows_path) = match context.platform {
+            Platform::Mac => (name, None),
+            Platform::Windows => {
+                let file_name = name.rsplit('\\').next().unwrap_or("").to_string();
+                (file_name, Some(name))
+            }
+        };
+
+        Ok(Self {
+            volume,
+            parent_dir_id,
+            name,
+            windows_path,
+            alias: None,
+        })
+    }
+
+    pub fn volume(&self) -> &Volume {
+        &self.volume
+    }
+
+    pub fn parent_dir_id(&self) -> i32 {
+        self.parent_dir_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // The full path the spec was read from, on Windows.
+    pub fn windows_path(&self) -> Option<&str> {
+        self.windows_path.as_deref()
+    }
+
+    pub fn alias(&self) -> Option<&AliasRecord> {
+        self.alias.as_ref()
+    }
+
+    pub fn is_alias(&self) -> bool {
+        self.alias.is_some()
+    }
+
+    // An FSSpec only has room for a volume's reference number, so a volume
+    // that's only known by name can't be saved until it's replaced.
+    pub fn set_volume(&mut self, volume: Volume) {
+        self.volume = volume;
+        self.alias = None;
+    }
+
+    pub fn set_parent_dir_id(&mut self, parent_dir_id: i32) {
+        self.parent_dir_id = parent_dir_id;
+        self.alias = None;
+    }
+
+    // On Windows the name is the last component of the path, so the path is
+    // changed to match.
+    pub fn set_name(&mut self, name: &str) {
+        if let Some(path) = &mut self.windows_path {
+            let directory_length = path.rfind('\\').map_or(0, |index| index + 1);
+            path.truncate(directory_length);
+            path.push_str(name);
+        }
+        self.name = String::from(name);
+        self.alias = None;
+    }
+
+    // Aliases are written back as the record they were read from. Everything
+    // else is written as an FSSpec.
+    pub fn to_bytes(&self, context: &LoadContext) -> Result<Vec<u8>> {
+        if let Some(alias) = &self.alias {
+            return Ok(alias.data.clone());
+        }
+
+        let ref_num = match &self.volume {
+            Volume::RefNum(ref_num) => *ref_num,
+            Volume::Name(name) => {
+                return Err(Error::from(ODBError::UnresolvedVolume {
+                    name: name.clone(),
+                }))
+            }
+        };
+
+        let mut bytes = ref_num.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.parent_dir_id.to_be_bytes());
+
+        let (encoding, unmappable) = (context.text_encoding, context.unmappable);
+        match (context.platform, &self.windows_path) {
+            (Platform::Windows, Some(path)) => {
+                bytes.extend(write_pascal_string(path, 256, encoding, unmappable)?)
+            }
+            _ => bytes.extend(write_pascal_string(&self.name, 64, encoding, unmappable)?),
+        }
+
+        Ok(bytes)
+    }
+
+    pub fn from_alias(buffer: &[u8], encoding: TextEncoding) -> Result<Self> {
+        if buffer.len() < ALIAS_EXTRA_DATA_OFFSET {
+            return Err(Error::from(ODBError::BadPackedValue));
+        }
+
+        let volume = Volume::Name(read_bounded_pascal_string(
+            &buffer[ALIAS_VOLUME_NAME_OFFSET..ALIAS_PARENT_DIR_ID_OFFSET],
+            encoding,
+        )?);
+        let parent_dir_id = i32::from_be_bytes(
+            buffer[ALIAS_PARENT_DIR_ID_OFFSET..ALIAS_FILE_NAME_OFFSET].try_into()?,
+        );
+        let name = read_bounded_pascal_string(&buffer[ALIAS_FILE_NAME_OFFSET..], encoding)?;
+
+        let mut alias = AliasRecord {
+            parent_name: None,
+            hfs_path: None,
+            posix_path: None,
+            data: buffer.to_vec(),
+        };
+
+        // The extra data is a list of tagged, even-padded items terminated by
+        // a tag of -1.
+        let mut index = ALIAS_EXTRA_DATA_OFFSET;
+        while index + 4 <= buffer.len() {
+            let tag = i16::from_be_bytes(buffer[index..(index + 2)].try_into()?);
+            let length = u16::from_be_bytes(buffer[(index + 2)..(index + 4)].try_into()?) as usize;
+            index += 4;
+
+            if tag == ALIAS_TAG_END {
+                break;
+            }
+
+            let data = buffer
+                .get(index..(index + length))
+                .ok_or(ODBError::BadPackedValue)?;
+
+            match tag {
+                ALIAS_TAG_PARENT_NAME => {
+                    alias.parent_name = Some(read_fixed_string(data, encoding))
+                }
+                ALIAS_TAG_ABSOLUTE_PATH => alias.hfs_path = Some(read_fixed_string(data, encoding)),
+                ALIAS_TAG_POSIX_PATH => {
+                    alias.posix_path = Some(String::from_utf8_lossy(data).into_owned())
+                }
+                _ => {}
+            }
+
+            index += length + (length % 2);
+        }
+
+        Ok(Self {
+            volume,
+            parent_dir_id,
+            name,
+            windows_path: None,
+            alias: Some(alias),
+        })
+    }
+
+    // Builds the most specific POSIX path the record allows. Classic file
+    // specs only identify the parent directory by number, so those fall back
+    // to the /.vol/<volume>/<directory>/<name> form that macOS resolves by ID.
+    pub fn to_posix_path(&self) -> String {
+        if let Some(path) = &self.windows_path {
+            return windows_to_posix_path(path);
+        }
+
+        if let Some(alias) = &self.alias {
+            if let Some(path) = &alias.posix_path {
+                return path.clone();
+            }
+
+            if let Some(path) = &alias.hfs_path {
+                return hfs_to_posix_path(path);
+            }
+        }
+
+        let name = posix_component(&self.name);
+        match &self.volume {
+            Volume::RefNum(ref_num) => format!("/.vol/{}/{}/{}", ref_num, self.parent_dir_id, name),
+            Volume::Name(volume) => {
+                let mut path = format!("/Volumes/{}", posix_component(volume));
+                let parent_name = self.alias.as_ref().and_then(|a| a.parent_name.as_ref());
+
+                if let (Some(parent), false) = (parent_name, self.parent_dir_id == ROOT_DIR_ID) {
+                    path.push('/');
+                    path.push_str(&posix_component(parent));
+                }
+
+                path.push('/');
+                path.push_str(&name);
+                path
+            }
+        }
+    }
+}
+
+// A pascal string whose length byte is checked against the buffer rather than
+// trusted.
+fn read_bounded_pascal_string(buffer: &[u8], encoding: TextEncoding) -> Result<String> {
+    let length = *buffer.first().ok_or(ODBError::BadPackedValue)? as usize;
+    let bytes = buffer.get(1..=length).ok_or(ODBError::BadPackedValue)?;
+    Ok(read_fixed_string(bytes, encoding))
+}
+
+// Slashes are legal in HFS names and appear as colons in POSIX paths.
+fn posix_component(name: &str) -> String {
+    name.replace('/', ":")
+}
+
+fn hfs_to_posix_path(path: &str) -> String {
+    let components: Vec<_> = path
+        .split(':')
+        .filter(|component| !component.is_empty())
+        .map(posix_component)
+        .collect();
+
+    format!("/Volumes/{}", components.join("/"))
+}
+
+fn windows_to_posix_path(path: &str) -> String {
+    if let Some(unc) = path.strip_prefix("\\\\") {
+        return format!("//{}", unc.replace('\\', "/"));
+    }
+
+    let path = path.replace('\\', "/");
+    match path.find(':') {
+        Some(1) => format!("/{}{}", &path[0..1], &path[2..]),
+        _ => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mac_file_spec() {
+        let mut buffer = vec![0xff, 0xff, 0x00, 0x00, 0x01, 0x2c];
+        buffer.push(8);
+        buffer.extend_from_slice(b"A/B.root");
+
+        let spec = FileSpec::new(&buffer, &LoadContext::for_platform(Platform::Mac)).unwrap();
+        assert_eq!(spec.volume, Volume::RefNum(-1));
+        assert_eq!(spec.parent_dir_id, 300);
+        assert_eq!(spec.name, "A/B.root");
+        assert_eq!(spec.to_posix_path(), "/.vol/-1/300/A:B.root");
+    }
+
+    #[test]
+    fn windows_file_spec() {
+        let path = b"C:\\Frontier\\Guest.root";
+        let mut buffer = vec![0; 6];
+        buffer.push(path.len() as u8);
+        buffer.extend_from_slice(path);
+
+        let context = LoadContext::for_platform(Platform::Windows);
+        let spec = FileSpec::new(&buffer, &context).unwrap();
+        assert_eq!(spec.name, "Guest.root");
+        assert_eq!(spec.to_posix_path(), "/C/Frontier/Guest.root");
+
+        let bytes = spec.to_bytes(&context).unwrap();
+        assert_eq!(FileSpec::new(&bytes, &context).unwrap(), spec);
+    }
+
+    #[test]
+    fn alias_paths() {
+        let mut buffer = vec![0; ALIAS_EXTRA_DATA_OFFSET];
+        buffer[ALIAS_VOLUME_NAME_OFFSET] = 2;
+        buffer[ALIAS_VOLUME_NAME_OFFSET + 1..ALIAS_VOLUME_NAME_OFFSET + 3].copy_from_slice(b"HD");
+        buffer[ALIAS_PARENT_DIR_ID_OFFSET + 3] = 40;
+        buffer[ALIAS_FILE_NAME_OFFSET] = 5;
+        buffer[ALIAS_FILE_NAME_OFFSET + 1..ALIAS_FILE_NAME_OFFSET + 6].copy_from_slice(b"notes");
+
+        let hfs_path = b"HD:Docs:notes";
+        buffer.extend_from_slice(&ALIAS_TAG_ABSOLUTE_PATH.to_be_bytes());
+        buffer.extend_from_slice(&(hfs_path.len() as u16).to_be_bytes());
+        buffer.extend_from_slice(hfs_path);
+        buffer.push(0);
+        buffer.extend_from_slice(&ALIAS_TAG_END.to_be_bytes());
+        buffer.extend_from_slice(&[0, 0]);
+
+        let spec = FileSpec::from_alias(&buffer, TextEncoding::MacRoman).unwrap();
+        assert_eq!(spec.volume, Volume::Name(String::from("HD")));
+        assert_eq!(spec.parent_dir_id, 40);
+        assert_eq!(spec.name, "notes");
+        assert_eq!(spec.to_posix_path(), "/Volumes/HD/Docs/notes");
+    }
+
+    #[test]
+    fn edited_aliases_are_saved_as_file_specs() {
+        let context = LoadContext::for_platform(Platform::Mac);
+        let mut buffer = vec![0; ALIAS_EXTRA_DATA_OFFSET];
+        buffer[ALIAS_VOLUME_NAME_OFFSET] = 2;
+        buffer[ALIAS_VOLUME_NAME_OFFSET + 1..ALIAS_VOLUME_NAME_OFFSET + 3].copy_from_slice(b"HD");
+        buffer[ALIAS_FILE_NAME_OFFSET] = 5;
+        buffer[ALIAS_FILE_NAME_OFFSET + 1..ALIAS_FILE_NAME_OFFSET + 6].copy_from_slice(b"notes");
+
+        let mut spec = FileSpec::from_alias(&buffer, TextEncoding::MacRoman).unwrap();
+        assert_eq!(spec.to_bytes(&context).unwrap(), buffer);
+
+        spec.set_name("todo");
+        assert!(!spec.is_alias());
+        assert!(matches!(
+            spec.to_bytes(&context),
+            Err(Error::ODB(ODBError::UnresolvedVolume { name })) if name == "HD"
+        ));
+
+        spec.set_volume(Volume::RefNum(-1));
+        let saved = FileSpec::new(&spec.to_bytes(&context).unwrap(), &context).unwrap();
+        assert_eq!(saved, FileSpec::from_parts(-1, 0, "todo"));
+    }
+
+    #[test]
+    fn renaming_a_windows_spec_keeps_its_directory() {
+        let path = b"C:\\Frontier\\Guest.root";
+        let mut buffer = vec![0; 6];
+        buffer.push(path.len() as u8);
+        buffer.extend_from_slice(path);
+
+        let context = LoadContext::for_platform(Platform::Windows);
+        let mut spec = FileSpec::new(&buffer, &context).unwrap();
+        spec.set_name("Other.root");
+        assert_eq!(spec.windows_path(), Some("C:\\Frontier\\Other.root"));
+
+        let saved = FileSpec::new(&spec.to_bytes(&context).unwrap(), &context).unwrap();
+        assert_eq!(saved.name(), "Other.root");
+        assert_eq!(saved, spec);
+    }
+}
@@ -16,18 +16,59 @@ You should have received a copy of the GNU General Public License
 along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+mod code_tree;
+mod collation;
+mod conversion;
 mod error;
+mod file_spec;
+mod mac_roman;
+mod menubar;
+mod obj_spec;
 mod object_db;
 mod odb_error;
+mod outline;
+mod pack;
+mod password;
+mod path;
+mod pict;
+mod quickdraw;
+mod script;
+mod sort_order;
 mod string_utils;
 mod table;
+mod table_formats;
 mod table_node;
+mod text_encoding;
 mod value_record;
 mod variable;
 mod window_info;
+mod wptext;
 
+pub use crate::code_tree::{CodeTree, Constant, Node, NodeKind};
+pub use crate::conversion::{Conversion, ConversionReport};
 pub use crate::error::*;
+pub use crate::file_spec::*;
+pub use crate::mac_roman::{decode_mac_roman, encode_mac_roman, Unmappable};
+pub use crate::menubar::{Menu, MenuCommand, MenuItem, MenuSeparator, Menubar};
+pub use crate::obj_spec::*;
 pub use crate::object_db::*;
+pub use crate::odb_error::ODBError;
+pub use crate::outline::{Headline, Headlines, Outline};
+pub use crate::pack::{pack_value, unpack_value};
+pub use crate::password::{Password, Redaction};
+pub use crate::path::{format_path, parse_path, PathSegment};
+pub use crate::pict::{Element, Image, Paint, PictWarning, Picture, Rendering, Shape, Text};
+pub use crate::quickdraw::*;
+pub use crate::script::Script;
+pub use crate::sort_order::SortOrder;
+pub use crate::table::Table;
+pub use crate::table_formats::TableFormats;
+pub use crate::table_node::TableNode;
+pub use crate::text_encoding::TextEncoding;
+pub use crate::value_record::{Dump, ExternalType, Value};
+pub use crate::variable::{LoadContext, Platform};
+pub use crate::window_info::WindowInfo;
+pub use crate::wptext::{Paragraph, Run, WpText};
 
 #[cfg(test)]
 mod tests {
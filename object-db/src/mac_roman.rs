@@ -0,0 +1,139 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::error::*;
+use crate::text_encoding::TextEncoding;
+
+// What to do with a character that MacRoman can't represent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unmappable {
+    // Write this byte in its place.
+    Replace(u8),
+    // Fail with ODBError::UnmappableCharacter.
+    Fail,
+}
+
+impl Default for Unmappable {
+    fn default() -> Self {
+        Unmappable::Replace(b'?')
+    }
+}
+
+// The Unicode character for each MacRoman byte, following Apple's
+// ROMAN.TXT mapping. 0x00 - 0x7F are ASCII, including the control
+// characters, and 0xDB is the euro sign rather than the older currency sign.
+#[rustfmt::skip]
+const DECODE_TABLE: [char; 256] = [
+    '\u{0000}', '\u{0001}', '\u{0002}', '\u{0003}', '\u{0004}', '\u{0005}', '\u{0006}', '\u{0007}', '\u{0008}', '\u{0009}', '\u{000A}', '\u{000B}', '\u{000C}', '\u{000D}', '\u{000E}', '\u{000F}',
+    '\u{0010}', '\u{0011}', '\u{0012}', '\u{0013}', '\u{0014}', '\u{0015}', '\u{0016}', '\u{0017}', '\u{0018}', '\u{0019}', '\u{001A}', '\u{001B}', '\u{001C}', '\u{001D}', '\u{001E}', '\u{001F}',
+    '\u{0020}', '\u{0021}', '\u{0022}', '\u{0023}', '\u{0024}', '\u{0025}', '\u{0026}', '\u{0027}', '\u{0028}', '\u{0029}', '\u{002A}', '\u{002B}', '\u{002C}', '\u{002D}', '\u{002E}', '\u{002F}',
+    '\u{0030}', '\u{0031}', '\u{0032}', '\u{0033}', '\u{0034}', '\u{0035}', '\u{0036}', '\u{0037}', '\u{0038}', '\u{0039}', '\u{003A}', '\u{003B}', '\u{003C}', '\u{003D}', '\u{003E}', '\u{003F}',
+    '\u{0040}', '\u{0041}', '\u{0042}', '\u{0043}', '\u{0044}', '\u{0045}', '\u{0046}', '\u{0047}', '\u{0048}', '\u{0049}', '\u{004A}', '\u{004B}', '\u{004C}', '\u{004D}', '\u{004E}', '\u{004F}',
+    '\u{0050}', '\u{0051}', '\u{0052}', '\u{0053}', '\u{0054}', '\u{0055}', '\u{0056}', '\u{0057}', '\u{0058}', '\u{0059}', '\u{005A}', '\u{005B}', '\u{005C}', '\u{005D}', '\u{005E}', '\u{005F}',
+    '\u{0060}', '\u{0061}', '\u{0062}', '\u{0063}', '\u{0064}', '\u{0065}', '\u{0066}', '\u{0067}', '\u{0068}', '\u{0069}', '\u{006A}', '\u{006B}', '\u{006C}', '\u{006D}', '\u{006E}', '\u{006F}',
+    '\u{0070}', '\u{0071}', '\u{0072}', '\u{0073}', '\u{0074}', '\u{0075}', '\u{0076}', '\u{0077}', '\u{0078}', '\u{0079}', '\u{007A}', '\u{007B}', '\u{007C}', '\u{007D}', '\u{007E}', '\u{007F}',
+    '\u{00C4}', '\u{00C5}', '\u{00C7}', '\u{00C9}', '\u{00D1}', '\u{00D6}', '\u{00DC}', '\u{00E1}', '\u{00E0}', '\u{00E2}', '\u{00E4}', '\u{00E3}', '\u{00E5}', '\u{00E7}', '\u{00E9}', '\u{00E8}',
+    '\u{00EA}', '\u{00EB}', '\u{00ED}', '\u{00EC}', '\u{00EE}', '\u{00EF}', '\u{00F1}', '\u{00F3}', '\u{00F2}', '\u{00F4}', '\u{00F6}', '\u{00F5}', '\u{00FA}', '\u{00F9}', '\u{00FB}', '\u{00FC}',
+    '\u{2020}', '\u{00B0}', '\u{00A2}', '\u{00A3}', '\u{00A7}', '\u{2022}', '\u{00B6}', '\u{00DF}', '\u{00AE}', '\u{00A9}', '\u{2122}', '\u{00B4}', '\u{00A8}', '\u{2260}', '\u{00C6}', '\u{00D8}',
+    '\u{221E}', '\u{00B1}', '\u{2264}', '\u{2265}', '\u{00A5}', '\u{00B5}', '\u{2202}', '\u{2211}', '\u{220F}', '\u{03C0}', '\u{222B}', '\u{00AA}', '\u{00BA}', '\u{03A9}', '\u{00E6}', '\u{00F8}',
+    '\u{00BF}', '\u{00A1}', '\u{00AC}', '\u{221A}', '\u{0192}', '\u{2248}', '\u{2206}', '\u{00AB}', '\u{00BB}', '\u{2026}', '\u{00A0}', '\u{00C0}', '\u{00C3}', '\u{00D5}', '\u{0152}', '\u{0153}',
+    '\u{2013}', '\u{2014}', '\u{201C}', '\u{201D}', '\u{2018}', '\u{2019}', '\u{00F7}', '\u{25CA}', '\u{00FF}', '\u{0178}', '\u{2044}', '\u{20AC}', '\u{2039}', '\u{203A}', '\u{FB01}', '\u{FB02}',
+    '\u{2021}', '\u{00B7}', '\u{201A}', '\u{201E}', '\u{2030}', '\u{00C2}', '\u{00CA}', '\u{00C1}', '\u{00CB}', '\u{00C8}', '\u{00CD}', '\u{00CE}', '\u{00CF}', '\u{00CC}', '\u{00D3}', '\u{00D4}',
+    '\u{F8FF}', '\u{00D2}', '\u{00DA}', '\u{00DB}', '\u{00D9}', '\u{0131}', '\u{02C6}', '\u{02DC}', '\u{00AF}', '\u{02D8}', '\u{02D9}', '\u{02DA}', '\u{00B8}', '\u{02DD}', '\u{02DB}', '\u{02C7}',
+];
+
+pub fn decode_mac_roman_byte(byte: u8) -> char {
+    DECODE_TABLE[byte as usize]
+}
+
+pub fn decode_mac_roman(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| decode_mac_roman_byte(*byte))
+        .collect()
+}
+
+pub fn encode_mac_roman_char(c: char) -> Option<u8> {
+    if c.is_ascii() {
+        return Some(c as u8);
+    }
+
+    DECODE_TABLE[0x80..]
+        .iter()
+        .position(|mac_char| *mac_char == c)
+        .map(|index| (index + 0x80) as u8)
+}
+
+pub fn encode_mac_roman(string: &str, unmappable: Unmappable) -> Result<Vec<u8>> {
+    TextEncoding::MacRoman.encode(string, unmappable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_byte_round_trips() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let string = decode_mac_roman(&bytes);
+        assert_eq!(string.chars().count(), 256);
+        assert_eq!(encode_mac_roman(&string, Unmappable::Fail).unwrap(), bytes);
+
+        for byte in 0..=255 {
+            let c = decode_mac_roman_byte(byte);
+            assert_eq!(encode_mac_roman_char(c), Some(byte), "{:#04x}", byte);
+        }
+    }
+
+    #[test]
+    fn decodes_the_corrected_entries() {
+        assert_eq!(
+            decode_mac_roman(&[0x11, 0x12, 0x13, 0x14]),
+            "\u{11}\u{12}\u{13}\u{14}"
+        );
+        assert_eq!(decode_mac_roman_byte(0xCE), 'Œ');
+        assert_eq!(decode_mac_roman_byte(0xCF), 'œ');
+        assert_eq!(decode_mac_roman_byte(0xD7), '◊');
+        assert_eq!(decode_mac_roman_byte(0xDB), '€');
+        assert_eq!(decode_mac_roman_byte(0xF0), '\u{F8FF}');
+    }
+
+    #[test]
+    fn unmappable_characters_follow_the_policy() {
+        assert_eq!(
+            encode_mac_roman("Café ☕", Unmappable::default()).unwrap(),
+            b"Caf\x8e ?"
+        );
+        assert_eq!(
+            encode_mac_roman("☕", Unmappable::Replace(b'_')).unwrap(),
+            b"_"
+        );
+        assert_eq!(
+            encode_mac_roman("日本", Unmappable::default()).unwrap(),
+            b"??"
+        );
+
+        match encode_mac_roman("Caf☕", Unmappable::Fail) {
+            Err(Error::ODB(error)) => assert_eq!(
+                error.to_string(),
+                "\"☕\" (U+2615) can't be written as a MacRoman character."
+            ),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
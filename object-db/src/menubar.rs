@@ -0,0 +1,546 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::error::*;
+use crate::odb_error::*;
+use crate::outline::*;
+use crate::script::*;
+use crate::variable::*;
+use db::{DBAddress, Database, NIL_DB_ADDRESS};
+#[cfg(feature = "export")]
+use serde::{Serialize, Serializer};
+use std::convert::TryInto;
+
+const SEPARATOR_TEXT: &str = "-";
+const ITEM_INFO_SIZE: usize = 6;
+// The script's address and the command key.
+const ITEM_KEYS_SIZE: usize = 5;
+
+// A menubar is an outline. Each summit is a menu, its subheads are the menu's
+// items, and an item with subheads of its own is a submenu. An item whose
+// text is "-" is a separator.
+//
+// Packed, it's the packed outline. The refcon of each item starts with the
+// address of the item's linked script, or nil if it has none, then its
+// command key, or 0, and a reserved byte. The scripts aren't part of the
+// menubar; each one is saved in a block of its own and read along with the
+// menubar by load_scripts. An item with an empty refcon has neither, and is
+// given one when it's saved.
+//
+// Each menu and item keeps the headline it was read from, so its flags and
+// the rest of its refcon are written back as they were.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Menubar {
+    pub menus: Vec<Menu>,
+    // The outline the menus were read from, without its headlines. It keeps
+    // the version and display state.
+    layout: Outline,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "export", derive(Serialize))]
+pub struct Menu {
+    pub title: String,
+    pub items: Vec<MenuItem>,
+    // The summit without its text and subheads.
+    #[cfg_attr(feature = "export", serde(skip))]
+    headline: Headline,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "export", derive(Serialize))]
+#[cfg_attr(feature = "export", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum MenuItem {
+    Separator(MenuSeparator),
+    Command(MenuCommand),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "export", derive(Serialize))]
+pub struct MenuSeparator {
+    // The item's headline, kept whole.
+    #[cfg_attr(feature = "export", serde(skip))]
+    headline: Box<Headline>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "export", derive(Serialize))]
+pub struct MenuCommand {
+    pub title: String,
+    #[cfg_attr(feature = "export", serde(skip_serializing_if = "Option::is_none"))]
+    pub command_key: Option<char>,
+    // Scripts are exported as their source text.
+    #[cfg_attr(
+        feature = "export",
+        serde(
+            serialize_with = "serialize_script",
+            skip_serializing_if = "Option::is_none"
+        )
+    )]
+    pub script: Option<Script>,
+    #[cfg_attr(feature = "export", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub subitems: Vec<MenuItem>,
+    // Where the linked script was read from or last saved to.
+    #[cfg_attr(feature = "export", serde(skip))]
+    script_address: DBAddress,
+    // The item's headline without its text and subheads. Its refcon is what
+    // follows the script's address and the command key, starting with the
+    // reserved byte.
+    #[cfg_attr(feature = "export", serde(skip))]
+    headline: Box<Headline>,
+}
+
+impl Menubar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[cfg(feature = "export")]
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.menus)?)
+    }
+
+    #[cfg(feature = "export")]
+    pub fn to_yaml(&self) -> Result<String> {
+        Ok(serde_norway::to_string(&self.menus)?)
+    }
+
+    // Unpacks the menubar without its scripts. Each command remembers where
+    // its script is, for load_scripts.
+    pub fn unpack(bytes: &[u8], context: &LoadContext) -> Result<Self> {
+        let mut layout = Outline::unpack(bytes, context)?;
+        let summits = std::mem::take(&mut layout.summits);
+
+        let mut menus = vec![];
+        for mut summit in summits {
+            menus.push(Menu {
+                title: std::mem::take(&mut summit.text),
+                items: unpack_items(std::mem::take(&mut summit.subheads), context)?,
+                headline: summit,
+            });
+        }
+
+        Ok(Self { menus, layout })
+    }
+
+    // Packs the menubar, pointing each command at the block its script was
+    // saved to by save_scripts.
+    pub fn pack(&self, context: &LoadContext) -> Result<Vec<u8>> {
+        let mut outline = self.layout.clone();
+        outline.summits = self
+            .menus
+            .iter()
+            .map(|menu| {
+                let mut headline = menu.headline.clone();
+                headline.text = menu.title.clone();
+                headline.subheads = pack_items(&menu.items, context)?;
+                Ok(headline)
+            })
+            .collect::<Result<_>>()?;
+
+        outline.pack(context)
+    }
+
+    // Reads the linked script of every command from the database.
+    pub(crate) fn load_scripts(&mut self, db: &mut Database, context: &LoadContext) -> Result<()> {
+        for (script, address) in self.linked_scripts_mut() {
+            if *address != NIL_DB_ADDRESS {
+                let variable = Variable::<Script>::new_on_disk(db, context, *address);
+                *script = Some(variable.into_in_memory()?.data);
+            }
+        }
+        Ok(())
+    }
+
+    // Writes every command's script to a new block, before the menubar is
+    // packed. The menubar is only saved when it's new, so the blocks of the
+    // scripts it replaced are released along with the menubar they belonged
+    // to.
+    pub(crate) fn save_scripts(&mut self, db: &mut Database, context: &LoadContext) -> Result<()> {
+        for (script, address) in self.linked_scripts_mut() {
+            *address = match script {
+                Some(script) => save_value(db, context, script, NIL_DB_ADDRESS)?,
+                None => NIL_DB_ADDRESS,
+            };
+        }
+        Ok(())
+    }
+
+    // Queues the blocks of the scripts to be released.
+    pub(crate) fn release_scripts(&self, db: &mut Database) {
+        for command in self.commands() {
+            db.push_release_stack(command.script_address);
+        }
+    }
+
+    // The script of every command, in outline order.
+    pub(crate) fn scripts(&self) -> Vec<Option<&Script>> {
+        self.commands()
+            .into_iter()
+            .map(|command| command.script.as_ref())
+            .collect()
+    }
+
+    // Gives the commands their scripts, in outline order, for menubars that
+    // weren't read from a database. Their scripts haven't been saved yet.
+    pub(crate) fn set_scripts(&mut self, scripts: Vec<Option<Script>>) -> Result<()> {
+        let linked_scripts = self.linked_scripts_mut();
+        if scripts.len() != linked_scripts.len() {
+            return Err(Error::from(ODBError::BadPackedValue));
+        }
+
+        for ((script, address), new_script) in linked_scripts.into_iter().zip(scripts) {
+            *script = new_script;
+            *address = NIL_DB_ADDRESS;
+        }
+        Ok(())
+    }
+
+    fn commands(&self) -> Vec<&MenuCommand> {
+        let mut commands = vec![];
+        for menu in self.menus.iter() {
+            collect_commands(&menu.items, &mut commands);
+        }
+        commands
+    }
+
+    // The script of every command and where it's saved, in outline order.
+    fn linked_scripts_mut(&mut self) -> Vec<(&mut Option<Script>, &mut DBAddress)> {
+        let mut linked_scripts = vec![];
+        for menu in self.menus.iter_mut() {
+            collect_linked_scripts(&mut menu.items, &mut linked_scripts);
+        }
+        linked_scripts
+    }
+}
+
+#[cfg(feature = "export")]
+impl Serialize for Menubar {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.menus.serialize(serializer)
+    }
+}
+
+impl LoadFromBytes for Menubar {
+    fn load_from_bytes(bytes: &[u8], context: &LoadContext) -> Result<Self> {
+        Self::unpack(bytes, context)
+    }
+}
+
+impl SaveToBytes for Menubar {
+    fn save_to_bytes(&self, context: &LoadContext) -> Result<Vec<u8>> {
+        self.pack(context)
+    }
+}
+
+impl Menu {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: String::from(title),
+            ..Self::default()
+        }
+    }
+}
+
+impl MenuSeparator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for MenuSeparator {
+    fn default() -> Self {
+        Self {
+            headline: Box::new(Headline::new(SEPARATOR_TEXT)),
+        }
+    }
+}
+
+impl MenuCommand {
+    pub fn new(title: &str) -> Self {
+        let mut headline = Headline::default();
+        headline.refcon = vec![0; ITEM_INFO_SIZE - ITEM_KEYS_SIZE];
+
+        Self {
+            title: String::from(title),
+            command_key: None,
+            script: None,
+            subitems: vec![],
+            script_address: NIL_DB_ADDRESS,
+            headline: Box::new(headline),
+        }
+    }
+}
+
+impl Default for MenuCommand {
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
+fn unpack_items(headlines: Vec<Headline>, context: &LoadContext) -> Result<Vec<MenuItem>> {
+    let mut items = vec![];
+
+    for mut headline in headlines {
+        if headline.text == SEPARATOR_TEXT {
+            items.push(MenuItem::Separator(MenuSeparator {
+                headline: Box::new(headline),
+            }));
+            continue;
+        }
+
+        let mut command = MenuCommand::new(&std::mem::take(&mut headline.text));
+        if !headline.refcon.is_empty() {
+            let info = headline
+                .refcon
+                .get(0..ITEM_INFO_SIZE)
+                .ok_or(ODBError::BadPackedValue)?;
+            command.script_address = DBAddress::from_be_bytes(info[0..4].try_into()?);
+            if info[4] != 0 {
+                command.command_key = Some(context.text_encoding.decode_byte(info[4]));
+            }
+            headline.refcon.drain(0..ITEM_KEYS_SIZE);
+        } else {
+            headline.refcon = vec![0; ITEM_INFO_SIZE - ITEM_KEYS_SIZE];
+        }
+        command.subitems = unpack_items(std::mem::take(&mut headline.subheads), context)?;
+        command.headline = Box::new(headline);
+
+        items.push(MenuItem::Command(command));
+    }
+
+    Ok(items)
+}
+
+fn pack_items(items: &[MenuItem], context: &LoadContext) -> Result<Vec<Headline>> {
+    items
+        .iter()
+        .map(|item| match item {
+            MenuItem::Separator(separator) => Ok((*separator.headline).clone()),
+            MenuItem::Command(command) => {
+                let mut headline = (*command.headline).clone();
+                headline.text = command.title.clone();
+
+                let mut refcon = command.script_address.to_be_bytes().to_vec();
+                refcon.push(match command.command_key {
+                    Some(key) => context.encode_byte(key)?,
+                    None => 0,
+                });
+                refcon.append(&mut headline.refcon);
+                headline.refcon = refcon;
+                headline.subheads = pack_items(&command.subitems, context)?;
+
+                Ok(headline)
+            }
+        })
+        .collect()
+}
+
+fn collect_commands<'a>(items: &'a [MenuItem], commands: &mut Vec<&'a MenuCommand>) {
+    for item in items {
+        if let MenuItem::Command(command) = item {
+            commands.push(command);
+            collect_commands(&command.subitems, commands);
+        }
+    }
+}
+
+fn collect_linked_scripts<'a>(
+    items: &'a mut [MenuItem],
+    linked_scripts: &mut Vec<(&'a mut Option<Script>, &'a mut DBAddress)>,
+) {
+    for item in items {
+        if let MenuItem::Command(command) = item {
+            let MenuCommand {
+                script,
+                script_address,
+                subitems,
+                ..
+            } = command;
+            linked_scripts.push((script, script_address));
+            collect_linked_scripts(subitems, linked_scripts);
+        }
+    }
+}
+
+#[cfg(feature = "export")]
+fn serialize_script<S: Serializer>(
+    script: &Option<Script>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match script {
+        Some(script) => serializer.serialize_str(&script.to_source_text()),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A File menu with Open…, whose script is in block 0x1234 and whose
+    // command key is O, a separator that's been commented out, and Quit, with
+    // the command key Q and no script.
+    #[rustfmt::skip]
+    const SAMPLE_MENUBAR: [u8; 78] = [
+        // the outline header
+        0x00, 0x02, 0x00, 0x04,
+        // the text of the headlines
+        0x00, 0x00, 0x00, 0x12,
+        b'F', b'i', b'l', b'e', b'\r',
+        b'O', b'p', b'e', b'n', 0xc9, b'\r',
+        b'-', b'\r',
+        b'Q', b'u', b'i', b't', b'\r',
+        // the headline records
+        0x00, 0x00, 0x00, 0x20,
+        0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x06,
+        0x00, 0x01, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x06,
+        // the item info of Open… and Quit
+        0x00, 0x00, 0x00, 0x0c,
+        0x00, 0x00, 0x12, 0x34, b'O', 0x00,
+        0x00, 0x00, 0x00, 0x00, b'Q', 0x00,
+    ];
+
+    fn context() -> LoadContext {
+        LoadContext::for_platform(Platform::Mac)
+    }
+
+    fn script(lines: &[&str]) -> Script {
+        let mut source = Outline::new();
+        source.summits = lines.iter().map(|line| Headline::new(line)).collect();
+        Script::new(source)
+    }
+
+    fn command(item: &MenuItem) -> &MenuCommand {
+        match item {
+            MenuItem::Command(command) => command,
+            MenuItem::Separator(_) => panic!("expected a command, found a separator"),
+        }
+    }
+
+    fn menubar() -> Menubar {
+        let mut open = MenuCommand::new("Open…");
+        open.command_key = Some('O');
+        open.script = Some(script(&["file.open ()"]));
+
+        let mut recent = MenuCommand::new("Recent");
+        let mut notes = MenuCommand::new("Notes");
+        notes.script = Some(script(&["edit (@notes)"]));
+        recent.subitems.push(MenuItem::Command(notes));
+
+        let mut file = Menu::new("File");
+        file.items = vec![
+            MenuItem::Command(open),
+            MenuItem::Separator(MenuSeparator::new()),
+            MenuItem::Command(recent),
+        ];
+        let mut menubar = Menubar::new();
+        menubar.menus.push(file);
+        menubar
+    }
+
+    #[test]
+    fn unpacks_the_menus_and_items() {
+        let menubar = Menubar::unpack(&SAMPLE_MENUBAR, &context()).unwrap();
+
+        assert_eq!(menubar.menus.len(), 1);
+        let file = &menubar.menus[0];
+        assert_eq!(file.title, "File");
+        assert_eq!(file.items.len(), 3);
+
+        let open = command(&file.items[0]);
+        assert_eq!(open.title, "Open…");
+        assert_eq!(open.command_key, Some('O'));
+        assert_eq!(open.script_address, 0x1234);
+        assert!(open.script.is_none());
+
+        assert!(
+            matches!(&file.items[1], MenuItem::Separator(separator) if separator.headline.is_comment)
+        );
+
+        let quit = command(&file.items[2]);
+        assert_eq!(quit.command_key, Some('Q'));
+        assert_eq!(quit.script_address, NIL_DB_ADDRESS);
+
+        assert_eq!(menubar.pack(&context()).unwrap(), SAMPLE_MENUBAR.to_vec());
+    }
+
+    #[test]
+    fn scripts_are_kept_out_of_the_menubar() {
+        let menubar = menubar();
+        let bytes = menubar.pack(&context()).unwrap();
+        let mut unpacked = Menubar::unpack(&bytes, &context()).unwrap();
+        assert!(unpacked.scripts().iter().all(Option::is_none));
+        assert_eq!(unpacked.pack(&context()).unwrap(), bytes);
+
+        let scripts = menubar.scripts().into_iter().map(|script| script.cloned());
+        unpacked.set_scripts(scripts.collect()).unwrap();
+        assert_eq!(unpacked, menubar);
+
+        assert!(unpacked.set_scripts(vec![None]).is_err());
+    }
+
+    #[test]
+    fn headlines_are_kept() {
+        let mut outline = Outline::unpack(&SAMPLE_MENUBAR, &context()).unwrap();
+        outline.summits[0].refcon = vec![1, 2, 3];
+        outline.summits[0].subheads[1].refcon = vec![4, 5];
+        outline.summits[0].subheads[2].is_comment = true;
+        outline.summits[0].subheads[2]
+            .refcon
+            .extend_from_slice(&[6, 7]);
+        let bytes = outline.pack(&context()).unwrap();
+
+        let mut menubar = Menubar::unpack(&bytes, &context()).unwrap();
+        if let MenuItem::Command(quit) = &mut menubar.menus[0].items[2] {
+            quit.command_key = Some('W');
+        }
+        let saved = Outline::unpack(&menubar.pack(&context()).unwrap(), &context()).unwrap();
+
+        assert!(saved.summits[0].is_expanded);
+        assert_eq!(saved.summits[0].refcon, vec![1, 2, 3]);
+        assert!(saved.summits[0].subheads[1].is_comment);
+        assert_eq!(saved.summits[0].subheads[1].refcon, vec![4, 5]);
+        assert!(saved.summits[0].subheads[2].is_comment);
+        assert_eq!(
+            saved.summits[0].subheads[2].refcon,
+            vec![0, 0, 0, 0, b'W', 0, 6, 7]
+        );
+    }
+
+    #[cfg(feature = "export")]
+    #[test]
+    fn exports_json_and_yaml() {
+        let menubar = menubar();
+
+        let json: serde_json::Value = serde_json::from_str(&menubar.to_json().unwrap()).unwrap();
+        assert_eq!(json[0]["title"], "File");
+        assert_eq!(json[0]["items"][0]["kind"], "command");
+        assert_eq!(json[0]["items"][0]["command_key"], "O");
+        assert_eq!(json[0]["items"][0]["script"], "file.open ()\n");
+        assert_eq!(json[0]["items"][1]["kind"], "separator");
+        assert_eq!(json[0]["items"][2]["subitems"][0]["title"], "Notes");
+
+        let yaml = menubar.to_yaml().unwrap();
+        assert!(yaml.starts_with("- title: File\n"));
+        assert!(yaml.contains("command_key: 'O'\n"));
+        assert!(yaml.contains("- kind: separator\n"));
+    }
+}
@@ -0,0 +1,618 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::error::*;
+use crate::mac_roman::*;
+use crate::odb_error::*;
+use crate::string_utils::*;
+use crate::text_encoding::TextEncoding;
+use std::convert::TryInto;
+use std::fmt;
+
+// Descriptors are stored the way AEFlattenDesc writes them: a four character
+// type, a four byte length and the data, padded to an even length. Lists and
+// records start with four reserved bytes and an item count, and every record
+// item is preceded by its keyword.
+const FLATTENED_DESC_MAGIC: &[u8; 4] = b"dle2";
+// How deeply descriptors can be nested before the specifier is treated as
+// damaged, so a bad one can't overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+const TYPE_NULL: OSType = OSType(*b"null");
+const TYPE_OBJECT_SPECIFIER: OSType = OSType(*b"obj ");
+const TYPE_RANGE: OSType = OSType(*b"rang");
+const TYPE_LIST: OSType = OSType(*b"list");
+const TYPE_RECORD: OSType = OSType(*b"reco");
+const TYPE_COMP_DESCRIPTOR: OSType = OSType(*b"cmpd");
+const TYPE_LOGICAL_DESCRIPTOR: OSType = OSType(*b"logi");
+const TYPE_SHORT: OSType = OSType(*b"shor");
+const TYPE_LONG: OSType = OSType(*b"long");
+const TYPE_TEXT: OSType = OSType(*b"TEXT");
+const TYPE_TYPE: OSType = OSType(*b"type");
+const TYPE_ENUMERATED: OSType = OSType(*b"enum");
+const TYPE_ABSOLUTE_ORDINAL: OSType = OSType(*b"abso");
+const TYPE_TRUE: OSType = OSType(*b"true");
+const TYPE_FALSE: OSType = OSType(*b"fals");
+const TYPE_BOOLEAN: OSType = OSType(*b"bool");
+
+const KEY_DESIRED_CLASS: OSType = OSType(*b"want");
+const KEY_KEY_FORM: OSType = OSType(*b"form");
+const KEY_KEY_DATA: OSType = OSType(*b"seld");
+const KEY_CONTAINER: OSType = OSType(*b"from");
+const KEY_RANGE_START: OSType = OSType(*b"star");
+const KEY_RANGE_STOP: OSType = OSType(*b"stop");
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OSType(pub [u8; 4]);
+
+impl OSType {
+    fn new(buffer: &[u8]) -> Result<Self> {
+        Ok(OSType(read_bytes(buffer, 0, 4)?.try_into()?))
+    }
+
+    fn is_identifier(&self) -> bool {
+        self.0[0].is_ascii_alphabetic() && self.0.iter().all(|byte| byte.is_ascii_alphanumeric())
+    }
+}
+
+// Codes that are valid identifiers print bare, the rest print as Frontier
+// string4 literals.
+impl fmt::Display for OSType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = decode_mac_roman(&self.0);
+        if self.is_identifier() {
+            write!(f, "{}", code)
+        } else {
+            write!(f, "'{}'", code)
+        }
+    }
+}
+
+impl fmt::Debug for OSType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}'", decode_mac_roman(&self.0))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyForm {
+    PropertyID,
+    Name,
+    UniqueID,
+    AbsolutePosition,
+    RelativePosition,
+    Test,
+    Range,
+    Whose,
+    Other(OSType),
+}
+
+impl KeyForm {
+    fn from_os_type(code: OSType) -> Self {
+        match &code.0 {
+            b"prop" => KeyForm::PropertyID,
+            b"name" => KeyForm::Name,
+            b"ID  " => KeyForm::UniqueID,
+            b"indx" => KeyForm::AbsolutePosition,
+            b"rele" => KeyForm::RelativePosition,
+            b"test" => KeyForm::Test,
+            b"rang" => KeyForm::Range,
+            b"whos" => KeyForm::Whose,
+            _ => KeyForm::Other(code),
+        }
+    }
+
+    fn to_os_type(self) -> OSType {
+        match self {
+            KeyForm::PropertyID => OSType(*b"prop"),
+            KeyForm::Name => OSType(*b"name"),
+            KeyForm::UniqueID => OSType(*b"ID  "),
+            KeyForm::AbsolutePosition => OSType(*b"indx"),
+            KeyForm::RelativePosition => OSType(*b"rele"),
+            KeyForm::Test => OSType(*b"test"),
+            KeyForm::Range => OSType(*b"rang"),
+            KeyForm::Whose => OSType(*b"whos"),
+            KeyForm::Other(code) => code,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Descriptor {
+    Null,
+    Boolean(bool),
+    Integer(i32),
+    Text(String),
+    Type(OSType),
+    Enumerated(OSType),
+    AbsoluteOrdinal(OSType),
+    ObjSpec(Box<ObjSpec>),
+    Range(Box<Descriptor>, Box<Descriptor>),
+    List(Vec<Descriptor>),
+    Record(OSType, Vec<(OSType, Descriptor)>),
+    Other(OSType, Vec<u8>),
+}
+
+impl Descriptor {
+    fn record_item(items: &[(OSType, Descriptor)], key: OSType) -> Option<&Descriptor> {
+        items
+            .iter()
+            .find(|(keyword, _)| *keyword == key)
+            .map(|(_, descriptor)| descriptor)
+    }
+
+    fn write(
+        &self,
+        bytes: &mut Vec<u8>,
+        encoding: TextEncoding,
+        unmappable: Unmappable,
+    ) -> Result<()> {
+        match self {
+            Descriptor::Null => write_descriptor(bytes, TYPE_NULL, &[]),
+            Descriptor::Boolean(value) => write_descriptor(bytes, TYPE_BOOLEAN, &[*value as u8]),
+            Descriptor::Integer(value) => write_descriptor(bytes, TYPE_LONG, &value.to_be_bytes()),
+            Descriptor::Text(value) => {
+                write_descriptor(bytes, TYPE_TEXT, &encoding.encode(value, unmappable)?)
+            }
+            Descriptor::Type(code) => write_descriptor(bytes, TYPE_TYPE, &code.0),
+            Descriptor::Enumerated(code) => write_descriptor(bytes, TYPE_ENUMERATED, &code.0),
+            Descriptor::AbsoluteOrdinal(code) => {
+                write_descriptor(bytes, TYPE_ABSOLUTE_ORDINAL, &code.0)
+            }
+            Descriptor::ObjSpec(spec) => spec.write(bytes, encoding, unmappable)?,
+            Descriptor::Range(start, stop) => write_keyed_items(
+                bytes,
+                TYPE_RANGE,
+                &[(KEY_RANGE_START, start), (KEY_RANGE_STOP, stop)],
+                encoding,
+                unmappable,
+            )?,
+            Descriptor::List(items) => {
+                let mut data = vec![0; 4];
+                data.extend_from_slice(&(items.len() as u32).to_be_bytes());
+                for item in items {
+                    item.write(&mut data, encoding, unmappable)?;
+                }
+                write_descriptor(bytes, TYPE_LIST, &data);
+            }
+            Descriptor::Record(descriptor_type, items) => {
+                let items: Vec<_> = items.iter().map(|(key, item)| (*key, item)).collect();
+                write_keyed_items(bytes, *descriptor_type, &items, encoding, unmappable)?;
+            }
+            Descriptor::Other(descriptor_type, data) => {
+                write_descriptor(bytes, *descriptor_type, data)
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Descriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Descriptor::Null => write!(f, "nil"),
+            Descriptor::Boolean(value) => write!(f, "{}", value),
+            Descriptor::Integer(value) => write!(f, "{}", value),
+            Descriptor::Text(value) => write!(f, "\"{}\"", value.replace('"', "\\\"")),
+            Descriptor::Type(code) | Descriptor::Enumerated(code) => write!(f, "{}", code),
+            Descriptor::AbsoluteOrdinal(code) => match &code.0 {
+                b"firs" => write!(f, "first"),
+                b"last" => write!(f, "last"),
+                b"midd" => write!(f, "middle"),
+                b"any " => write!(f, "any"),
+                b"all " => write!(f, "all"),
+                _ => write!(f, "{}", code),
+            },
+            Descriptor::ObjSpec(spec) => write!(f, "{}", spec),
+            Descriptor::Range(start, stop) => write!(f, "{} to {}", start, stop),
+            Descriptor::List(items) => {
+                write!(f, "{{")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "}}")
+            }
+            Descriptor::Record(_, items) => {
+                write!(f, "{{")?;
+                for (i, (keyword, item)) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}:{}", keyword, item)?;
+                }
+                write!(f, "}}")
+            }
+            Descriptor::Other(code, data) => write!(f, "{}:<{} bytes>", code, data.len()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjSpec {
+    pub class: OSType,
+    pub key_form: KeyForm,
+    pub key_data: Descriptor,
+    pub container: Option<Box<ObjSpec>>,
+}
+
+impl ObjSpec {
+    pub fn new(buffer: &[u8], encoding: TextEncoding) -> Result<Self> {
+        let buffer = match buffer.strip_prefix(FLATTENED_DESC_MAGIC) {
+            // The magic number is followed by four reserved bytes.
+            Some(rest) => read_bytes(rest, 4, rest.len().saturating_sub(4))?,
+            None => buffer,
+        };
+
+        let (descriptor, _) = read_descriptor(buffer, 0, encoding, 0)?;
+        match descriptor {
+            Descriptor::ObjSpec(spec) => Ok(*spec),
+            _ => Err(Error::from(ODBError::BadPackedValue)),
+        }
+    }
+
+    // Flattens the specifier the way AEFlattenDesc does, so it can be read
+    // back by ObjSpec::new.
+    pub fn to_bytes(&self, encoding: TextEncoding, unmappable: Unmappable) -> Result<Vec<u8>> {
+        let mut bytes = FLATTENED_DESC_MAGIC.to_vec();
+        bytes.extend_from_slice(&[0; 4]);
+        self.write(&mut bytes, encoding, unmappable)?;
+        Ok(bytes)
+    }
+
+    fn write(
+        &self,
+        bytes: &mut Vec<u8>,
+        encoding: TextEncoding,
+        unmappable: Unmappable,
+    ) -> Result<()> {
+        let class = Descriptor::Type(self.class);
+        let key_form = Descriptor::Enumerated(self.key_form.to_os_type());
+        let container = match &self.container {
+            Some(container) => Descriptor::ObjSpec(container.clone()),
+            None => Descriptor::Null,
+        };
+
+        write_keyed_items(
+            bytes,
+            TYPE_OBJECT_SPECIFIER,
+            &[
+                (KEY_DESIRED_CLASS, &class),
+                (KEY_KEY_FORM, &key_form),
+                (KEY_KEY_DATA, &self.key_data),
+                (KEY_CONTAINER, &container),
+            ],
+            encoding,
+            unmappable,
+        )
+    }
+
+    fn from_record(items: &[(OSType, Descriptor)]) -> Result<Self> {
+        let class = match Descriptor::record_item(items, KEY_DESIRED_CLASS) {
+            Some(Descriptor::Type(code)) => *code,
+            _ => return Err(Error::from(ODBError::BadPackedValue)),
+        };
+
+        let key_form = match Descriptor::record_item(items, KEY_KEY_FORM) {
+            Some(Descriptor::Enumerated(code)) | Some(Descriptor::Type(code)) => {
+                KeyForm::from_os_type(*code)
+            }
+            _ => return Err(Error::from(ODBError::BadPackedValue)),
+        };
+
+        let key_data = Descriptor::record_item(items, KEY_KEY_DATA)
+            .cloned()
+            .unwrap_or(Descriptor::Null);
+
+        let container = match Descriptor::record_item(items, KEY_CONTAINER) {
+            Some(Descriptor::ObjSpec(spec)) => Some(spec.clone()),
+            _ => None,
+        };
+
+        Ok(Self {
+            class,
+            key_form,
+            key_data,
+            container,
+        })
+    }
+}
+
+// Renders the specifier the way Frontier displays object references: the
+// outermost container first, each element joined by a dot, with the key in
+// brackets.
+impl fmt::Display for ObjSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(container) = &self.container {
+            write!(f, "{}.", container)?;
+        }
+
+        match self.key_form {
+            KeyForm::PropertyID => match &self.key_data {
+                Descriptor::Type(code) | Descriptor::Enumerated(code) => write!(f, "{}", code),
+                data => write!(f, "{}[{}]", self.class, data),
+            },
+            KeyForm::UniqueID => write!(f, "{}[id {}]", self.class, self.key_data),
+            KeyForm::RelativePosition => match &self.key_data {
+                Descriptor::Enumerated(code) if &code.0 == b"next" => {
+                    write!(f, "{}[next]", self.class)
+                }
+                Descriptor::Enumerated(code) if &code.0 == b"prev" => {
+                    write!(f, "{}[previous]", self.class)
+                }
+                data => write!(f, "{}[{}]", self.class, data),
+            },
+            KeyForm::Test | KeyForm::Whose => {
+                write!(f, "{}[where {}]", self.class, self.key_data)
+            }
+            _ => write!(f, "{}[{}]", self.class, self.key_data),
+        }
+    }
+}
+
+fn read_descriptor(
+    buffer: &[u8],
+    index: usize,
+    encoding: TextEncoding,
+    depth: usize,
+) -> Result<(Descriptor, usize)> {
+    if depth > MAX_DEPTH {
+        return Err(Error::from(ODBError::BadPackedValue));
+    }
+
+    let descriptor_type = OSType::new(read_bytes(buffer, index, 4)?)?;
+    let length = read_u32(buffer, index + 4)? as usize;
+    let data = read_bytes(buffer, index + 8, length)?;
+    let next = index + 8 + length + (length % 2);
+
+    let descriptor = match descriptor_type {
+        TYPE_NULL => Descriptor::Null,
+        TYPE_TRUE => Descriptor::Boolean(true),
+        TYPE_FALSE => Descriptor::Boolean(false),
+        TYPE_BOOLEAN => Descriptor::Boolean(read_bytes(data, 0, 1)?[0] != 0),
+        TYPE_SHORT => {
+            Descriptor::Integer(i16::from_be_bytes(read_bytes(data, 0, 2)?.try_into()?) as i32)
+        }
+        TYPE_LONG => Descriptor::Integer(read_u32(data, 0)? as i32),
+        TYPE_TEXT => Descriptor::Text(read_fixed_string(data, encoding)),
+        TYPE_TYPE => Descriptor::Type(OSType::new(data)?),
+        TYPE_ENUMERATED => Descriptor::Enumerated(OSType::new(data)?),
+        TYPE_ABSOLUTE_ORDINAL => Descriptor::AbsoluteOrdinal(OSType::new(data)?),
+        TYPE_LIST => Descriptor::List(
+            read_items(data, false, encoding, depth)?
+                .into_iter()
+                .map(|(_, item)| item)
+                .collect(),
+        ),
+        TYPE_OBJECT_SPECIFIER => Descriptor::ObjSpec(Box::new(ObjSpec::from_record(
+            &read_keyed_items(data, encoding, depth)?,
+        )?)),
+        TYPE_RANGE => {
+            let items = read_keyed_items(data, encoding, depth)?;
+            let start = Descriptor::record_item(&items, KEY_RANGE_START)
+                .cloned()
+                .unwrap_or(Descriptor::Null);
+            let stop = Descriptor::record_item(&items, KEY_RANGE_STOP)
+                .cloned()
+                .unwrap_or(Descriptor::Null);
+            Descriptor::Range(Box::new(start), Box::new(stop))
+        }
+        TYPE_RECORD | TYPE_COMP_DESCRIPTOR | TYPE_LOGICAL_DESCRIPTOR => {
+            Descriptor::Record(descriptor_type, read_keyed_items(data, encoding, depth)?)
+        }
+        _ => Descriptor::Other(descriptor_type, data.to_vec()),
+    };
+
+    Ok((descriptor, next))
+}
+
+fn read_keyed_items(
+    data: &[u8],
+    encoding: TextEncoding,
+    depth: usize,
+) -> Result<Vec<(OSType, Descriptor)>> {
+    read_items(data, true, encoding, depth)
+}
+
+fn read_items(
+    data: &[u8],
+    is_keyed: bool,
+    encoding: TextEncoding,
+    depth: usize,
+) -> Result<Vec<(OSType, Descriptor)>> {
+    // ignore bytes 0 - 3 (reserved)
+    let count = read_u32(data, 4)?;
+    let mut index = 8;
+    let mut items = vec![];
+
+    for _ in 0..count {
+        let keyword = if is_keyed {
+            let keyword = OSType::new(read_bytes(data, index, 4)?)?;
+            index += 4;
+            keyword
+        } else {
+            TYPE_NULL
+        };
+
+        let (item, next) = read_descriptor(data, index, encoding, depth + 1)?;
+        items.push((keyword, item));
+        index = next;
+    }
+
+    Ok(items)
+}
+
+fn write_descriptor(bytes: &mut Vec<u8>, descriptor_type: OSType, data: &[u8]) {
+    bytes.extend_from_slice(&descriptor_type.0);
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        bytes.push(0);
+    }
+}
+
+fn write_keyed_items(
+    bytes: &mut Vec<u8>,
+    descriptor_type: OSType,
+    items: &[(OSType, &Descriptor)],
+    encoding: TextEncoding,
+    unmappable: Unmappable,
+) -> Result<()> {
+    let mut data = vec![0; 4];
+    data.extend_from_slice(&(items.len() as u32).to_be_bytes());
+    for (keyword, item) in items {
+        data.extend_from_slice(&keyword.0);
+        item.write(&mut data, encoding, unmappable)?;
+    }
+    write_descriptor(bytes, descriptor_type, &data);
+    Ok(())
+}
+
+fn read_bytes(buffer: &[u8], index: usize, length: usize) -> Result<&[u8]> {
+    let bytes = buffer
+        .get(index..(index + length))
+        .ok_or(ODBError::BadPackedValue)?;
+    Ok(bytes)
+}
+
+fn read_u32(buffer: &[u8], index: usize) -> Result<u32> {
+    Ok(u32::from_be_bytes(
+        read_bytes(buffer, index, 4)?.try_into()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(descriptor_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut bytes = descriptor_type.to_vec();
+        bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn record(descriptor_type: &[u8; 4], items: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut data = vec![0; 4];
+        data.extend_from_slice(&(items.len() as u32).to_be_bytes());
+        for (keyword, item) in items {
+            data.extend_from_slice(*keyword);
+            data.extend_from_slice(item);
+        }
+        descriptor(descriptor_type, &data)
+    }
+
+    fn obj_spec(class: &[u8; 4], form: &[u8; 4], key_data: Vec<u8>, from: Vec<u8>) -> Vec<u8> {
+        record(
+            b"obj ",
+            &[
+                (b"want", descriptor(b"type", class)),
+                (b"form", descriptor(b"enum", form)),
+                (b"seld", key_data),
+                (b"from", from),
+            ],
+        )
+    }
+
+    #[test]
+    fn nested_specifier() {
+        let window = obj_spec(
+            b"cwin",
+            b"indx",
+            descriptor(b"long", &[0, 0, 0, 1]),
+            descriptor(b"null", &[]),
+        );
+        let file = obj_spec(b"file", b"name", descriptor(b"TEXT", b"Read Me"), window);
+        let name = obj_spec(b"prop", b"prop", descriptor(b"type", b"pnam"), file);
+
+        let spec = ObjSpec::new(&name, TextEncoding::MacRoman).unwrap();
+        assert_eq!(spec.key_form, KeyForm::PropertyID);
+        assert_eq!(spec.key_data, Descriptor::Type(OSType(*b"pnam")));
+
+        let file = spec.container.as_ref().unwrap();
+        assert_eq!(file.class, OSType(*b"file"));
+        assert_eq!(file.key_data, Descriptor::Text(String::from("Read Me")));
+        assert_eq!(file.container.as_ref().unwrap().container, None);
+
+        assert_eq!(spec.to_string(), "cwin[1].file[\"Read Me\"].pnam");
+    }
+
+    #[test]
+    fn range_and_ordinals() {
+        let start = obj_spec(
+            b"cpar",
+            b"indx",
+            descriptor(b"abso", b"firs"),
+            descriptor(b"null", &[]),
+        );
+        let stop = obj_spec(
+            b"cpar",
+            b"indx",
+            descriptor(b"long", &[0, 0, 0, 3]),
+            descriptor(b"null", &[]),
+        );
+        let range = record(b"rang", &[(b"star", start), (b"stop", stop)]);
+        let mut flattened = b"dle2".to_vec();
+        flattened.extend_from_slice(&[0; 4]);
+        flattened.extend(obj_spec(b"cpar", b"rang", range, descriptor(b"null", &[])));
+
+        let spec = ObjSpec::new(&flattened, TextEncoding::MacRoman).unwrap();
+        assert_eq!(spec.key_form, KeyForm::Range);
+        assert_eq!(spec.to_string(), "cpar[cpar[first] to cpar[3]]");
+
+        let bytes = spec
+            .to_bytes(TextEncoding::MacRoman, Unmappable::Fail)
+            .unwrap();
+        assert_eq!(bytes, flattened);
+        assert_eq!(ObjSpec::new(&bytes, TextEncoding::MacRoman).unwrap(), spec);
+    }
+
+    #[test]
+    fn truncated_data_is_an_error() {
+        let window = obj_spec(
+            b"cwin",
+            b"indx",
+            descriptor(b"long", &[0, 0, 0, 1]),
+            descriptor(b"null", &[]),
+        );
+        assert!(ObjSpec::new(&window[..window.len() - 6], TextEncoding::MacRoman).is_err());
+    }
+
+    #[test]
+    fn deeply_nested_specifiers_are_damaged() {
+        let nested = |depth: usize| {
+            let mut spec = descriptor(b"null", &[]);
+            for _ in 0..depth {
+                spec = obj_spec(b"cwin", b"indx", descriptor(b"long", &[0, 0, 0, 1]), spec);
+            }
+            spec
+        };
+
+        assert!(ObjSpec::new(&nested(MAX_DEPTH), TextEncoding::MacRoman).is_ok());
+        assert!(matches!(
+            ObjSpec::new(&nested(MAX_DEPTH + 2), TextEncoding::MacRoman),
+            Err(Error::ODB(ODBError::BadPackedValue))
+        ));
+    }
+}
@@ -16,16 +16,26 @@ You should have received a copy of the GNU General Public License
 along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use crate::collation::*;
+use crate::conversion::*;
 use crate::error::*;
+use crate::mac_roman::Unmappable;
 use crate::odb_error::*;
+use crate::path::*;
 use crate::string_utils::*;
 use crate::table::*;
+use crate::table_node::*;
+use crate::text_encoding::*;
+use crate::value_record::*;
+use crate::variable::*;
 use crate::window_info::*;
-use db;
 use std::convert::TryInto;
 use std::fs;
+use std::path::Path;
 
 const ODB_VIEW: usize = 0;
+const ODB_RECORD_VERSION: u16 = 3;
+const ODB_RECORD_SIZE: usize = 442;
 const WINDOW_INFO_COUNT: usize = 6;
 
 const IS_FLAG_DISABLED_MASK: u16 = 0x8000;
@@ -40,17 +50,63 @@ pub struct ObjectDB {
     is_flag_disabled: bool,
     is_popup_disabled: bool,
     is_big_window: bool,
-    root_table: Option<Table>,
+    root_table: Table,
+    root_table_address: db::DBAddress,
+    is_dirty: bool,
+    conversion_report: ConversionReport,
+    // Other roots mounted into this one's namespace, in the order they were
+    // opened.
+    guests: Vec<(String, ObjectDB)>,
+    // The ODB record as it was read. It's rewritten with the fields that can
+    // change, so the parts that aren't decoded are saved unchanged.
+    record: Vec<u8>,
+    context: LoadContext,
 }
 
 impl ObjectDB {
+    // Strings are decoded with the encoding of the platform that created the
+    // database.
     pub fn load_file(file: fs::File) -> Result<Self> {
-        let mut db = db::Database::open_file(file, false)?;
-        let address = db.get_view(ODB_VIEW);
+        Self::load(db::Database::open_file(file, false)?, None)
+    }
 
-        let mut buffer = [0; 2];
-        db.read_block_into_buffer(address, 2, &mut buffer)?;
-        let version_number = u16::from_be_bytes(buffer);
+    // Opens a root written by a build of Frontier that used a different
+    // encoding than the usual one for its platform, like a Japanese one.
+    pub fn load_file_with_encoding(file: fs::File, text_encoding: TextEncoding) -> Result<Self> {
+        Self::load(db::Database::open_file(file, false)?, Some(text_encoding))
+    }
+
+    // Starts a new root in the file, holding the standard tables. Nothing is
+    // written until it's saved.
+    pub fn create_file(file: fs::File) -> Result<Self> {
+        let mut odb = Self::load(db::Database::create_file(file, 0)?, None)?;
+        odb.conversion_report = ConversionReport::default();
+        Ok(odb)
+    }
+
+    fn load(mut db: db::Database, text_encoding: Option<TextEncoding>) -> Result<Self> {
+        let mut context = LoadContext::new(&db);
+        if let Some(text_encoding) = text_encoding {
+            context.text_encoding = text_encoding;
+        }
+
+        // A database without an ODB record gets an empty one, and then a new
+        // root table below. They're written the first time it's saved, so
+        // opening the file never writes to it.
+        let address = db.get_view(ODB_VIEW);
+        let mut buffer = if address == db::NIL_DB_ADDRESS {
+            let mut record = vec![0; ODB_RECORD_SIZE];
+            record[0..2].copy_from_slice(&ODB_RECORD_VERSION.to_be_bytes());
+            record
+        } else {
+            db.read_block(address)?
+        };
+        let version_number = u16::from_be_bytes(
+            buffer
+                .get(0..2)
+                .ok_or(ODBError::BadDatabaseVersion)?
+                .try_into()?,
+        );
 
         let mut odb = Self {
             db,
@@ -59,28 +115,42 @@ impl ObjectDB {
             is_flag_disabled: false,
             is_popup_disabled: false,
             is_big_window: false,
-            root_table: None,
+            root_table: Table::new(),
+            root_table_address: db::NIL_DB_ADDRESS,
+            is_dirty: false,
+            conversion_report: ConversionReport::default(),
+            guests: vec![],
+            record: vec![],
+            context,
         };
+        odb.is_dirty = address == db::NIL_DB_ADDRESS;
 
-        // Brave Flea does not support ODB version 1 files
-        if !(version_number == 2 || version_number == 3) {
+        if !(FRONTIER_4_RECORD_VERSION..=ODB_RECORD_VERSION).contains(&version_number) {
             return Err(Error::from(ODBError::BadDatabaseVersion));
         }
 
-        let buffer = odb.db.read_block(address)?;
         // let version_number = u16::from_be_bytes(buffer[0..2].try_into()?);
-        let root_table_address = u32::from_be_bytes(buffer[2..6].try_into()?);
+        let root_table_address = u32::from_be_bytes(
+            buffer
+                .get(2..6)
+                .ok_or(ODBError::BadDatabaseVersion)?
+                .try_into()?,
+        );
+
+        if version_number == FRONTIER_4_RECORD_VERSION {
+            buffer = odb.convert_version_1_record(&buffer)?;
+            odb.is_dirty = true;
+        }
 
         // bytes 6 - 377
         let size = WINDOW_INFO_SIZE as usize;
         for i in 0..WINDOW_INFO_COUNT {
             odb.window_info.push(WindowInfo::new(
                 &buffer[6 + (i * size)..(6 + size) + (i * size)],
+                context.text_encoding,
             )?);
         }
 
-        // TODO: fontier4root conversion
-
         let script_string_address = u32::from_be_bytes(buffer[378..382].try_into()?);
         let flags = u16::from_be_bytes(buffer[382..384].try_into()?);
         // let primary_agent_index = u16::from_be_bytes(buffer[384..386].try_into()?);
@@ -89,24 +159,1219 @@ impl ObjectDB {
 
         if script_string_address != db::NIL_DB_ADDRESS {
             let buffer = odb.db.read_block(script_string_address)?;
-            odb.script_string = read_fixed_string(&buffer);
+            odb.script_string = read_fixed_string(&buffer, context.text_encoding);
         }
 
         odb.is_flag_disabled = (flags & IS_FLAG_DISABLED_MASK) != 0;
         odb.is_popup_disabled = (flags & IS_POPUP_DISABLED_MASK) != 0;
         odb.is_big_window = (flags & IS_BIG_WINDOW_MASK) != 0;
 
-        // TODO: load system table
-        odb.load_system_table(root_table_address, false)?;
+        odb.record = buffer;
+        odb.load_system_table(root_table_address, true)?;
+        odb.root_table_address = root_table_address;
+
+        // The tables of a Frontier 4 root are all read up front so every one
+        // that needs converting is found.
+        if version_number == FRONTIER_4_RECORD_VERSION {
+            odb.root_table.load_all(&mut odb.db, &context)?;
+        }
+        odb.conversion_report.add_tables(&odb.root_table);
 
         // TODO: guest root logic
 
         Ok(odb)
     }
 
+    // What was upgraded from an older format or repaired when the database
+    // was opened. The changes are written to the file the next time it's
+    // saved.
+    pub fn conversion_report(&self) -> &ConversionReport {
+        &self.conversion_report
+    }
+
+    pub fn root(&self) -> &Table {
+        &self.root_table
+    }
+
+    pub fn root_mut(&mut self) -> &mut Table {
+        &mut self.root_table
+    }
+
+    pub fn window_info(&self) -> &[WindowInfo] {
+        &self.window_info
+    }
+
+    // Changes made through the slice are saved the next time the database
+    // is. The record is compared with the one on disk when it's saved, so
+    // borrowing the slice without changing anything doesn't write it.
+    pub fn window_info_mut(&mut self) -> &mut [WindowInfo] {
+        &mut self.window_info
+    }
+
+    pub fn text_encoding(&self) -> TextEncoding {
+        self.context.text_encoding
+    }
+
+    // What happens to characters the text encoding doesn't have when the
+    // database is saved. Saving fails with UnmappableCharacter unless it's
+    // been told to replace them.
+    pub fn unmappable(&self) -> Unmappable {
+        self.context.unmappable
+    }
+
+    pub fn set_unmappable(&mut self, unmappable: Unmappable) {
+        self.context.unmappable = unmappable;
+    }
+
+    pub fn script_string(&self) -> &str {
+        &self.script_string
+    }
+
+    pub fn is_flag_disabled(&self) -> bool {
+        self.is_flag_disabled
+    }
+
+    pub fn is_popup_disabled(&self) -> bool {
+        self.is_popup_disabled
+    }
+
+    pub fn is_big_window(&self) -> bool {
+        self.is_big_window
+    }
+
+    pub fn set_flag_disabled(&mut self, is_flag_disabled: bool) {
+        self.is_flag_disabled = is_flag_disabled;
+    }
+
+    pub fn set_popup_disabled(&mut self, is_popup_disabled: bool) {
+        self.is_popup_disabled = is_popup_disabled;
+    }
+
+    pub fn set_big_window(&mut self, is_big_window: bool) {
+        self.is_big_window = is_big_window;
+    }
+
+    // Opens another root as a guest database, mounted under its file name.
+    // Paths that start with that name, like ["Guest.root"].suites, are looked
+    // up in the guest. Returns the name it was mounted under. Files whose
+    // names are already in use have to be opened with open_guest_as.
+    pub fn open_guest<P: AsRef<Path>>(&mut self, path: P) -> Result<&str> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        self.open_guest_as(path, &name)
+    }
+
+    // Opens another root as a guest database, mounted under the given name.
+    // The name can't be one that's used by another guest or by an item in
+    // the root table, since paths starting with it would be ambiguous.
+    pub fn open_guest_as<P: AsRef<Path>>(&mut self, path: P, name: &str) -> Result<&str> {
+        if name.is_empty() {
+            return Err(Error::from(ODBError::InvalidName {
+                name: String::from(name),
+            }));
+        }
+        if self.guest(name).is_some() {
+            return Err(Error::from(ODBError::GuestExists {
+                name: String::from(name),
+            }));
+        }
+        if self.root_table.get(name).is_some() {
+            return Err(Error::from(ODBError::GuestNameConflict {
+                name: String::from(name),
+            }));
+        }
+
+        let file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        let guest = ObjectDB::load_file(file)?;
+        self.guests.push((String::from(name), guest));
+
+        Ok(&self.guests[self.guests.len() - 1].0)
+    }
+
+    pub fn guest(&self, name: &str) -> Option<&ObjectDB> {
+        let key = fold_case(name);
+        self.guests
+            .iter()
+            .find(|(guest_name, _)| fold_case(guest_name) == key)
+            .map(|(_, guest)| guest)
+    }
+
+    // Guests are saved on their own, with their own save method.
+    pub fn guest_mut(&mut self, name: &str) -> Option<&mut ObjectDB> {
+        let key = fold_case(name);
+        self.guests
+            .iter_mut()
+            .find(|(guest_name, _)| fold_case(guest_name) == key)
+            .map(|(_, guest)| guest)
+    }
+
+    pub fn guest_names(&self) -> impl Iterator<Item = &str> {
+        self.guests.iter().map(|(name, _)| name.as_str())
+    }
+
+    // Unmounts a guest without saving it, handing it back so it can be saved
+    // first if it has changed.
+    pub fn close_guest(&mut self, name: &str) -> Option<ObjectDB> {
+        let key = fold_case(name);
+        let index = self
+            .guests
+            .iter()
+            .position(|(guest_name, _)| fold_case(guest_name) == key)?;
+
+        Some(self.guests.remove(index).1)
+    }
+
+    pub fn lookup(&mut self, path: &str) -> Result<&TableNode> {
+        self.lookup_mut(path).map(|node| &*node)
+    }
+
+    // Finds the item a Frontier path like user.prefs.mail["smtp host"] refers
+    // to, loading any subtables along the way from disk.
+    pub fn lookup_mut(&mut self, path: &str) -> Result<&mut TableNode> {
+        let segments = parse_path(path)?;
+        self.lookup_segments(&segments, false)
+    }
+
+    // Finds the subtable a path refers to, loading it first if it's still on
+    // disk. Changes made to it are written the next time the database is
+    // saved.
+    pub fn lookup_table_mut(&mut self, path: &str) -> Result<&mut Table> {
+        let segments = parse_path(path)?;
+        match self.lookup_segments(&segments, true)?.value_mut() {
+            Value::TableValue(table) => Ok(table),
+            _ => Err(Error::from(ODBError::NotATable {
+                path: format_path(&segments),
+            })),
+        }
+    }
+
+    // Finds the item an address value points to. Addresses are paths that
+    // may start with @ and name the root table explicitly.
+    pub fn resolve_address(&mut self, address: &str) -> Result<&mut TableNode> {
+        let address = address.trim_start().trim_start_matches('@');
+        let mut segments = parse_path(address)?;
+
+        let is_rooted =
+            matches!(segments.first(), Some(PathSegment::Name(name)) if fold_case(name) == "root");
+        if is_rooted && segments.len() > 1 {
+            segments.remove(0);
+        }
+
+        self.lookup_segments(&segments, false)
+    }
+
+    // Walks the path, loading the tables along it. The value of the item it
+    // ends at is only loaded when load_last is set.
+    fn lookup_segments(
+        &mut self,
+        segments: &[PathSegment],
+        load_last: bool,
+    ) -> Result<&mut TableNode> {
+        if let (Some(PathSegment::Name(name)), true) = (segments.first(), segments.len() > 1) {
+            if self.guest(name).is_some() {
+                // An item added to the root table after the guest was mounted
+                // would otherwise be hidden by it.
+                if self.root_table.get(name).is_some() {
+                    return Err(Error::from(ODBError::GuestNameConflict {
+                        name: name.clone(),
+                    }));
+                }
+
+                return self
+                    .guest_mut(name)
+                    .unwrap()
+                    .lookup_segments(&segments[1..], load_last);
+            }
+        }
+
+        let db = &mut self.db;
+        let context = &self.context;
+        let mut table = &mut self.root_table;
+
+        for (i, segment) in segments.iter().enumerate() {
+            let table_path = || match i {
+                0 => String::from("root"),
+                _ => format_path(&segments[..i]),
+            };
+
+            let node = match segment {
+                PathSegment::Name(name) => {
+                    table.get_mut(name).ok_or_else(|| ODBError::NameNotFound {
+                        table: table_path(),
+                        name: name.clone(),
+                    })?
+                }
+                PathSegment::Index(index) => {
+                    table
+                        .get_index_mut(*index)
+                        .ok_or_else(|| ODBError::IndexOutOfRange {
+                            table: table_path(),
+                            index: *index,
+                        })?
+                }
+            };
+
+            if i == segments.len() - 1 {
+                if load_last {
+                    node.load_value(db, context)?;
+                }
+                return Ok(node);
+            }
+
+            table = match node.load_value(db, context)? {
+                Value::TableValue(subtable) => subtable,
+                _ => {
+                    return Err(Error::from(ODBError::NotATable {
+                        path: format_path(&segments[..=i]),
+                    }))
+                }
+            };
+        }
+
+        unreachable!("parse_path never returns an empty path")
+    }
+
+    // Writes every changed table and external value to the database. Nothing
+    // is overwritten: changes go to new blocks at the end of the file, and a
+    // new ODB record pointing at the new root table is written last. The
+    // database header, which points at the ODB record, is the only thing
+    // rewritten in place, and it's synced to disk after everything it points
+    // at, so if the save is cut short the file holds either the old tree or
+    // the new one. The old blocks are released after that. Free blocks aren't
+    // reused, so the file keeps growing until it's compacted with save_as.
+    //
+    // The ODB record is only written when it differs from the one on disk,
+    // which covers changes to the window info and flags.
+    pub fn save(&mut self) -> Result<()> {
+        self.root_table_address =
+            self.root_table
+                .save(&mut self.db, &self.context, self.root_table_address)?;
+
+        let record = self.pack_record()?;
+        if record == self.record && !self.is_dirty {
+            return Ok(());
+        }
+
+        let record_address = self.db.allocate(&record)?;
+        self.db.push_release_stack(self.db.get_view(ODB_VIEW));
+        self.db.set_view(ODB_VIEW, record_address);
+        self.db.flush()?;
+        self.record = record;
+
+        self.db.flush_release_stack()?;
+        self.db.flush()?;
+        self.is_dirty = false;
+
+        Ok(())
+    }
+
+    // Writes everything to a new database in the file and carries on with
+    // that one. Only what's in use is copied, so the new file is also
+    // compacted.
+    pub fn save_as(&mut self, file: fs::File) -> Result<()> {
+        self.root_table.load_all(&mut self.db, &self.context)?;
+        self.root_table.detach();
+
+        let mut db = db::Database::create_file(file, self.db.get_system_id())?;
+
+        let script_string_address = self.script_string_address()?;
+        let script_string_address = if script_string_address != db::NIL_DB_ADDRESS {
+            db.allocate(&self.db.read_block(script_string_address)?)?
+        } else {
+            db::NIL_DB_ADDRESS
+        };
+        self.record[378..382].copy_from_slice(&script_string_address.to_be_bytes());
+
+        self.db = db;
+        self.root_table_address = db::NIL_DB_ADDRESS;
+        self.is_dirty = true;
+
+        self.save()
+    }
+
+    // Converts a Frontier 4 ODB record to the current version. A version 1
+    // record has the same fields as a current one, in the same places: the
+    // root table's address, the six window infos, the address of the script
+    // string, the flags and the index of the primary agent, followed by
+    // unused space. What changed after Frontier 4 is the way tables are
+    // stored, and they're converted as they're read.
+    //
+    // Each field is checked before it's copied into the new record. One that
+    // the old record is too short to hold, or that doesn't decode, is left
+    // the way a new root has it and listed in the conversion report.
+    fn convert_version_1_record(&mut self, old: &[u8]) -> Result<Vec<u8>> {
+        let mut record = vec![0; ODB_RECORD_SIZE];
+        record[0..2].copy_from_slice(&ODB_RECORD_VERSION.to_be_bytes());
+        record[2..6].copy_from_slice(old.get(2..6).ok_or(ODBError::BadDatabaseVersion)?);
+        let mut dropped = vec![];
+
+        let size = WINDOW_INFO_SIZE as usize;
+        for i in 0..WINDOW_INFO_COUNT {
+            let range = (6 + (i * size))..((6 + size) + (i * size));
+            match old.get(range.clone()) {
+                Some(bytes) if WindowInfo::new(bytes, self.context.text_encoding).is_ok() => {
+                    record[range].copy_from_slice(bytes)
+                }
+                _ => dropped.push(format!("window info {}", i + 1)),
+            }
+        }
+
+        // The script string is only carried over if its block can be read.
+        match old.get(378..382) {
+            Some(bytes) => {
+                let address = u32::from_be_bytes(bytes.try_into()?);
+                if address == db::NIL_DB_ADDRESS || self.db.read_block(address).is_ok() {
+                    record[378..382].copy_from_slice(bytes);
+                } else {
+                    dropped.push(String::from("script string"));
+                }
+            }
+            None => dropped.push(String::from("script string")),
+        }
+
+        for (field, range) in [("flags", 382..384), ("primary agent", 384..386)] {
+            match old.get(range.clone()) {
+                Some(bytes) => record[range].copy_from_slice(bytes),
+                None => dropped.push(String::from(field)),
+            }
+        }
+
+        // The unused space is kept as far as the old record goes.
+        if let Some(unused) = old.get(386..old.len().min(ODB_RECORD_SIZE)) {
+            record[386..(386 + unused.len())].copy_from_slice(unused);
+        }
+
+        let conversions = &mut self.conversion_report.conversions;
+        conversions.push(Conversion::Record {
+            version: FRONTIER_4_RECORD_VERSION,
+        });
+        conversions.extend(
+            dropped
+                .into_iter()
+                .map(|field| Conversion::RecordField { field }),
+        );
+
+        Ok(record)
+    }
+
+    fn script_string_address(&self) -> Result<db::DBAddress> {
+        Ok(u32::from_be_bytes(self.record[378..382].try_into()?))
+    }
+
+    fn pack_record(&self) -> Result<Vec<u8>> {
+        let mut record = self.record.clone();
+        record[2..6].copy_from_slice(&self.root_table_address.to_be_bytes());
+
+        let size = WINDOW_INFO_SIZE as usize;
+        for (i, window_info) in self.window_info.iter().enumerate() {
+            record[6 + (i * size)..(6 + size) + (i * size)].copy_from_slice(
+                &window_info.to_bytes(self.context.text_encoding, self.context.unmappable)?,
+            );
+        }
+
+        let mut flags = u16::from_be_bytes(record[382..384].try_into()?);
+        for (mask, is_set) in [
+            (IS_FLAG_DISABLED_MASK, self.is_flag_disabled),
+            (IS_POPUP_DISABLED_MASK, self.is_popup_disabled),
+            (IS_BIG_WINDOW_MASK, self.is_big_window),
+        ] {
+            flags = if is_set { flags | mask } else { flags & !mask };
+        }
+        record[382..384].copy_from_slice(&flags.to_be_bytes());
+
+        Ok(record)
+    }
+
+    // Frees the memory used by subtables that haven't been changed since they
+    // were loaded.
+    pub fn purge(&mut self) {
+        self.root_table.purge();
+    }
+
     fn load_system_table(&mut self, address: db::DBAddress, create: bool) -> Result<()> {
-        let table = Table::load_system_table(&mut self.db, address)?;
-        self.root_table = Some(table);
+        if address == db::NIL_DB_ADDRESS && !create {
+            return Err(Error::from(ODBError::NoRootTable));
+        }
+
+        let mut table = Table::load_system_table(&mut self.db, &self.context, address)?;
+        for name in table.add_missing_system_tables()? {
+            self.conversion_report
+                .conversions
+                .push(Conversion::SystemTable {
+                    name: String::from(name),
+                });
+        }
+
+        self.root_table = table;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::menubar::*;
+    use crate::outline::*;
+    use crate::quickdraw::Rect;
+    use crate::script::*;
+    use crate::sort_order::SortOrder;
+    use crate::wptext::WpText;
+    use std::path::PathBuf;
+
+    const SAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../Sample.root");
+
+    fn open_sample() -> ObjectDB {
+        ObjectDB::load_file(fs::File::open(SAMPLE).unwrap()).unwrap()
+    }
+
+    // A path in the temp directory that's deleted when it goes out of scope,
+    // even if the test panics.
+    struct TempRoot(PathBuf);
+
+    impl TempRoot {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("brave-flea-{}-{}", name, std::process::id())))
+        }
+
+        // A copy of Sample.root.
+        fn sample(name: &str) -> Self {
+            let root = Self::new(name);
+            fs::copy(SAMPLE, &root.0).unwrap();
+            root
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+
+        fn file(&self) -> fs::File {
+            fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&self.0)
+                .unwrap()
+        }
+
+        fn open(&self) -> ObjectDB {
+            ObjectDB::load_file(self.file()).unwrap()
+        }
+    }
+
+    impl Drop for TempRoot {
+        fn drop(&mut self) {
+            let _ = match self.0.is_dir() {
+                true => fs::remove_dir_all(&self.0),
+                false => fs::remove_file(&self.0),
+            };
+        }
+    }
+
+    #[test]
+    fn lookup_by_name_and_index() {
+        let mut odb = open_sample();
+
+        let node = odb.lookup("validUser").unwrap();
+        assert!(matches!(node.value(), Value::BooleanValue(true)));
+
+        let node = odb.lookup("VALIDUSER").unwrap();
+        assert_eq!(node.name(), "validUser");
+
+        let node = odb.lookup("[5]").unwrap();
+        assert!(matches!(node.value(), Value::CharValue('C')));
+    }
+
+    #[test]
+    fn nested_tables_can_be_edited() {
+        let mut odb = open_sample();
+        let mut prefs = Table::new();
+        prefs
+            .insert("mail", Value::TableValue(Box::default()))
+            .unwrap();
+        odb.root_mut()
+            .insert("prefs", Value::TableValue(Box::new(prefs)))
+            .unwrap();
+
+        odb.lookup_table_mut("prefs.mail")
+            .unwrap()
+            .insert("smtp host", Value::StringValue(String::from("mail")))
+            .unwrap();
+        assert!(
+            matches!(odb.lookup("prefs.mail[\"smtp host\"]").unwrap().value(), Value::StringValue(s) if s == "mail")
+        );
+        assert!(odb.root().has_unsaved_changes());
+
+        match odb.lookup_table_mut("validUser") {
+            Err(Error::ODB(ODBError::NotATable { path })) => assert_eq!(path, "validUser"),
+            result => panic!("{:?}", result.map(|table| table.len())),
+        }
+    }
+
+    #[test]
+    fn sort_orders() {
+        // Just Sample.root's own items, without the standard tables that are
+        // added to it when it's opened.
+        let mut odb = open_sample();
+        let mut table =
+            Table::load_system_table(&mut odb.db, &odb.context, odb.root_table_address).unwrap();
+        let table = &mut table;
+        let names = |table: &Table| {
+            table
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(table.sort_order(), SortOrder::ByName);
+        assert!(!table.is_dirty());
+
+        table.set_sort_order(SortOrder::ByValue);
+        assert!(table.is_dirty());
+        assert_eq!(
+            names(table),
+            vec![
+                "validUser",
+                "pi",
+                "age",
+                "middleInitial",
+                "lastName",
+                "firstName",
+                "birthDate"
+            ]
+        );
+
+        table.set_sort_order(SortOrder::ByKind);
+        assert_eq!(
+            names(table),
+            vec![
+                "middleInitial",
+                "age",
+                "validUser",
+                "birthDate",
+                "pi",
+                "firstName",
+                "lastName"
+            ]
+        );
+    }
+
+    #[test]
+    fn save_commits_a_new_root() {
+        let root = TempRoot::sample("odb-save");
+        let open = || root.open();
+
+        // The first save writes the standard tables that Sample.root is
+        // missing. After that, saving without changes writes nothing.
+        let mut odb = open();
+        odb.save().unwrap();
+        let old_record = odb.db.get_view(ODB_VIEW);
+        odb.save().unwrap();
+        assert_eq!(odb.db.get_view(ODB_VIEW), old_record);
+
+        let mut friends = Table::new();
+        friends
+            .insert("dave", Value::StringValue(String::from("Dave")))
+            .unwrap();
+        odb.root_mut()
+            .insert("friends", Value::TableValue(Box::new(friends)))
+            .unwrap();
+        odb.root_mut()
+            .set_value("age", Value::LongValue(39))
+            .unwrap();
+        odb.save().unwrap();
+        assert_ne!(odb.db.get_view(ODB_VIEW), old_record);
+        drop(odb);
+
+        let mut odb = open();
+        assert!(matches!(
+            odb.lookup("age").unwrap().value(),
+            Value::LongValue(39)
+        ));
+        assert!(
+            matches!(odb.lookup("friends.dave").unwrap().value(), Value::StringValue(s) if s == "Dave")
+        );
+        assert_eq!(odb.window_info().len(), WINDOW_INFO_COUNT);
+    }
+
+    #[test]
+    fn menubar_scripts_are_saved_in_blocks_of_their_own() {
+        let root = TempRoot::sample("odb-menubar");
+        let mut source = Outline::new();
+        source.summits.push(Headline::new("file.open ()"));
+
+        let mut open = MenuCommand::new("Open…");
+        open.command_key = Some('O');
+        open.script = Some(Script::new(source.clone()));
+        let mut file = Menu::new("File");
+        file.items.push(MenuItem::Command(open));
+        let mut menubar = Menubar::new();
+        menubar.menus.push(file);
+
+        let mut odb = root.open();
+        odb.root_mut()
+            .insert("menubar", Value::Menubar(menubar.clone()))
+            .unwrap();
+        odb.save().unwrap();
+        drop(odb);
+
+        let mut odb = root.open();
+        let saved = odb
+            .root_table
+            .load_value("menubar", &mut odb.db, &odb.context)
+            .unwrap();
+        assert!(
+            matches!(saved, Some(Value::Menubar(saved)) if saved.scripts() == menubar.scripts())
+        );
+
+        // The menubar's item points at the block the script was saved to.
+        let address = odb.lookup("menubar").unwrap().old_address();
+        let block = odb.db.read_block(address).unwrap();
+        let outline = Outline::unpack(&block, &odb.context).unwrap();
+        let refcon = &outline.summits[0].subheads[0].refcon;
+        assert_eq!(refcon[4], b'O');
+        let script_address = u32::from_be_bytes(refcon[0..4].try_into().unwrap());
+        let block = odb.db.read_block(script_address).unwrap();
+        assert_eq!(
+            Script::unpack(&block, &odb.context).unwrap().source(),
+            &source
+        );
+
+        // Replacing the menubar releases the script's block along with it.
+        odb.root_mut().set_value("menubar", Value::NoValue).unwrap();
+        odb.save().unwrap();
+    }
+
+    #[test]
+    fn word_processing_text_is_kept_packed() {
+        let root = TempRoot::sample("odb-wptext");
+        let mut odb = root.open();
+
+        let packed = [0x00, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00];
+        let address = odb.db.allocate(&packed).unwrap();
+        let mut node =
+            TableNode::new_on_disk(String::from("notes"), ExternalType::WordProcessor, address);
+        assert!(matches!(
+            node.load_value(&mut odb.db, &odb.context).unwrap(),
+            Value::ExternalValue(ExternalType::WordProcessor, bytes) if bytes == &packed
+        ));
+
+        odb.root_mut()
+            .insert("notes", Value::WpText(WpText::new()))
+            .unwrap();
+        assert!(matches!(
+            odb.save(),
+            Err(Error::ODB(ODBError::CantSaveWpText))
+        ));
+    }
+
+    // A copy of Sample.root whose root table has a version 1 header, the way
+    // Frontier 4 wrote them, with its ODB record replaced by the given one.
+    fn frontier_4_root(name: &str, record: &dyn Fn(&mut db::Database) -> Vec<u8>) -> TempRoot {
+        let root = TempRoot::new(name);
+
+        // The header is read the way Frontier reads them, just after the
+        // block header and the sizes of the table and its records.
+        let mut bytes = fs::read(SAMPLE).unwrap();
+        let header = 0x2a9 + 8 + 4 + 4;
+        bytes[header..header + 2].copy_from_slice(&1u16.to_be_bytes());
+        fs::write(root.path(), bytes).unwrap();
+
+        let mut db = db::Database::open_file(root.file(), false).unwrap();
+        let record = record(&mut db);
+        let address = db.allocate(&record).unwrap();
+        db.set_view(ODB_VIEW, address);
+        db.flush().unwrap();
+        root
+    }
+
+    // A Frontier 4 window info: its rect, font name, font number, size and
+    // style, window ref, hidden and unused flags and reserved bytes.
+    fn version_1_window_info(font_name: &[u8], font_size: u16, is_hidden: bool) -> Vec<u8> {
+        let mut bytes = vec![0, 40, 0, 20, 1, 44, 2, 88];
+        bytes.push(font_name.len() as u8);
+        bytes.extend_from_slice(font_name);
+        bytes.resize(8 + 34, 0);
+        bytes.extend_from_slice(&[0, 3]);
+        bytes.extend_from_slice(&font_size.to_be_bytes());
+        bytes.extend_from_slice(&[0, 1]);
+        bytes.extend_from_slice(&[0x00, 0x12, 0x34, 0x56]);
+        bytes.push(is_hidden as u8);
+        bytes.push(0);
+        bytes.extend_from_slice(&[0; 8]);
+        bytes
+    }
+
+    #[test]
+    fn frontier_4_roots_are_converted() {
+        let old_root = frontier_4_root("frontier4", &|db| {
+            let script_string = db.allocate(b"msg (\"hello\")").unwrap();
+
+            let mut record = vec![0, 1, 0, 0, 0x02, 0xa9];
+            for i in 0..WINDOW_INFO_COUNT {
+                record.extend(version_1_window_info(b"Geneva", 9 + i as u16, i == 4));
+            }
+            record.extend_from_slice(&script_string.to_be_bytes());
+            record.extend_from_slice(&(IS_FLAG_DISABLED_MASK | 0x0001).to_be_bytes());
+            record.extend_from_slice(&2u16.to_be_bytes());
+            record.resize(ODB_RECORD_SIZE, 0);
+            record
+        });
+        let new_root = TempRoot::new("frontier5");
+
+        // Every field is carried over, so only the record and the root
+        // table's header are reported, besides the standard tables that
+        // Sample.root doesn't have.
+        let mut odb = ObjectDB::load_file(fs::File::open(old_root.path()).unwrap()).unwrap();
+        let conversions = &odb.conversion_report().conversions;
+        assert_eq!(conversions[0], Conversion::Record { version: 1 });
+        assert!(conversions[1..(conversions.len() - 1)]
+            .iter()
+            .all(|conversion| matches!(conversion, Conversion::SystemTable { .. })));
+        assert_eq!(
+            conversions.last(),
+            Some(&Conversion::TableHeader {
+                path: String::from("root"),
+                version: 1
+            })
+        );
+
+        odb.save_as(new_root.file()).unwrap();
+        drop(odb);
+
+        let mut odb = new_root.open();
+        assert!(odb.conversion_report().is_empty());
+        assert_eq!(odb.script_string(), "msg (\"hello\")");
+        assert!(odb.is_flag_disabled());
+        assert!(!odb.is_popup_disabled());
+        assert_eq!(odb.record[382..386], [0x80, 0x01, 0, 2]);
+
+        let window_info = odb.window_info();
+        assert_eq!(window_info.len(), WINDOW_INFO_COUNT);
+        assert_eq!(window_info[4].font_name(), "Geneva");
+        assert_eq!(window_info[4].font_size(), 13);
+        assert_eq!(window_info[4].font_number(), 3);
+        assert!(window_info[4].is_hidden());
+        assert!(!window_info[3].is_hidden());
+        assert_eq!(
+            window_info[0].window_rect(),
+            Rect {
+                top: 40,
+                left: 20,
+                bottom: 300,
+                right: 600
+            }
+        );
+
+        assert!(matches!(
+            odb.lookup("validUser").unwrap().value(),
+            Value::BooleanValue(true)
+        ));
+        assert!(
+            matches!(odb.lookup("lastName").unwrap().value(), Value::StringValue(s) if s == "Howard")
+        );
+        assert!(odb.lookup("system").is_ok());
+    }
+
+    #[test]
+    fn fields_of_short_frontier_4_records_are_reported() {
+        // The second window info's font name is longer than its field, and
+        // the record ends before the third.
+        let root = frontier_4_root("frontier4-short", &|_| {
+            let mut record = vec![0, 1, 0, 0, 0x02, 0xa9];
+            record.extend(version_1_window_info(b"Monaco", 9, false));
+            record.extend_from_slice(&[0xff; WINDOW_INFO_SIZE as usize]);
+            record
+        });
+
+        let odb = ObjectDB::load_file(fs::File::open(root.path()).unwrap()).unwrap();
+        let dropped: Vec<_> = odb
+            .conversion_report()
+            .conversions
+            .iter()
+            .filter_map(|conversion| match conversion {
+                Conversion::RecordField { field } => Some(field.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            dropped,
+            vec![
+                "window info 2",
+                "window info 3",
+                "window info 4",
+                "window info 5",
+                "window info 6",
+                "script string",
+                "flags",
+                "primary agent"
+            ]
+        );
+
+        assert_eq!(odb.window_info()[0].font_name(), "Monaco");
+        assert_eq!(odb.window_info()[1].font_name(), "");
+        assert_eq!(odb.script_string(), "");
+        assert!(!odb.is_flag_disabled());
+    }
+
+    #[test]
+    fn guests_are_looked_up_and_saved_on_their_own() {
+        let root = TempRoot::sample("guest");
+
+        let mut odb = open_sample();
+        let name = odb.open_guest(root.path()).unwrap().to_string();
+        assert!(odb.open_guest(root.path()).is_err());
+        assert_eq!(odb.guest_names().collect::<Vec<_>>(), vec![name.as_str()]);
+
+        let guest_path = format!("[\"{}\"].firstName", name.to_uppercase());
+        assert!(
+            matches!(odb.lookup(&guest_path).unwrap().value(), Value::StringValue(s) if s == "Ted")
+        );
+
+        let address = format!("@root.[\"{}\"].age", name);
+        assert_eq!(odb.resolve_address(&address).unwrap().name(), "age");
+        assert!(odb.resolve_address("@root.nothing").is_err());
+
+        let guest = odb.guest_mut(&name).unwrap();
+        guest
+            .root_mut()
+            .set_value("age", Value::LongValue(40))
+            .unwrap();
+        guest.save().unwrap();
+
+        let mut guest = odb.close_guest(&name).unwrap();
+        assert!(odb.guest(&name).is_none());
+        assert!(matches!(
+            guest.lookup("age").unwrap().value(),
+            Value::LongValue(40)
+        ));
+        drop(guest);
+
+        let mut guest = root.open();
+        assert!(matches!(
+            guest.lookup("age").unwrap().value(),
+            Value::LongValue(40)
+        ));
+    }
+
+    #[test]
+    fn guest_names_must_be_unambiguous() {
+        let dir = TempRoot::new("guests");
+        fs::create_dir_all(dir.path().join("other")).unwrap();
+        let first = dir.path().join("Guest.root");
+        let second = dir.path().join("other").join("Guest.root");
+        fs::copy(SAMPLE, &first).unwrap();
+        fs::copy(SAMPLE, &second).unwrap();
+
+        let mut odb = open_sample();
+        odb.open_guest(&first).unwrap();
+        assert!(matches!(
+            odb.open_guest(&second),
+            Err(Error::ODB(ODBError::GuestExists { .. }))
+        ));
+        assert!(matches!(
+            odb.open_guest_as(&second, "firstName"),
+            Err(Error::ODB(ODBError::GuestNameConflict { .. }))
+        ));
+        odb.open_guest_as(&second, "Other Guest").unwrap();
+        assert!(odb.lookup("[\"Other Guest\"].age").is_ok());
+
+        // An item added later with a guest's name doesn't quietly win or
+        // lose.
+        odb.root_mut()
+            .insert("Guest.root", Value::LongValue(1))
+            .unwrap();
+        assert!(matches!(
+            odb.lookup("[\"Guest.root\"].age"),
+            Err(Error::ODB(ODBError::GuestNameConflict { .. }))
+        ));
+    }
+
+    #[test]
+    fn missing_roots_are_created() {
+        let root = TempRoot::new("new");
+        let path = root.path();
+        let odb = ObjectDB::create_file(root.file()).unwrap();
+        let names = |odb: &ObjectDB| {
+            odb.root()
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        let skeleton = vec![
+            "scratchpad",
+            "suites",
+            "system",
+            "temp",
+            "user",
+            "websites",
+            "workspace",
+        ];
+        assert_eq!(names(&odb), skeleton);
+        assert!(odb.conversion_report().is_empty());
+        drop(odb);
+
+        // Nothing was written, so the tables are created again, and this time
+        // they're reported as repairs.
+        let mut odb = ObjectDB::load_file(fs::File::open(path).unwrap()).unwrap();
+        assert_eq!(names(&odb), skeleton);
+        assert_eq!(odb.conversion_report().conversions.len(), skeleton.len());
+        assert!(odb.save().is_err());
+        drop(odb);
+
+        root.open().save().unwrap();
+
+        let mut odb = ObjectDB::load_file(fs::File::open(path).unwrap()).unwrap();
+        assert_eq!(names(&odb), skeleton);
+        assert!(odb.conversion_report().is_empty());
+        assert!(matches!(
+            odb.lookup("suites").unwrap().value(),
+            Value::DiskValue(_)
+        ));
+        assert!(matches!(
+            odb.root_table
+                .load_value("suites", &mut odb.db, &odb.context),
+            Ok(Some(Value::TableValue(_)))
+        ));
+
+        // Sample.root with its root table unlinked from the ODB record can be
+        // opened read-only, since repairing it doesn't write anything.
+        let mut bytes = fs::read(SAMPLE).unwrap();
+        bytes[0x8e..0x92].copy_from_slice(&[0; 4]);
+        fs::write(path, &bytes).unwrap();
+
+        let odb = ObjectDB::load_file(fs::File::open(path).unwrap()).unwrap();
+        assert_eq!(names(&odb), skeleton);
+        drop(odb);
+        assert_eq!(fs::read(path).unwrap(), bytes);
+
+        // Sample.root itself has its own items but none of the standard
+        // tables, which are added alongside them.
+        fs::copy(SAMPLE, path).unwrap();
+        let mut odb = root.open();
+        assert_eq!(
+            odb.conversion_report().conversions[0],
+            Conversion::SystemTable {
+                name: String::from("system")
+            }
+        );
+        assert_eq!(odb.root().len(), 7 + skeleton.len());
+        odb.save().unwrap();
+        drop(odb);
+
+        let odb = ObjectDB::load_file(fs::File::open(path).unwrap()).unwrap();
+        assert!(odb.conversion_report().is_empty());
+        assert!(matches!(
+            odb.root().get("validUser").unwrap().value(),
+            Value::BooleanValue(true)
+        ));
+    }
+
+    #[test]
+    fn window_info_and_flags_are_saved() {
+        let root = TempRoot::sample("windows");
+        let open = || root.open();
+
+        let mut odb = open();
+        assert!(!odb.is_big_window());
+        let window_info = &mut odb.window_info_mut()[2];
+        window_info.set_font_name("Monaco");
+        window_info.set_font_size(9);
+        window_info.set_hidden(true);
+        odb.set_big_window(true);
+        odb.set_popup_disabled(true);
+        odb.save().unwrap();
+        drop(odb);
+
+        let odb = open();
+        assert!(odb.is_big_window());
+        assert!(odb.is_popup_disabled());
+        assert!(!odb.is_flag_disabled());
+        assert_eq!(odb.window_info()[2].font_name(), "Monaco");
+        assert_eq!(odb.window_info()[2].font_size(), 9);
+        assert!(odb.window_info()[2].is_hidden());
+        assert_eq!(odb.window_info()[0].font_name(), "Chicago");
+    }
+
+    #[test]
+    fn unchanged_window_info_isnt_saved() {
+        let root = TempRoot::sample("unchanged-windows");
+        let mut odb = root.open();
+        odb.save().unwrap();
+        drop(odb);
+        let saved = fs::read(root.path()).unwrap();
+
+        let mut odb = root.open();
+        let font_size = odb.window_info()[1].font_size();
+        odb.window_info_mut()[1].set_font_size(font_size);
+        odb.set_big_window(odb.is_big_window());
+        odb.save().unwrap();
+        drop(odb);
+        assert_eq!(fs::read(root.path()).unwrap(), saved);
+    }
+
+    #[test]
+    fn strings_use_the_text_encoding() {
+        let root = TempRoot::sample("encoding");
+        let file = || root.file();
+
+        let mut odb = ObjectDB::load_file_with_encoding(file(), TextEncoding::MacJapanese).unwrap();
+        assert_eq!(odb.text_encoding(), TextEncoding::MacJapanese);
+        odb.root_mut()
+            .insert("名前", Value::StringValue(String::from("フロンティア")))
+            .unwrap();
+        odb.save().unwrap();
+        drop(odb);
+
+        let mut odb = ObjectDB::load_file_with_encoding(file(), TextEncoding::MacJapanese).unwrap();
+        assert!(
+            matches!(odb.lookup("名前").unwrap().value(), Value::StringValue(s) if s == "フロンティア")
+        );
+
+        let odb = ObjectDB::load_file(file()).unwrap();
+        assert_eq!(odb.text_encoding(), TextEncoding::MacRoman);
+        assert!(odb.root().get("名前").is_none());
+    }
+
+    #[test]
+    fn unmappable_characters_fail_the_save() {
+        let root = TempRoot::sample("unmappable");
+        let file = || root.file();
+
+        let mut odb = ObjectDB::load_file(file()).unwrap();
+        assert_eq!(odb.unmappable(), Unmappable::Fail);
+        odb.root_mut()
+            .insert("country", Value::StringValue(String::from("日本")))
+            .unwrap();
+        assert!(matches!(
+            odb.save(),
+            Err(Error::ODB(ODBError::UnmappableCharacter {
+                character: '日',
+                ..
+            }))
+        ));
+
+        odb.set_unmappable(Unmappable::Replace(b'?'));
+        odb.save().unwrap();
+        drop(odb);
+
+        let mut odb = ObjectDB::load_file(file()).unwrap();
+        assert!(
+            matches!(odb.lookup("country").unwrap().value(), Value::StringValue(s) if s == "??")
+        );
+    }
+
+    #[test]
+    fn outlines_are_saved_as_externals() {
+        let root = TempRoot::sample("outline");
+        let open = || root.open();
+
+        let mut summit = Headline::new("To do");
+        summit.is_expanded = true;
+        summit
+            .subheads
+            .push(Headline::new("Write the outline code"));
+        let mut outline = Outline::new();
+        outline.summits.push(summit);
+
+        let mut odb = open();
+        odb.root_mut()
+            .insert("todo", Value::Outline(outline.clone()))
+            .unwrap();
+        odb.save().unwrap();
+        drop(odb);
+
+        let mut odb = open();
+        assert_eq!(
+            odb.root().get("todo").unwrap().external_type(),
+            Some(ExternalType::Outline)
+        );
+        match odb.lookup("todo").unwrap().value() {
+            Value::DiskValue(_) => {}
+            value => panic!("{:?}", value),
+        }
+        match odb.root_table.load_value("todo", &mut odb.db, &odb.context) {
+            Ok(Some(Value::Outline(loaded))) => assert_eq!(*loaded, outline),
+            value => panic!("{:?}", value),
+        }
+    }
+
+    #[test]
+    fn undecodable_externals_are_kept_raw() {
+        let root = TempRoot::sample("damaged-outline");
+        let copy = TempRoot::new("damaged-outline-copy");
+
+        // An outline whose headline count runs past the end of its block.
+        let damaged = vec![0, 0, 0, 9, 0xff, 0xff];
+        let mut odb = root.open();
+        odb.root_mut()
+            .insert(
+                "notes",
+                Value::ExternalValue(ExternalType::Outline, damaged.clone()),
+            )
+            .unwrap();
+        odb.save().unwrap();
+        drop(odb);
+
+        // The rest of the table loads, and the outline can still be copied to
+        // another root.
+        let mut odb = root.open();
+        assert_eq!(
+            odb.root().get("notes").unwrap().external_type(),
+            Some(ExternalType::Outline)
+        );
+        match odb
+            .root_table
+            .load_value("notes", &mut odb.db, &odb.context)
+        {
+            Ok(Some(Value::ExternalValue(ExternalType::Outline, bytes))) => {
+                assert_eq!(*bytes, damaged)
+            }
+            value => panic!("{:?}", value),
+        }
+        assert!(matches!(
+            odb.lookup("validUser").unwrap().value(),
+            Value::BooleanValue(true)
+        ));
+        odb.save_as(copy.file()).unwrap();
+        drop(odb);
+
+        let mut odb = copy.open();
+        match odb
+            .root_table
+            .load_value("notes", &mut odb.db, &odb.context)
+        {
+            Ok(Some(Value::ExternalValue(ExternalType::Outline, bytes))) => {
+                assert_eq!(*bytes, damaged)
+            }
+            value => panic!("{:?}", value),
+        }
+    }
+
+    #[test]
+    fn lookup_reports_the_missing_segment() {
+        let mut odb = open_sample();
+
+        match odb.lookup("nothing.here") {
+            Err(Error::ODB(ODBError::NameNotFound { table, name })) => {
+                assert_eq!(table, "root");
+                assert_eq!(name, "nothing");
+            }
+            result => panic!("{:?}", result.map(|node| node.value())),
+        }
+
+        match odb.lookup("[15]") {
+            Err(Error::ODB(ODBError::IndexOutOfRange { index, .. })) => assert_eq!(index, 15),
+            result => panic!("{:?}", result.map(|node| node.value())),
+        }
+
+        match odb.lookup("validUser.name") {
+            Err(Error::ODB(ODBError::NotATable { path })) => assert_eq!(path, "validUser"),
+            result => panic!("{:?}", result.map(|node| node.value())),
+        }
+    }
+}
@@ -16,25 +16,167 @@ You should have received a copy of the GNU General Public License
 along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use crate::text_encoding::TextEncoding;
 use std::error;
 use std::fmt;
 
 #[derive(Debug)]
 pub enum ODBError {
     BadDatabaseVersion,
+    BadPackedValue,
+    PathSyntax {
+        path: String,
+        position: usize,
+    },
+    NameNotFound {
+        table: String,
+        name: String,
+    },
+    IndexOutOfRange {
+        table: String,
+        index: usize,
+    },
+    NotATable {
+        path: String,
+    },
+    NameExists {
+        name: String,
+    },
+    InvalidName {
+        name: String,
+    },
+    GuestExists {
+        name: String,
+    },
+    GuestNameConflict {
+        name: String,
+    },
+    NoRootTable,
+    ValueNotLoaded,
+    UnmappableCharacter {
+        character: char,
+        encoding: TextEncoding,
+    },
+    PictureTooLarge {
+        width: usize,
+        height: usize,
+    },
+    CantDecompile {
+        construct: String,
+    },
+    UnresolvedVolume {
+        name: String,
+    },
+    InvalidHeadline {
+        text: String,
+    },
+    CantSaveWpText,
 }
 
 impl ODBError {
     fn as_str(&self) -> &'static str {
         match *self {
             ODBError::BadDatabaseVersion => "The version number of this database file is not recognized by this version of Brave Flea.",
+            ODBError::BadPackedValue => "This database contains a damaged value. Use the Save a Copy command to create a new, compacted database.",
+            ODBError::PathSyntax { .. } => "The path could not be parsed.",
+            ODBError::NameNotFound { .. } => "The table doesn't contain an item with that name.",
+            ODBError::IndexOutOfRange { .. } => "The table doesn't contain an item at that index.",
+            ODBError::NotATable { .. } => "The path passes through an item that isn't a table.",
+            ODBError::NameExists { .. } => "The table already contains an item with that name.",
+            ODBError::InvalidName { .. } => "Names must be between 1 and 255 characters long and fit in 255 bytes.",
+            ODBError::GuestExists { .. } => "A guest database is already open under that name.",
+            ODBError::GuestNameConflict { .. } => "A guest database and an item in the root table have the same name.",
+            ODBError::NoRootTable => "This database doesn't have a root table.",
+            ODBError::ValueNotLoaded => "The value hasn't been loaded from the database.",
+            ODBError::UnmappableCharacter { .. } => "The text contains a character that can't be written in the database's text encoding.",
+            ODBError::PictureTooLarge { .. } => "The picture is too large to render.",
+            ODBError::CantDecompile { .. } => "The script's code contains something that can't be written as UserTalk.",
+            ODBError::UnresolvedVolume { .. } => "The file spec names its volume, but only a volume's reference number can be saved.",
+            ODBError::InvalidHeadline { .. } => "Headlines can't contain returns.",
+            ODBError::CantSaveWpText => "Word processing text can't be saved in a root, because Frontier's format for it isn't supported yet.",
         }
     }
 }
 
 impl fmt::Display for ODBError {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(fmt, "{}", self.as_str())
+        match self {
+            ODBError::PathSyntax { path, position } => {
+                write!(
+                    fmt,
+                    "Can't parse the path \"{}\" at character {}.",
+                    path,
+                    position + 1
+                )
+            }
+            ODBError::NameNotFound { table, name } if table.is_empty() => {
+                write!(fmt, "The table doesn't contain an item named \"{}\".", name)
+            }
+            ODBError::NameNotFound { table, name } => {
+                write!(
+                    fmt,
+                    "The table \"{}\" doesn't contain an item named \"{}\".",
+                    table, name
+                )
+            }
+            ODBError::IndexOutOfRange { table, index } => {
+                write!(
+                    fmt,
+                    "The table \"{}\" doesn't contain an item at index {}.",
+                    table, index
+                )
+            }
+            ODBError::NotATable { path } => write!(fmt, "\"{}\" is not a table.", path),
+            ODBError::NameExists { name } => write!(
+                fmt,
+                "The table already contains an item named \"{}\".",
+                name
+            ),
+            ODBError::InvalidName { name } => write!(
+                fmt,
+                "\"{}\" can't be used as a name. Names must be between 1 and 255 characters long and fit in 255 bytes.",
+                name
+            ),
+            ODBError::GuestExists { name } => {
+                write!(fmt, "A guest database is already open as \"{}\".", name)
+            }
+            ODBError::GuestNameConflict { name } => write!(
+                fmt,
+                "\"{}\" names both a guest database and an item in the root table.",
+                name
+            ),
+            ODBError::UnmappableCharacter {
+                character,
+                encoding,
+            } => write!(
+                fmt,
+                "\"{}\" (U+{:04X}) can't be written as a {} character.",
+                character,
+                *character as u32,
+                encoding.name()
+            ),
+            ODBError::PictureTooLarge { width, height } => write!(
+                fmt,
+                "The picture is {} by {} pixels, which is too large to render.",
+                width, height
+            ),
+            ODBError::CantDecompile { construct } => write!(
+                fmt,
+                "The script's code can't be decompiled because it contains {}.",
+                construct
+            ),
+            ODBError::UnresolvedVolume { name } => write!(
+                fmt,
+                "The file spec is on the volume \"{}\", which has to be given as a reference number before it can be saved.",
+                name
+            ),
+            ODBError::InvalidHeadline { text } => write!(
+                fmt,
+                "The headline \"{}\" can't be saved because it contains a return.",
+                text.escape_debug()
+            ),
+            _ => write!(fmt, "{}", self.as_str()),
+        }
     }
 }
 
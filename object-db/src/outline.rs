@@ -0,0 +1,409 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::error::*;
+use crate::odb_error::*;
+use crate::variable::*;
+use std::convert::TryInto;
+use std::slice;
+
+const OUTLINE_VERSION: u16 = 2;
+const OUTLINE_HEADER_SIZE: usize = 4;
+const HEAD_RECORD_SIZE: usize = 8;
+// Each headline's text is ended by a return.
+const HEADLINE_END: u8 = b'\r';
+
+// The flags word is a Mac bitfield, so the first flag is the high bit.
+const IS_EXPANDED_MASK: u16 = 0x8000;
+const IS_COMMENT_MASK: u16 = 0x4000;
+const IS_BREAKPOINT_MASK: u16 = 0x2000;
+const KNOWN_FLAGS_MASK: u16 = IS_EXPANDED_MASK | IS_COMMENT_MASK | IS_BREAKPOINT_MASK;
+
+// An outline is a list of summits, each of which may have subheads of its
+// own.
+//
+// Packed, it starts with a version number and the size of the header,
+// followed by the outline's display state, which is kept as it was read.
+// Then come three handles, each a 4 byte length followed by its bytes. The
+// first is the text of every headline in outline order, each one ended by a
+// return. The second has a record for each headline: its level, its flags
+// word and the length of its refcon. The last holds the refcons, one after
+// another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outline {
+    pub version: u16,
+    pub summits: Vec<Headline>,
+    display_state: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Headline {
+    pub text: String,
+    pub is_expanded: bool,
+    pub is_comment: bool,
+    pub is_breakpoint: bool,
+    // Data the application that owns the outline attached to the headline.
+    pub refcon: Vec<u8>,
+    pub subheads: Vec<Headline>,
+    // Flag bits that aren't decoded, written back as they were read.
+    other_flags: u16,
+}
+
+impl Outline {
+    pub fn new() -> Self {
+        Self {
+            version: OUTLINE_VERSION,
+            summits: vec![],
+            display_state: vec![],
+        }
+    }
+
+    pub fn summits(&self) -> &[Headline] {
+        &self.summits
+    }
+
+    // Every headline in outline order, along with its level. Summits are at
+    // level 0.
+    pub fn iter(&self) -> Headlines<'_> {
+        Headlines {
+            stack: vec![self.summits.iter()],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.summits.is_empty()
+    }
+
+    pub fn unpack(bytes: &[u8], context: &LoadContext) -> Result<Self> {
+        let (outline, length) = Self::unpack_prefix(bytes, context)?;
+        if length != bytes.len() {
+            return Err(Error::from(ODBError::BadPackedValue));
+        }
+        Ok(outline)
+    }
+
+    // Unpacks an outline from the start of the bytes, returning it along with
+    // the number of bytes it took up, for values that keep more after it.
+    pub(crate) fn unpack_prefix(bytes: &[u8], context: &LoadContext) -> Result<(Self, usize)> {
+        let header = bytes
+            .get(0..OUTLINE_HEADER_SIZE)
+            .ok_or(ODBError::BadPackedValue)?;
+        let version = u16::from_be_bytes(header[0..2].try_into()?);
+        let header_size = u16::from_be_bytes(header[2..4].try_into()?) as usize;
+        let display_state = bytes
+            .get(OUTLINE_HEADER_SIZE..header_size)
+            .ok_or(ODBError::BadPackedValue)?
+            .to_vec();
+
+        let mut index = header_size;
+        let text = read_handle(bytes, &mut index)?;
+        let records = read_handle(bytes, &mut index)?;
+        let refcons = read_handle(bytes, &mut index)?;
+
+        // Every headline's text ends with a return, so there's one more piece
+        // than there are headlines, and it's empty.
+        let mut lines: Vec<_> = text.split(|byte| *byte == HEADLINE_END).collect();
+        if lines.pop() != Some(&[]) || records.len() != lines.len() * HEAD_RECORD_SIZE {
+            return Err(Error::from(ODBError::BadPackedValue));
+        }
+
+        // The headlines that are still open at each level, summit first.
+        let mut open: Vec<Headline> = vec![];
+        let mut summits = vec![];
+        let mut refcon_index = 0;
+
+        for (line, record) in lines.iter().zip(records.chunks(HEAD_RECORD_SIZE)) {
+            let level = u16::from_be_bytes(record[0..2].try_into()?) as usize;
+            let flags = u16::from_be_bytes(record[2..4].try_into()?);
+            let refcon_length = u32::from_be_bytes(record[4..8].try_into()?) as usize;
+            let refcon = refcons
+                .get(refcon_index..(refcon_index + refcon_length))
+                .ok_or(ODBError::BadPackedValue)?;
+            refcon_index += refcon_length;
+
+            // A headline can only be one level deeper than the one before.
+            if level > open.len() {
+                return Err(Error::from(ODBError::BadPackedValue));
+            }
+            close_headlines(&mut open, &mut summits, level);
+
+            open.push(Headline {
+                text: context.text_encoding.decode(line),
+                is_expanded: (flags & IS_EXPANDED_MASK) != 0,
+                is_comment: (flags & IS_COMMENT_MASK) != 0,
+                is_breakpoint: (flags & IS_BREAKPOINT_MASK) != 0,
+                refcon: refcon.to_vec(),
+                subheads: vec![],
+                other_flags: flags & !KNOWN_FLAGS_MASK,
+            });
+        }
+
+        if refcon_index != refcons.len() {
+            return Err(Error::from(ODBError::BadPackedValue));
+        }
+        close_headlines(&mut open, &mut summits, 0);
+
+        let outline = Self {
+            version,
+            summits,
+            display_state,
+        };
+        Ok((outline, index))
+    }
+
+    pub fn pack(&self, context: &LoadContext) -> Result<Vec<u8>> {
+        let header_size = OUTLINE_HEADER_SIZE + self.display_state.len();
+
+        let mut text = vec![];
+        let mut records = vec![];
+        let mut refcons = vec![];
+        for (level, headline) in self.iter() {
+            let encoded = context.encode(&headline.text)?;
+            if encoded.contains(&HEADLINE_END) {
+                return Err(Error::from(ODBError::InvalidHeadline {
+                    text: headline.text.clone(),
+                }));
+            }
+            text.extend(encoded);
+            text.push(HEADLINE_END);
+
+            records.extend_from_slice(&(level as u16).to_be_bytes());
+            records.extend_from_slice(&headline.flags().to_be_bytes());
+            records.extend_from_slice(&(headline.refcon.len() as u32).to_be_bytes());
+            refcons.extend_from_slice(&headline.refcon);
+        }
+
+        let mut bytes = self.version.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&(header_size as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.display_state);
+        for handle in [text, records, refcons] {
+            bytes.extend_from_slice(&(handle.len() as u32).to_be_bytes());
+            bytes.extend(handle);
+        }
+
+        Ok(bytes)
+    }
+}
+
+impl Default for Outline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LoadFromBytes for Outline {
+    fn load_from_bytes(bytes: &[u8], context: &LoadContext) -> Result<Self> {
+        Self::unpack(bytes, context)
+    }
+}
+
+impl SaveToBytes for Outline {
+    fn save_to_bytes(&self, context: &LoadContext) -> Result<Vec<u8>> {
+        self.pack(context)
+    }
+}
+
+impl Default for Headline {
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
+impl Headline {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: String::from(text),
+            is_expanded: false,
+            is_comment: false,
+            is_breakpoint: false,
+            refcon: vec![],
+            subheads: vec![],
+            other_flags: 0,
+        }
+    }
+
+    pub fn subheads(&self) -> &[Headline] {
+        &self.subheads
+    }
+
+    fn flags(&self) -> u16 {
+        let mut flags = self.other_flags;
+        for (mask, is_set) in [
+            (IS_EXPANDED_MASK, self.is_expanded),
+            (IS_COMMENT_MASK, self.is_comment),
+            (IS_BREAKPOINT_MASK, self.is_breakpoint),
+        ] {
+            if is_set {
+                flags |= mask;
+            }
+        }
+        flags
+    }
+}
+
+// A handle packed as a 4 byte length followed by its bytes.
+fn read_handle<'a>(bytes: &'a [u8], index: &mut usize) -> Result<&'a [u8]> {
+    let length = bytes
+        .get(*index..(*index + 4))
+        .ok_or(ODBError::BadPackedValue)?;
+    let length = u32::from_be_bytes(length.try_into()?) as usize;
+    let handle = bytes
+        .get((*index + 4)..(*index + 4 + length))
+        .ok_or(ODBError::BadPackedValue)?;
+    *index += 4 + length;
+    Ok(handle)
+}
+
+// Moves the open headlines deeper than the level into their parents, or into
+// the summits if they're at level 0.
+fn close_headlines(open: &mut Vec<Headline>, summits: &mut Vec<Headline>, level: usize) {
+    while open.len() > level {
+        let headline = open.pop().unwrap();
+        match open.last_mut() {
+            Some(parent) => parent.subheads.push(headline),
+            None => summits.push(headline),
+        }
+    }
+}
+
+pub struct Headlines<'a> {
+    stack: Vec<slice::Iter<'a, Headline>>,
+}
+
+impl<'a> Iterator for Headlines<'a> {
+    type Item = (usize, &'a Headline);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(siblings) = self.stack.last_mut() {
+            match siblings.next() {
+                Some(headline) => {
+                    let level = self.stack.len() - 1;
+                    self.stack.push(headline.subheads.iter());
+                    return Some((level, headline));
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An outline laid out the way it's packed: the header and display state,
+    // then the headlines' text, their records and their refcons.
+    #[rustfmt::skip]
+    const SAMPLE_OUTLINE: [u8; 93] = [
+        // version 2, an 8 byte header with 4 bytes of display state
+        0x00, 0x02, 0x00, 0x08, 0x00, 0x00, 0x00, 0x03,
+        // the text of the five headlines, in MacRoman
+        0x00, 0x00, 0x00, 0x1f,
+        b'G', b'r', b'o', b'c', b'e', b'r', b'i', b'e', b's', b'\r',
+        b'C', b'a', b'f', 0x8e, b'\r',
+        b'M', b'i', b'l', b'k', b'\r',
+        b'2', b'%', b'\r',
+        b'E', b'r', b'r', b'a', b'n', b'd', b's', b'\r',
+        // a level, flags word and refcon length for each headline
+        0x00, 0x00, 0x00, 0x28,
+        0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+        0x00, 0x01, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x02, 0x20, 0x01, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // the refcons
+        0x00, 0x00, 0x00, 0x02, 0x01, 0x02,
+    ];
+
+    fn context() -> LoadContext {
+        LoadContext::for_platform(Platform::Mac)
+    }
+
+    #[test]
+    fn unpacks_the_headline_tree() {
+        let outline = Outline::unpack(&SAMPLE_OUTLINE, &context()).unwrap();
+
+        assert_eq!(outline.summits().len(), 2);
+        assert_eq!(outline.len(), 5);
+
+        let groceries = &outline.summits()[0];
+        assert_eq!(groceries.text, "Groceries");
+        assert!(groceries.is_expanded);
+        assert_eq!(groceries.subheads().len(), 2);
+        assert_eq!(groceries.subheads()[0].text, "Café");
+        assert_eq!(groceries.subheads()[0].refcon, vec![1, 2]);
+        assert!(groceries.subheads()[1].is_comment);
+        assert!(groceries.subheads()[1].subheads()[0].is_breakpoint);
+
+        let walked: Vec<_> = outline
+            .iter()
+            .map(|(level, headline)| (level, headline.text.as_str()))
+            .collect();
+        assert_eq!(
+            walked,
+            vec![
+                (0, "Groceries"),
+                (1, "Café"),
+                (1, "Milk"),
+                (2, "2%"),
+                (0, "Errands")
+            ]
+        );
+
+        assert_eq!(outline.pack(&context()).unwrap(), SAMPLE_OUTLINE.to_vec());
+    }
+
+    #[test]
+    fn damaged_outlines_are_errors() {
+        // A headline two levels below the one before it.
+        let mut bytes = SAMPLE_OUTLINE;
+        bytes[72] = 3;
+        assert!(Outline::unpack(&bytes, &context()).is_err());
+
+        // Text that doesn't end with a return.
+        let mut bytes = SAMPLE_OUTLINE;
+        bytes[42] = b'x';
+        assert!(Outline::unpack(&bytes, &context()).is_err());
+
+        // A refcon that runs past the end of the refcons.
+        let mut bytes = SAMPLE_OUTLINE;
+        bytes[62] = 3;
+        assert!(Outline::unpack(&bytes, &context()).is_err());
+
+        assert!(Outline::unpack(&SAMPLE_OUTLINE[..90], &context()).is_err());
+    }
+
+    #[test]
+    fn headlines_cant_hold_returns() {
+        let mut outline = Outline::new();
+        outline.summits.push(Headline::new("two\rlines"));
+
+        assert!(matches!(
+            outline.pack(&context()),
+            Err(Error::ODB(ODBError::InvalidHeadline { .. }))
+        ));
+    }
+}
@@ -0,0 +1,615 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::code_tree::*;
+use crate::error::*;
+use crate::file_spec::*;
+use crate::menubar::*;
+use crate::obj_spec::*;
+use crate::odb_error::*;
+use crate::outline::*;
+use crate::password::*;
+use crate::pict::*;
+use crate::quickdraw::*;
+use crate::script::*;
+use crate::table::*;
+use crate::value_record::*;
+use crate::variable::*;
+use crate::wptext::*;
+use std::convert::TryInto;
+
+const PACK_VERSION: u16 = 1;
+const PACK_HEADER_SIZE: usize = 8;
+
+// Packs a value into Brave Flea's own interchange format, for copying values
+// between roots and storing them outside of one. It isn't the format that
+// Frontier's pack verb writes, and Frontier can't read it.
+//
+// A packed value starts with a version number, the value's type and, for
+// externals, the external type. Then comes the length of the value's data,
+// followed by the data. Lists are a count of their items followed by each
+// item packed, and records and tables are a count followed by each item's
+// name, as a pascal string, and its packed value. Tables are packed with
+// everything in them, so their values have to be loaded first. A menubar's
+// scripts are kept out of it in a root, so here they follow the packed
+// menubar as a list with an item for each command. Packing keeps
+// everything, passwords included, so it round trips. Redaction is left to
+// Value::dump.
+pub fn pack_value(value: &Value, context: &LoadContext) -> Result<Vec<u8>> {
+    let mut value_type = DiskSymbolValueType::of(value);
+    let mut external_id = 0;
+    if let Some(external_type) = external_type_of(value) {
+        value_type = DiskSymbolValueType::ExternalValue as u8;
+        external_id = external_type as u8;
+    }
+
+    let data = match value {
+        Value::Uninitialized | Value::NoValue => vec![],
+        Value::CharValue(c) => vec![context.encode_byte(*c)?],
+        Value::IntValue(n) => n.to_be_bytes().to_vec(),
+        Value::LongValue(n) => n.to_be_bytes().to_vec(),
+        Value::BinaryValue(bytes) => bytes.clone(),
+        Value::BooleanValue(b) => vec![*b as u8],
+        Value::DateValue(date) => mac_seconds_from_date(*date).to_be_bytes().to_vec(),
+        Value::AddressValue(s) | Value::StringValue(s) => context.encode(s)?,
+        Value::Password(password) => context.encode(password.reveal())?,
+        Value::CodeValue(tree) => tree.pack(context)?,
+        Value::DoubleValue(d) => d.to_be_bytes().to_vec(),
+        Value::DirectionValue(direction) => vec![*direction],
+        Value::OSTypeValue(code) | Value::EnumValue(code) => code.0.to_vec(),
+        Value::PointValue(point) => point.to_bytes().to_vec(),
+        Value::RectValue(rect) => rect.to_bytes().to_vec(),
+        Value::PatternValue(pattern) => pattern.to_bytes().to_vec(),
+        Value::RGBValue(color) => color.to_bytes().to_vec(),
+        Value::FixedValue(f) => ((f * FIXED_ONE).round() as i32).to_be_bytes().to_vec(),
+        Value::SingleValue(f) => f.to_bits().to_be_bytes().to_vec(),
+        Value::FileSpec(spec) => spec.to_bytes(context)?,
+        Value::ObjSpec(spec) => spec.to_bytes(context.text_encoding, context.unmappable)?,
+        Value::ListValue(items) => pack_list(items, context)?,
+        Value::RecordValue(items) => pack_record(
+            items.iter().map(|(name, value)| (name.as_str(), value)),
+            items.len(),
+            context,
+        )?,
+        Value::TableValue(table) => pack_record(
+            table.iter().map(|(name, node)| (name, node.value())),
+            table.len(),
+            context,
+        )?,
+        Value::Outline(outline) => outline.pack(context)?,
+        Value::Script(script) => script.pack(context)?,
+        Value::WpText(wptext) => wptext.pack(context)?,
+        Value::Menubar(menubar) => pack_menubar(menubar, context)?,
+        Value::Picture(picture) => picture.pack(),
+        Value::ExternalValue(_, bytes) | Value::RawValue(_, bytes) => bytes.clone(),
+        Value::DiskValue(_) => return Err(Error::from(ODBError::ValueNotLoaded)),
+    };
+
+    let mut bytes = PACK_VERSION.to_be_bytes().to_vec();
+    bytes.push(value_type);
+    bytes.push(external_id);
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    bytes.extend(data);
+    Ok(bytes)
+}
+
+// Unpacks a value packed by pack_value. Externals of the kinds that are
+// decoded become their own values, the rest stay external values.
+pub fn unpack_value(bytes: &[u8], context: &LoadContext) -> Result<Value> {
+    let mut index = 0;
+    let value = read_value(bytes, &mut index, context)?;
+    if index != bytes.len() {
+        return Err(Error::from(ODBError::BadPackedValue));
+    }
+    Ok(value)
+}
+
+// The data of a list: a count of the items, then each one packed.
+pub(crate) fn pack_list(items: &[Value], context: &LoadContext) -> Result<Vec<u8>> {
+    let mut bytes = (items.len() as u32).to_be_bytes().to_vec();
+    for item in items {
+        bytes.extend(pack_value(item, context)?);
+    }
+    Ok(bytes)
+}
+
+pub(crate) fn unpack_list(data: &[u8], context: &LoadContext) -> Result<Vec<Value>> {
+    let mut index = 0;
+    let count = read_count(data, &mut index)?;
+
+    let mut items = vec![];
+    for _ in 0..count {
+        items.push(read_value(data, &mut index, context)?);
+    }
+    check_end(data, index)?;
+    Ok(items)
+}
+
+// The data of a record: a count of the items, then each one's name and
+// packed value.
+pub(crate) fn pack_record<'a>(
+    items: impl Iterator<Item = (&'a str, &'a Value)>,
+    count: usize,
+    context: &LoadContext,
+) -> Result<Vec<u8>> {
+    let mut bytes = (count as u32).to_be_bytes().to_vec();
+    for (name, value) in items {
+        let name = encode_name(name, context)?;
+        bytes.push(name.len() as u8);
+        bytes.extend(name);
+        bytes.extend(pack_value(value, context)?);
+    }
+    Ok(bytes)
+}
+
+pub(crate) fn unpack_record(data: &[u8], context: &LoadContext) -> Result<Vec<(String, Value)>> {
+    let mut index = 0;
+    let count = read_count(data, &mut index)?;
+
+    let mut items = vec![];
+    for _ in 0..count {
+        let length = *data.get(index).ok_or(ODBError::BadPackedValue)? as usize;
+        let name = data
+            .get((index + 1)..(index + 1 + length))
+            .ok_or(ODBError::BadPackedValue)?;
+        index += 1 + length;
+        let name = context.text_encoding.decode(name);
+        items.push((name, read_value(data, &mut index, context)?));
+    }
+    check_end(data, index)?;
+    Ok(items)
+}
+
+// The length of the packed menubar and the menubar, then the list of its
+// commands' scripts, with no value for commands that don't have one.
+fn pack_menubar(menubar: &Menubar, context: &LoadContext) -> Result<Vec<u8>> {
+    let packed = menubar.pack(context)?;
+    let scripts: Vec<Value> = menubar
+        .scripts()
+        .into_iter()
+        .map(|script| match script {
+            Some(script) => Value::Script(script.clone()),
+            None => Value::NoValue,
+        })
+        .collect();
+
+    let mut bytes = (packed.len() as u32).to_be_bytes().to_vec();
+    bytes.extend(packed);
+    bytes.extend(pack_list(&scripts, context)?);
+    Ok(bytes)
+}
+
+fn unpack_menubar(data: &[u8], context: &LoadContext) -> Result<Menubar> {
+    let mut index = 0;
+    let length = read_count(data, &mut index)?;
+    let packed = data
+        .get(index..(index + length))
+        .ok_or(ODBError::BadPackedValue)?;
+    let mut menubar = Menubar::unpack(packed, context)?;
+
+    let scripts = unpack_list(&data[(index + length)..], context)?
+        .into_iter()
+        .map(|value| match value {
+            Value::Script(script) => Ok(Some(script)),
+            Value::NoValue => Ok(None),
+            _ => Err(Error::from(ODBError::BadPackedValue)),
+        })
+        .collect::<Result<_>>()?;
+    menubar.set_scripts(scripts)?;
+
+    Ok(menubar)
+}
+
+fn read_value(bytes: &[u8], index: &mut usize, context: &LoadContext) -> Result<Value> {
+    let header = bytes
+        .get(*index..(*index + PACK_HEADER_SIZE))
+        .ok_or(ODBError::BadPackedValue)?;
+    if u16::from_be_bytes(header[0..2].try_into()?) != PACK_VERSION {
+        return Err(Error::from(ODBError::BadPackedValue));
+    }
+    let value_type = header[2];
+    let external_id = header[3];
+    let length = u32::from_be_bytes(header[4..8].try_into()?) as usize;
+    *index += PACK_HEADER_SIZE;
+
+    let data = bytes
+        .get(*index..(*index + length))
+        .ok_or(ODBError::BadPackedValue)?;
+    *index += length;
+
+    // Values that don't decode are kept as they were packed.
+    match unpack_data(value_type, external_id, data, context) {
+        Ok(value) => Ok(value),
+        Err(_) if value_type == DiskSymbolValueType::ExternalValue as u8 => {
+            let external_type =
+                ExternalType::from_id(external_id).ok_or(ODBError::BadPackedValue)?;
+            Ok(Value::ExternalValue(external_type, data.to_vec()))
+        }
+        Err(_) => Ok(Value::RawValue(value_type, data.to_vec())),
+    }
+}
+
+fn unpack_data(
+    value_type: u8,
+    external_id: u8,
+    data: &[u8],
+    context: &LoadContext,
+) -> Result<Value> {
+    let encoding = context.text_encoding;
+    let fixed = |size: usize| -> Result<&[u8]> {
+        if data.len() != size {
+            return Err(Error::from(ODBError::BadPackedValue));
+        }
+        Ok(data)
+    };
+
+    let value = match value_type {
+        x if x == DiskSymbolValueType::Uninitialized as u8 => Value::Uninitialized,
+        x if x == DiskSymbolValueType::NoValue as u8 => Value::NoValue,
+        x if x == DiskSymbolValueType::CharValue as u8 => {
+            Value::CharValue(encoding.decode_byte(fixed(1)?[0]))
+        }
+        x if x == DiskSymbolValueType::IntValue as u8 => {
+            Value::IntValue(i16::from_be_bytes(fixed(2)?.try_into()?))
+        }
+        x if x == DiskSymbolValueType::LongValue as u8 => {
+            Value::LongValue(i32::from_be_bytes(fixed(4)?.try_into()?))
+        }
+        x if x == DiskSymbolValueType::BinaryValue as u8 => Value::BinaryValue(data.to_vec()),
+        x if x == DiskSymbolValueType::BooleanValue as u8 => Value::BooleanValue(fixed(1)?[0] != 0),
+        x if x == DiskSymbolValueType::DateValue as u8 => Value::DateValue(date_from_mac_seconds(
+            u32::from_be_bytes(fixed(4)?.try_into()?),
+        )),
+        x if x == DiskSymbolValueType::AddressValye as u8 => {
+            Value::AddressValue(encoding.decode(data))
+        }
+        x if x == DiskSymbolValueType::CodeValue as u8 => {
+            Value::CodeValue(CodeTree::unpack(data, context)?)
+        }
+        x if x == DiskSymbolValueType::DoubleValue as u8 => Value::DoubleValue(read_double(data)?),
+        x if x == DiskSymbolValueType::StringValue as u8 => {
+            Value::StringValue(encoding.decode(data))
+        }
+        x if x == DiskSymbolValueType::PasswordValue as u8 => {
+            Value::Password(Password::new(encoding.decode(data)))
+        }
+        x if x == DiskSymbolValueType::DirectionValue as u8 => Value::DirectionValue(fixed(1)?[0]),
+        x if x == DiskSymbolValueType::OSTypeValue as u8 => {
+            Value::OSTypeValue(OSType(fixed(4)?.try_into()?))
+        }
+        x if x == DiskSymbolValueType::PointValue as u8 => {
+            Value::PointValue(Point::new(fixed(4)?)?)
+        }
+        x if x == DiskSymbolValueType::RectValue as u8 => Value::RectValue(Rect::new(fixed(8)?)?),
+        x if x == DiskSymbolValueType::PatternValue as u8 => {
+            Value::PatternValue(Pattern::new(fixed(8)?)?)
+        }
+        x if x == DiskSymbolValueType::RGBValue as u8 => Value::RGBValue(RGBColor::new(fixed(6)?)?),
+        x if x == DiskSymbolValueType::FixedValue as u8 => {
+            Value::FixedValue(i32::from_be_bytes(fixed(4)?.try_into()?) as f64 / FIXED_ONE)
+        }
+        x if x == DiskSymbolValueType::SingleValue as u8 => {
+            Value::SingleValue(f32::from_bits(u32::from_be_bytes(fixed(4)?.try_into()?)))
+        }
+        x if x == DiskSymbolValueType::ObjSpecValue as u8 => {
+            Value::ObjSpec(ObjSpec::new(data, encoding)?)
+        }
+        x if x == DiskSymbolValueType::FileSpecValue as u8 => {
+            Value::FileSpec(FileSpec::new(data, context)?)
+        }
+        x if x == DiskSymbolValueType::AliasValue as u8 => {
+            Value::FileSpec(FileSpec::from_alias(data, encoding)?)
+        }
+        x if x == DiskSymbolValueType::EnumValue as u8 => {
+            Value::EnumValue(OSType(fixed(4)?.try_into()?))
+        }
+        x if x == DiskSymbolValueType::ListValue as u8 => {
+            Value::ListValue(unpack_list(data, context)?)
+        }
+        x if x == DiskSymbolValueType::RecordValue as u8 => {
+            Value::RecordValue(unpack_record(data, context)?)
+        }
+        x if x == DiskSymbolValueType::ExternalValue as u8 => {
+            unpack_external(external_id, data, context)?
+        }
+        _ => return Err(Error::from(ODBError::BadPackedValue)),
+    };
+
+    Ok(value)
+}
+
+fn unpack_external(external_id: u8, data: &[u8], context: &LoadContext) -> Result<Value> {
+    let external_type = ExternalType::from_id(external_id).ok_or(ODBError::BadPackedValue)?;
+
+    let value = match external_type {
+        ExternalType::Outline => Value::Outline(Outline::unpack(data, context)?),
+        ExternalType::WordProcessor => Value::WpText(WpText::unpack(data, context)?),
+        ExternalType::Table => {
+            let mut table = Table::new();
+            for (name, value) in unpack_record(data, context)? {
+                table.insert(&name, value)?;
+            }
+            Value::TableValue(Box::new(table))
+        }
+        ExternalType::Script => Value::Script(Script::unpack(data, context)?),
+        ExternalType::Menubar => Value::Menubar(unpack_menubar(data, context)?),
+        ExternalType::Picture => Value::Picture(Picture::unpack(data, context)?),
+        ExternalType::Head => Value::ExternalValue(external_type, data.to_vec()),
+    };
+
+    Ok(value)
+}
+
+fn external_type_of(value: &Value) -> Option<ExternalType> {
+    let external_type = match value {
+        Value::TableValue(_) => ExternalType::Table,
+        Value::Outline(_) => ExternalType::Outline,
+        Value::Script(_) => ExternalType::Script,
+        Value::WpText(_) => ExternalType::WordProcessor,
+        Value::Menubar(_) => ExternalType::Menubar,
+        Value::Picture(_) => ExternalType::Picture,
+        Value::ExternalValue(external_type, _) => *external_type,
+        _ => return None,
+    };
+
+    Some(external_type)
+}
+
+fn read_count(data: &[u8], index: &mut usize) -> Result<usize> {
+    let count = data
+        .get(*index..(*index + 4))
+        .ok_or(ODBError::BadPackedValue)?;
+    *index += 4;
+    Ok(u32::from_be_bytes(count.try_into()?) as usize)
+}
+
+fn check_end(data: &[u8], index: usize) -> Result<()> {
+    if index != data.len() {
+        return Err(Error::from(ODBError::BadPackedValue));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collation::fold_case;
+    use crate::text_encoding::*;
+    use db::Database;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use std::collections::BTreeMap;
+
+    const SAMPLE_ROOT_TABLE: db::DBAddress = 0x2a9;
+
+    fn context() -> LoadContext {
+        LoadContext::for_platform(Platform::Mac)
+    }
+
+    // Tables carry their creation times, so values are compared by their
+    // dumps, which describe tables by their items.
+    fn describe(value: &Value) -> String {
+        format!("{:?}", value.dump(Redaction::Reveal))
+    }
+
+    fn text(max_length: usize) -> impl Strategy<Value = String> {
+        vec(any::<u8>(), 0..max_length).prop_map(|bytes| TextEncoding::MacRoman.decode(&bytes))
+    }
+
+    fn name() -> impl Strategy<Value = String> {
+        "[a-zA-Z][a-zA-Z0-9 ]{0,11}"
+    }
+
+    fn outline() -> impl Strategy<Value = Outline> {
+        vec(name(), 0..4).prop_map(|lines| {
+            let mut outline = Outline::new();
+            outline.summits = lines.iter().map(|line| Headline::new(line)).collect();
+            outline
+        })
+    }
+
+    fn wptext() -> impl Strategy<Value = WpText> {
+        vec(vec((name(), any::<u16>(), any::<u16>()), 1..3), 1..3).prop_map(|paragraphs| {
+            let mut wptext = WpText::new();
+            wptext.paragraphs = paragraphs
+                .into_iter()
+                .map(|runs| Paragraph {
+                    runs: runs
+                        .into_iter()
+                        .map(|(text, font_size, font_style)| Run {
+                            text,
+                            font_name: String::from("Geneva"),
+                            font_size,
+                            font_style,
+                        })
+                        .collect(),
+                })
+                .collect();
+            wptext
+        })
+    }
+
+    fn menubar() -> impl Strategy<Value = Menubar> {
+        let item = prop_oneof![
+            Just(()).prop_map(|_| MenuItem::Separator(MenuSeparator::new())),
+            (
+                name(),
+                proptest::option::of("[A-Z]"),
+                proptest::option::of(outline())
+            )
+                .prop_map(|(title, key, source)| {
+                    let mut command = MenuCommand::new(&title);
+                    command.command_key = key.and_then(|key| key.chars().next());
+                    command.script = source.map(Script::new);
+                    MenuItem::Command(command)
+                }),
+        ];
+
+        vec((name(), vec(item, 0..3)), 0..3).prop_map(|menus| {
+            let mut menubar = Menubar::new();
+            menubar.menus = menus
+                .into_iter()
+                .map(|(title, items)| {
+                    let mut menu = Menu::new(&title);
+                    menu.items = items;
+                    menu
+                })
+                .collect();
+            menubar
+        })
+    }
+
+    fn scalar() -> BoxedStrategy<Value> {
+        prop_oneof![
+            Just(()).prop_map(|_| Value::Uninitialized),
+            Just(()).prop_map(|_| Value::NoValue),
+            any::<u8>().prop_map(|byte| Value::CharValue(TextEncoding::MacRoman.decode_byte(byte))),
+            any::<i16>().prop_map(Value::IntValue),
+            any::<i32>().prop_map(Value::LongValue),
+            vec(any::<u8>(), 0..16).prop_map(Value::BinaryValue),
+            any::<bool>().prop_map(Value::BooleanValue),
+            any::<u32>().prop_map(|seconds| Value::DateValue(date_from_mac_seconds(seconds))),
+            text(16).prop_map(Value::AddressValue),
+            name().prop_map(|name| Value::CodeValue(CodeTree::new(vec![Node::identifier(&name)]))),
+            any::<f64>().prop_map(Value::DoubleValue),
+            text(32).prop_map(Value::StringValue),
+            text(32).prop_map(|secret| Value::Password(Password::new(secret))),
+            any::<u8>().prop_map(Value::DirectionValue),
+            any::<[u8; 4]>().prop_map(|code| Value::OSTypeValue(OSType(code))),
+            any::<(i16, i16)>().prop_map(|(v, h)| Value::PointValue(Point { v, h })),
+            any::<(i16, i16, i16, i16)>().prop_map(|(top, left, bottom, right)| {
+                Value::RectValue(Rect {
+                    top,
+                    left,
+                    bottom,
+                    right,
+                })
+            }),
+            any::<[u8; 8]>().prop_map(|rows| Value::PatternValue(Pattern { rows })),
+            any::<(u16, u16, u16)>().prop_map(|(red, green, blue)| Value::RGBValue(RGBColor {
+                red,
+                green,
+                blue
+            })),
+            any::<i32>().prop_map(|n| Value::FixedValue(n as f64 / FIXED_ONE)),
+            any::<f32>().prop_map(Value::SingleValue),
+            any::<[u8; 4]>().prop_map(|code| Value::EnumValue(OSType(code))),
+            (any::<i16>(), any::<i32>(), text(63)).prop_map(|(ref_num, parent_dir_id, name)| {
+                Value::FileSpec(FileSpec::from_parts(ref_num, parent_dir_id, &name))
+            }),
+            (any::<[u8; 4]>(), name()).prop_map(|(class, name)| {
+                Value::ObjSpec(ObjSpec {
+                    class: OSType(class),
+                    key_form: KeyForm::Name,
+                    key_data: Descriptor::Text(name),
+                    container: None,
+                })
+            }),
+            outline().prop_map(Value::Outline),
+            outline().prop_map(|source| Value::Script(Script::new(source))),
+            wptext().prop_map(Value::WpText),
+            menubar().prop_map(Value::Menubar),
+            Just(()).prop_map(|_| Value::Picture(Picture::default())),
+            vec(any::<u8>(), 0..16)
+                .prop_map(|bytes| Value::ExternalValue(ExternalType::Head, bytes)),
+            // Nothing decodes the unused type number, so these stay raw.
+            vec(any::<u8>(), 0..16).prop_map(|bytes| Value::RawValue(17, bytes)),
+        ]
+        .boxed()
+    }
+
+    fn value() -> impl Strategy<Value = Value> {
+        scalar().prop_recursive(3, 24, 4, |inner| {
+            prop_oneof![
+                vec(inner.clone(), 0..4).prop_map(Value::ListValue),
+                vec((name(), inner.clone()), 0..4).prop_map(Value::RecordValue),
+                // Names in a table can't differ only by case.
+                vec((name(), inner), 0..4).prop_map(|items| {
+                    let items = items
+                        .into_iter()
+                        .map(|(name, value)| (fold_case(&name), (name, value)))
+                        .collect::<BTreeMap<_, _>>();
+                    let mut table = Table::new();
+                    for (name, value) in items.into_values() {
+                        table.insert(&name, value).unwrap();
+                    }
+                    Value::TableValue(Box::new(table))
+                }),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn every_value_round_trips(value in value()) {
+            let bytes = pack_value(&value, &context()).unwrap();
+            let unpacked = unpack_value(&bytes, &context()).unwrap();
+            prop_assert_eq!(describe(&unpacked), describe(&value));
+            prop_assert_eq!(pack_value(&unpacked, &context()).unwrap(), bytes);
+        }
+
+        #[test]
+        fn damaged_values_are_errors(value in value(), cut in 1usize..8) {
+            let bytes = pack_value(&value, &context()).unwrap();
+            prop_assert!(unpack_value(&bytes[..(bytes.len() - cut.min(bytes.len()))], &context()).is_err());
+        }
+    }
+
+    #[test]
+    fn passwords_are_packed_with_their_secret() {
+        let value = Value::ListValue(vec![Value::Password(Password::new(String::from(
+            "hunter2",
+        )))]);
+
+        let bytes = pack_value(&value, &context()).unwrap();
+        match unpack_value(&bytes, &context()).unwrap() {
+            Value::ListValue(items) => {
+                assert!(matches!(&items[0], Value::Password(p) if p.reveal() == "hunter2"));
+                assert!(!format!("{:?}", items[0].dump(Redaction::Redact)).contains("hunter2"));
+            }
+            value => panic!("unexpected value {:?}", value),
+        }
+    }
+
+    #[test]
+    fn packs_the_sample_root() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../Sample.root");
+        let mut db = Database::open_file(std::fs::File::open(path).unwrap(), true).unwrap();
+        let mut root = Table::load_system_table(&mut db, &context(), SAMPLE_ROOT_TABLE).unwrap();
+        root.load_all(&mut db, &context()).unwrap();
+
+        for (_, node) in root.iter() {
+            let bytes = pack_value(node.value(), &context()).unwrap();
+            let unpacked = unpack_value(&bytes, &context()).unwrap();
+            assert_eq!(describe(&unpacked), describe(node.value()));
+        }
+
+        let root = Value::TableValue(Box::new(root));
+        let bytes = pack_value(&root, &context()).unwrap();
+        assert_eq!(
+            describe(&unpack_value(&bytes, &context()).unwrap()),
+            describe(&root)
+        );
+
+        let error = pack_value(&Value::DiskValue(SAMPLE_ROOT_TABLE), &context()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "ODB error: The value hasn't been loaded from the database."
+        );
+    }
+}
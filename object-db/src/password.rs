@@ -0,0 +1,74 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::fmt;
+
+const REDACTED: &str = "<redacted>";
+
+// Whether a dump writes out secrets, like passwords, or leaves them out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redaction {
+    Redact,
+    Reveal,
+}
+
+// A password value. Its Debug and Display output never includes the secret,
+// so it can't end up in logs or dumps by accident; reveal() has to be called
+// to get at it.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Password(String);
+
+impl Password {
+    pub fn new(secret: String) -> Self {
+        Self(secret)
+    }
+
+    pub fn reveal(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Password({})", REDACTED)
+    }
+}
+
+impl fmt::Display for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatting_never_shows_the_secret() {
+        let password = Password::new(String::from("hunter2"));
+
+        assert_eq!(format!("{}", password), "<redacted>");
+        assert_eq!(format!("{:?}", password), "Password(<redacted>)");
+        assert_eq!(
+            format!("{:#?}", Some(&password)),
+            "Some(\n    Password(<redacted>),\n)"
+        );
+        assert_eq!(password.reveal(), "hunter2");
+    }
+}
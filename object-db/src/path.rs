@@ -0,0 +1,250 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::error::*;
+use crate::odb_error::*;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+// One step of a Frontier path: either the name of an item, or a one-based
+// index into the table's sorted items.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    Name(String),
+    Index(usize),
+}
+
+// Names that aren't identifiers are written in brackets and quotes.
+impl fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathSegment::Name(name) if is_identifier(name) => write!(f, "{}", name),
+            PathSegment::Name(name) => {
+                write!(
+                    f,
+                    "[\"{}\"]",
+                    name.replace('\\', "\\\\").replace('"', "\\\"")
+                )
+            }
+            PathSegment::Index(index) => write!(f, "[{}]", index),
+        }
+    }
+}
+
+// Joins segments back into a path, e.g. user.prefs.mail["smtp host"].
+pub fn format_path(segments: &[PathSegment]) -> String {
+    let mut path = String::new();
+
+    for segment in segments {
+        if let PathSegment::Name(name) = segment {
+            if is_identifier(name) && !path.is_empty() {
+                path.push('.');
+            }
+        }
+        path.push_str(&segment.to_string());
+    }
+
+    path
+}
+
+// Parses the subset of UserTalk addressing used to name database items:
+// identifiers separated by dots, ["quoted names"] in brackets and [n] indexes.
+pub fn parse_path(path: &str) -> Result<Vec<PathSegment>> {
+    let syntax_error = |position: usize| {
+        Error::from(ODBError::PathSyntax {
+            path: String::from(path),
+            position,
+        })
+    };
+
+    let mut segments = vec![];
+    let mut chars = path.char_indices().peekable();
+
+    loop {
+        skip_whitespace(&mut chars);
+
+        match chars.peek().copied() {
+            None => return Err(syntax_error(path.len())),
+            Some((_, '[')) => {
+                chars.next();
+                skip_whitespace(&mut chars);
+                segments.push(parse_bracket(&mut chars, path.len()).map_err(syntax_error)?);
+                skip_whitespace(&mut chars);
+
+                match chars.next() {
+                    Some((_, ']')) => {}
+                    Some((position, _)) => return Err(syntax_error(position)),
+                    None => return Err(syntax_error(path.len())),
+                }
+            }
+            Some((_, c)) if is_identifier_start(c) => {
+                let mut name = String::new();
+                while let Some((_, c)) = chars.peek().copied() {
+                    if !is_identifier_char(c) {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+
+                segments.push(PathSegment::Name(name));
+            }
+            Some((position, _)) => return Err(syntax_error(position)),
+        }
+
+        skip_whitespace(&mut chars);
+
+        match chars.peek().copied() {
+            None => return Ok(segments),
+            Some((_, '.')) => {
+                chars.next();
+                skip_whitespace(&mut chars);
+                if chars.peek().is_none() {
+                    return Err(syntax_error(path.len()));
+                }
+            }
+            Some((_, '[')) => {}
+            Some((position, _)) => return Err(syntax_error(position)),
+        }
+    }
+}
+
+fn parse_bracket(
+    chars: &mut Peekable<CharIndices>,
+    end: usize,
+) -> std::result::Result<PathSegment, usize> {
+    match chars.peek().copied() {
+        Some((position, c)) if c.is_ascii_digit() => {
+            let mut digits = String::new();
+            while let Some((_, c)) = chars.peek().copied() {
+                if !c.is_ascii_digit() {
+                    break;
+                }
+                digits.push(c);
+                chars.next();
+            }
+
+            match digits.parse::<usize>() {
+                Ok(index) if index > 0 => Ok(PathSegment::Index(index)),
+                _ => Err(position),
+            }
+        }
+        Some((position, quote)) => {
+            let close_quote = match quote {
+                '"' => '"',
+                '\u{201C}' => '\u{201D}',
+                _ => return Err(position),
+            };
+            chars.next();
+
+            let mut name = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) => name.push(c),
+                        None => return Err(end),
+                    },
+                    Some((_, c)) if c == close_quote => return Ok(PathSegment::Name(name)),
+                    Some((_, c)) => name.push(c),
+                    None => return Err(end),
+                }
+            }
+        }
+        None => Err(end),
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<CharIndices>) {
+    while let Some((_, c)) = chars.peek() {
+        if !c.is_whitespace() {
+            break;
+        }
+        chars.next();
+    }
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if is_identifier_start(c) => chars.all(is_identifier_char),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> PathSegment {
+        PathSegment::Name(String::from(name))
+    }
+
+    #[test]
+    fn parses_frontier_paths() {
+        assert_eq!(
+            parse_path("user.prefs.mail[\"smtp host\"]").unwrap(),
+            vec![name("user"), name("prefs"), name("mail"), name("smtp host")]
+        );
+        assert_eq!(
+            parse_path("system . verbs.[3][\"say \\\"hi\\\"\"]").unwrap(),
+            vec![
+                name("system"),
+                name("verbs"),
+                PathSegment::Index(3),
+                name("say \"hi\"")
+            ]
+        );
+        assert_eq!(
+            parse_path("[\u{201C}Read Me\u{201D}]").unwrap(),
+            vec![name("Read Me")]
+        );
+    }
+
+    #[test]
+    fn reports_where_parsing_failed() {
+        for (path, expected) in &[
+            ("", 0),
+            ("user.", 5),
+            ("user prefs", 5),
+            ("a[0]", 2),
+            ("a[\"b]", 5),
+        ] {
+            match parse_path(path) {
+                Err(Error::ODB(ODBError::PathSyntax { position, .. })) => {
+                    assert_eq!(position, *expected, "{}", path)
+                }
+                result => panic!("{}: {:?}", path, result),
+            }
+        }
+    }
+
+    #[test]
+    fn formats_paths() {
+        let segments = parse_path("user.prefs.mail[\"smtp host\"][2]").unwrap();
+        assert_eq!(format_path(&segments), "user.prefs.mail[\"smtp host\"][2]");
+    }
+}
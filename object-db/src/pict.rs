@@ -0,0 +1,1618 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::error::*;
+use crate::odb_error::*;
+use crate::quickdraw::*;
+use crate::text_encoding::*;
+use crate::variable::*;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+
+const PICT_HEADER_SIZE: usize = 10;
+const VERSION_2_HEADER_SIZE: usize = 24;
+const END_OF_PICTURE: u16 = 0x00ff;
+const DEFAULT_TEXT_SIZE: u16 = 12;
+const DEVICE_COLOR_TABLE: u16 = 0x8000;
+// A frame can be up to 65535 pixels on a side, which would take 12 GB to
+// render. Pictures are rendered at their own size, so this is 48 MB.
+const MAX_CANVAS_PIXELS: usize = 4096 * 4096;
+
+const BLACK: RGBColor = RGBColor {
+    red: 0,
+    green: 0,
+    blue: 0,
+};
+const WHITE: RGBColor = RGBColor {
+    red: 0xffff,
+    green: 0xffff,
+    blue: 0xffff,
+};
+const SOLID: Pattern = Pattern { rows: [0xff; 8] };
+
+// A QuickDraw picture. The opcodes are read into a list of elements that can
+// be drawn as SVG or PNG. The picture itself is kept as it was read, so it's
+// saved unchanged.
+//
+// Packed, it's the picture as QuickDraw stores it: its size, its frame and
+// its opcodes, one byte each in version 1 pictures and two bytes each, with
+// their data word aligned, in version 2 pictures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Picture {
+    pub frame: Rect,
+    pub version: u8,
+    elements: Vec<Element>,
+    warnings: Vec<PictWarning>,
+    data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    Shape {
+        shape: Shape,
+        paint: Paint,
+        color: RGBColor,
+    },
+    Text(Text),
+    Image(Image),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Line(Point, Point),
+    Rect(Rect),
+    // The corner point holds the width and height of the corner ovals.
+    RoundRect(Rect, Point),
+    Oval(Rect),
+    Polygon(Vec<Point>),
+}
+
+// Shapes are either framed with a pen of the given size, or filled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Paint {
+    Frame(Point),
+    Fill,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Text {
+    pub location: Point,
+    pub text: String,
+    pub font_name: String,
+    pub font_size: u16,
+    pub font_style: u8,
+    pub color: RGBColor,
+}
+
+// A bitmap or pixmap, stretched to fill its destination.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub destination: Rect,
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<RGBColor>,
+}
+
+// Something in the picture that wasn't drawn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PictWarning {
+    // The opcode was read but isn't supported, so it was skipped.
+    UnsupportedOpcode { offset: usize, opcode: u16 },
+    // The picture ended before its end opcode. Nothing after the offset was
+    // read.
+    Truncated { offset: usize },
+    // PNG renderings don't draw text.
+    TextNotDrawn { count: usize },
+}
+
+// The output of a renderer, and everything in the picture it couldn't draw.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendering<T> {
+    pub output: T,
+    pub warnings: Vec<PictWarning>,
+}
+
+impl Picture {
+    pub fn elements(&self) -> &[Element] {
+        &self.elements
+    }
+
+    pub fn warnings(&self) -> &[PictWarning] {
+        &self.warnings
+    }
+
+    pub fn unpack(bytes: &[u8], context: &LoadContext) -> Result<Self> {
+        let header = bytes
+            .get(0..PICT_HEADER_SIZE)
+            .ok_or(ODBError::BadPackedValue)?;
+        let frame = Rect::new(&header[2..10])?;
+
+        let (version, start) = match bytes.get(PICT_HEADER_SIZE..(PICT_HEADER_SIZE + 4)) {
+            Some([0x11, 0x01, ..]) => (1, PICT_HEADER_SIZE + 2),
+            Some([0x00, 0x11, 0x02, 0xff]) => (2, PICT_HEADER_SIZE + 4),
+            _ => return Err(Error::from(ODBError::BadPackedValue)),
+        };
+
+        let mut parser = Parser::new(bytes, start, version, context.text_encoding);
+        parser.run();
+
+        Ok(Self {
+            frame,
+            version,
+            elements: parser.elements,
+            warnings: parser.warnings,
+            data: bytes.to_vec(),
+        })
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    pub fn to_svg(&self) -> Rendering<String> {
+        let frame = self.frame;
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\">\n",
+            width(frame),
+            height(frame),
+            frame.left,
+            frame.top,
+            width(frame),
+            height(frame)
+        );
+
+        for element in self.elements.iter() {
+            match element {
+                Element::Shape {
+                    shape,
+                    paint,
+                    color,
+                } => svg.push_str(&svg_shape(shape, *paint, *color)),
+                Element::Text(text) => svg.push_str(&svg_text(text)),
+                Element::Image(image) => {
+                    if let Ok(png) =
+                        encode_png(image.width, image.height, &rgb_bytes(&image.pixels))
+                    {
+                        let destination = image.destination;
+                        svg.push_str(&format!(
+                            "<image x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" preserveAspectRatio=\"none\" href=\"data:image/png;base64,{}\"/>\n",
+                            destination.left,
+                            destination.top,
+                            width(destination),
+                            height(destination),
+                            base64(&png)
+                        ));
+                    }
+                }
+            }
+        }
+
+        svg.push_str("</svg>\n");
+
+        Rendering {
+            output: svg,
+            warnings: self.warnings.clone(),
+        }
+    }
+
+    pub fn to_png(&self) -> Result<Rendering<Vec<u8>>> {
+        let mut canvas = Canvas::new(self.frame)?;
+        let mut text_count = 0;
+
+        for element in self.elements.iter() {
+            match element {
+                Element::Shape {
+                    shape,
+                    paint,
+                    color,
+                } => canvas.draw_shape(shape, *paint, *color),
+                Element::Text(_) => text_count += 1,
+                Element::Image(image) => canvas.draw_image(image),
+            }
+        }
+
+        let mut warnings = self.warnings.clone();
+        if text_count > 0 {
+            warnings.push(PictWarning::TextNotDrawn { count: text_count });
+        }
+
+        Ok(Rendering {
+            output: encode_png(canvas.width, canvas.height, &canvas.pixels)?,
+            warnings,
+        })
+    }
+}
+
+// An empty version 2 picture.
+impl Default for Picture {
+    fn default() -> Self {
+        let mut data = vec![0; PICT_HEADER_SIZE];
+        data.extend([0x00, 0x11, 0x02, 0xff, 0x0c, 0x00]);
+        data.extend([0; VERSION_2_HEADER_SIZE]);
+        data.extend(END_OF_PICTURE.to_be_bytes());
+        let size = data.len() as u16;
+        data[0..2].copy_from_slice(&size.to_be_bytes());
+
+        Self {
+            frame: Rect {
+                top: 0,
+                left: 0,
+                bottom: 0,
+                right: 0,
+            },
+            version: 2,
+            elements: vec![],
+            warnings: vec![],
+            data,
+        }
+    }
+}
+
+impl LoadFromBytes for Picture {
+    fn load_from_bytes(bytes: &[u8], context: &LoadContext) -> Result<Self> {
+        Self::unpack(bytes, context)
+    }
+}
+
+impl SaveToBytes for Picture {
+    fn save_to_bytes(&self, _context: &LoadContext) -> Result<Vec<u8>> {
+        Ok(self.pack())
+    }
+}
+
+impl fmt::Display for PictWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PictWarning::UnsupportedOpcode { offset, opcode } => write!(
+                f,
+                "Opcode 0x{:04X} at offset {} isn't supported and was skipped.",
+                opcode, offset
+            ),
+            PictWarning::Truncated { offset } => {
+                write!(f, "The picture couldn't be read past offset {}.", offset)
+            }
+            PictWarning::TextNotDrawn { count } => {
+                write!(f, "{} text items weren't drawn.", count)
+            }
+        }
+    }
+}
+
+// Reads big-endian values, returning None at the end of the data.
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position.checked_add(count)?)?;
+        self.position += count;
+        Some(bytes)
+    }
+
+    fn skip(&mut self, count: usize) -> Option<()> {
+        self.bytes(count).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn i8(&mut self) -> Option<i8> {
+        Some(self.u8()? as i8)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn point(&mut self) -> Option<Point> {
+        Point::new(self.bytes(4)?).ok()
+    }
+
+    fn rect(&mut self) -> Option<Rect> {
+        Rect::new(self.bytes(8)?).ok()
+    }
+
+    fn rgb_color(&mut self) -> Option<RGBColor> {
+        RGBColor::new(self.bytes(6)?).ok()
+    }
+
+    fn pattern(&mut self) -> Option<Pattern> {
+        Pattern::new(self.bytes(8)?).ok()
+    }
+
+    // Skips a region, returning its bounds if it's a plain rectangle.
+    fn region(&mut self) -> Option<Option<Rect>> {
+        let size = self.u16()? as usize;
+        let data = self.bytes(size.checked_sub(2)?)?;
+        if size == 10 {
+            Some(Rect::new(data).ok())
+        } else {
+            Some(None)
+        }
+    }
+}
+
+// How the rows of a bitmap or pixmap are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RowFormat {
+    Unpacked,
+    // 32 bit pixels without their unused byte.
+    Rgb,
+    // PackBits compressed, in units of the given number of bytes. 32 bit
+    // pixels are packed one component at a time.
+    PackBits(usize),
+}
+
+// The drawing state as the opcodes are read.
+struct Parser<'a> {
+    reader: Reader<'a>,
+    version: u8,
+    encoding: TextEncoding,
+    elements: Vec<Element>,
+    warnings: Vec<PictWarning>,
+    pen_location: Point,
+    pen_size: Point,
+    pen_pattern: Pattern,
+    fill_pattern: Pattern,
+    foreground: RGBColor,
+    background: RGBColor,
+    oval_size: Point,
+    text_location: Point,
+    font_number: u16,
+    font_names: HashMap<u16, String>,
+    text_size: u16,
+    text_face: u8,
+    last_rect: Rect,
+    last_round_rect: Rect,
+    last_oval: Rect,
+    last_polygon: Vec<Point>,
+    last_region: Option<Rect>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8], position: usize, version: u8, encoding: TextEncoding) -> Self {
+        let origin = Point { v: 0, h: 0 };
+        let empty = Rect {
+            top: 0,
+            left: 0,
+            bottom: 0,
+            right: 0,
+        };
+
+        Self {
+            reader: Reader { data, position },
+            version,
+            encoding,
+            elements: vec![],
+            warnings: vec![],
+            pen_location: origin,
+            pen_size: Point { v: 1, h: 1 },
+            pen_pattern: SOLID,
+            fill_pattern: SOLID,
+            foreground: BLACK,
+            background: WHITE,
+            oval_size: origin,
+            text_location: origin,
+            font_number: 0,
+            font_names: HashMap::new(),
+            text_size: 0,
+            text_face: 0,
+            last_rect: empty,
+            last_round_rect: empty,
+            last_oval: empty,
+            last_polygon: vec![],
+            last_region: None,
+        }
+    }
+
+    fn run(&mut self) {
+        loop {
+            let offset = self.reader.position;
+            let opcode = match self.version {
+                1 => self.reader.u8().map(u16::from),
+                _ => self.reader.u16(),
+            };
+
+            match opcode {
+                Some(END_OF_PICTURE) => break,
+                Some(opcode) => match self.read_opcode(opcode) {
+                    Some(true) => {}
+                    Some(false) => self
+                        .warnings
+                        .push(PictWarning::UnsupportedOpcode { offset, opcode }),
+                    None => {
+                        self.warnings.push(PictWarning::Truncated { offset });
+                        break;
+                    }
+                },
+                None => {
+                    self.warnings.push(PictWarning::Truncated { offset });
+                    break;
+                }
+            }
+
+            // Version 2 opcodes are word aligned.
+            if self.version == 2 && self.reader.position % 2 == 1 {
+                self.reader.position += 1;
+            }
+        }
+    }
+
+    // Reads the opcode's data, returning whether the opcode is supported, or
+    // None if the data runs past the end of the picture.
+    fn read_opcode(&mut self, opcode: u16) -> Option<bool> {
+        let reader = &mut self.reader;
+
+        match opcode {
+            0x0000 => {}
+            0x0001 => {
+                reader.region()?;
+            }
+            0x0002 => {
+                reader.pattern()?;
+            }
+            0x0003 => self.font_number = reader.u16()?,
+            0x0004 => self.text_face = reader.u8()?,
+            0x0005 | 0x0008 | 0x0015 | 0x0016 => reader.skip(2)?,
+            0x0006 => reader.skip(4)?,
+            0x0007 => self.pen_size = reader.point()?,
+            0x0009 => self.pen_pattern = reader.pattern()?,
+            0x000a => self.fill_pattern = reader.pattern()?,
+            0x000b => self.oval_size = reader.point()?,
+            0x000c => {
+                reader.skip(4)?;
+                return Some(false);
+            }
+            0x000d => self.text_size = reader.u16()?,
+            0x000e => self.foreground = old_style_color(reader.u32()?),
+            0x000f => self.background = old_style_color(reader.u32()?),
+            0x0010 => reader.skip(8)?,
+            0x0011 => reader.skip(self.version as usize)?,
+            0x0012..=0x0014 => {
+                let pattern = self.read_pixel_pattern()?;
+                match opcode {
+                    0x0013 => self.pen_pattern = pattern,
+                    0x0014 => self.fill_pattern = pattern,
+                    _ => {}
+                }
+            }
+            0x0017..=0x0019 | 0x001c | 0x001e => {}
+            0x001a => self.foreground = reader.rgb_color()?,
+            0x001b => self.background = reader.rgb_color()?,
+            0x001d | 0x001f => reader.skip(6)?,
+            0x0020 => {
+                let from = reader.point()?;
+                let to = reader.point()?;
+                self.line(from, to);
+            }
+            0x0021 => {
+                let to = reader.point()?;
+                self.line(self.pen_location, to);
+            }
+            0x0022 => {
+                let from = reader.point()?;
+                let to = offset_point(from, reader.i8()?, reader.i8()?);
+                self.line(from, to);
+            }
+            0x0023 => {
+                let to = offset_point(self.pen_location, reader.i8()?, reader.i8()?);
+                self.line(self.pen_location, to);
+            }
+            0x0028 => {
+                self.text_location = reader.point()?;
+                self.text()?;
+            }
+            0x0029 => {
+                self.text_location.h = self.text_location.h.wrapping_add(reader.u8()? as i16);
+                self.text()?;
+            }
+            0x002a => {
+                self.text_location.v = self.text_location.v.wrapping_add(reader.u8()? as i16);
+                self.text()?;
+            }
+            0x002b => {
+                self.text_location.h = self.text_location.h.wrapping_add(reader.u8()? as i16);
+                self.text_location.v = self.text_location.v.wrapping_add(reader.u8()? as i16);
+                self.text()?;
+            }
+            0x002c => {
+                let length = reader.u16()? as usize;
+                let data = reader.bytes(length)?;
+                if length > 3 {
+                    let id = u16::from_be_bytes([data[0], data[1]]);
+                    let name_length = (data[2] as usize).min(length - 3);
+                    let name = self.encoding.decode(&data[3..(3 + name_length)]);
+                    self.font_names.insert(id, name);
+                }
+            }
+            0x0030..=0x005f => {
+                let is_same = opcode & 0x08 != 0;
+                let last = match opcode & 0xf0 {
+                    0x0030 => &mut self.last_rect,
+                    0x0040 => &mut self.last_round_rect,
+                    _ => &mut self.last_oval,
+                };
+                if !is_same {
+                    *last = reader.rect()?;
+                }
+                let rect = *last;
+                let shape = match opcode & 0xf0 {
+                    0x0030 => Shape::Rect(rect),
+                    0x0040 => Shape::RoundRect(rect, self.oval_size),
+                    _ => Shape::Oval(rect),
+                };
+                return Some(self.draw(shape, opcode));
+            }
+            0x0060..=0x006f => {
+                // Arcs aren't drawn.
+                if opcode & 0x08 == 0 {
+                    reader.skip(8)?;
+                }
+                reader.skip(4)?;
+                return Some(false);
+            }
+            0x0070..=0x007f => {
+                if opcode & 0x08 == 0 {
+                    let size = reader.u16()? as usize;
+                    reader.rect()?;
+                    let count = size.checked_sub(10)? / 4;
+                    let mut points = vec![];
+                    for _ in 0..count {
+                        points.push(reader.point()?);
+                    }
+                    self.last_polygon = points;
+                }
+                let shape = Shape::Polygon(self.last_polygon.clone());
+                return Some(self.draw(shape, opcode));
+            }
+            0x0080..=0x008f => {
+                // Only rectangular regions are drawn.
+                if opcode & 0x08 == 0 {
+                    self.last_region = reader.region()?;
+                }
+                return match self.last_region {
+                    Some(rect) => Some(self.draw(Shape::Rect(rect), opcode)),
+                    None => Some(false),
+                };
+            }
+            0x0090 | 0x0091 | 0x0098 | 0x0099 | 0x009a | 0x009b => return self.read_pixels(opcode),
+            0x00a0 => reader.skip(2)?,
+            0x00a1 => {
+                reader.skip(2)?;
+                let size = reader.u16()? as usize;
+                reader.skip(size)?;
+            }
+            0x0c00 => reader.skip(VERSION_2_HEADER_SIZE)?,
+            _ => {
+                self.skip_reserved(opcode)?;
+                return Some(false);
+            }
+        }
+
+        Some(true)
+    }
+
+    // Skips the data of the opcodes Apple reserved, whose lengths are fixed
+    // or stored ahead of the data.
+    fn skip_reserved(&mut self, opcode: u16) -> Option<()> {
+        let reader = &mut self.reader;
+
+        match opcode {
+            0x0024..=0x0027
+            | 0x002d..=0x002f
+            | 0x0092..=0x0097
+            | 0x009c..=0x009f
+            | 0x00a2..=0x00af => {
+                let length = reader.u16()? as usize;
+                reader.skip(length)
+            }
+            0x00b0..=0x00cf | 0x8000..=0x80ff => Some(()),
+            0x00d0..=0x00fe | 0x8100..=0xffff => {
+                let length = reader.u32()? as usize;
+                reader.skip(length)
+            }
+            0x0100..=0x7fff => reader.skip(((opcode >> 8) * 2) as usize),
+            _ => None,
+        }
+    }
+
+    fn line(&mut self, from: Point, to: Point) {
+        self.elements.push(Element::Shape {
+            shape: Shape::Line(from, to),
+            paint: Paint::Frame(self.pen_size),
+            color: self.pattern_color(self.pen_pattern),
+        });
+        self.pen_location = to;
+    }
+
+    fn text(&mut self) -> Option<()> {
+        let count = self.reader.u8()? as usize;
+        let text = self.encoding.decode(self.reader.bytes(count)?);
+
+        self.elements.push(Element::Text(Text {
+            location: self.text_location,
+            text,
+            font_name: self.font_name(),
+            font_size: if self.text_size == 0 {
+                DEFAULT_TEXT_SIZE
+            } else {
+                self.text_size
+            },
+            font_style: self.text_face,
+            color: self.foreground,
+        }));
+
+        Some(())
+    }
+
+    fn font_name(&self) -> String {
+        if let Some(name) = self.font_names.get(&self.font_number) {
+            return name.clone();
+        }
+
+        let name = match self.font_number {
+            0 => "Chicago",
+            2 => "New York",
+            4 => "Monaco",
+            20 => "Times",
+            21 => "Helvetica",
+            22 => "Courier",
+            _ => "Geneva",
+        };
+        String::from(name)
+    }
+
+    // Adds a shape drawn with the verb in the low bits of the opcode: frame,
+    // paint, erase, invert or fill. Inverting isn't supported.
+    fn draw(&mut self, shape: Shape, opcode: u16) -> bool {
+        let (paint, color) = match opcode & 0x07 {
+            0 => (
+                Paint::Frame(self.pen_size),
+                self.pattern_color(self.pen_pattern),
+            ),
+            1 => (Paint::Fill, self.pattern_color(self.pen_pattern)),
+            2 => (Paint::Fill, self.background),
+            4 => (Paint::Fill, self.pattern_color(self.fill_pattern)),
+            _ => return false,
+        };
+
+        self.elements.push(Element::Shape {
+            shape,
+            paint,
+            color,
+        });
+        true
+    }
+
+    // Patterns are drawn as a solid blend of the foreground and background
+    // colors.
+    fn pattern_color(&self, pattern: Pattern) -> RGBColor {
+        let ones = pattern.rows.iter().map(|row| row.count_ones()).sum::<u32>();
+        let blend = |foreground: u16, background: u16| {
+            ((foreground as u32 * ones + background as u32 * (64 - ones)) / 64) as u16
+        };
+
+        RGBColor {
+            red: blend(self.foreground.red, self.background.red),
+            green: blend(self.foreground.green, self.background.green),
+            blue: blend(self.foreground.blue, self.background.blue),
+        }
+    }
+
+    // Reads a pixel pattern, returning its one bit equivalent.
+    fn read_pixel_pattern(&mut self) -> Option<Pattern> {
+        let reader = &mut self.reader;
+        let pattern_type = reader.u16()?;
+        let pattern = reader.pattern()?;
+
+        match pattern_type {
+            1 => {
+                let row_bytes = (reader.u16()? & 0x3fff) as usize;
+                let bounds = reader.rect()?;
+                let (pack_type, pixel_size, _) = read_pixmap_fields(reader)?;
+                read_color_table(reader)?;
+                let format = row_format(row_bytes, pack_type, pixel_size, true);
+                read_rows(reader, row_bytes, &bounds, format)?;
+            }
+            2 => {
+                reader.rgb_color()?;
+            }
+            _ => {}
+        }
+
+        Some(pattern)
+    }
+
+    // Reads a bitmap or pixmap opcode.
+    fn read_pixels(&mut self, opcode: u16) -> Option<bool> {
+        let reader = &mut self.reader;
+        let is_direct = opcode == 0x009a || opcode == 0x009b;
+        if is_direct {
+            reader.skip(4)?;
+        }
+
+        let row_bytes = reader.u16()?;
+        let is_pixmap = is_direct || row_bytes & 0x8000 != 0;
+        let row_bytes = (row_bytes & 0x3fff) as usize;
+        let bounds = reader.rect()?;
+
+        let (mut pack_type, mut pixel_size, mut component_count) = (0, 1, 1);
+        let mut colors = None;
+        if is_pixmap {
+            let fields = read_pixmap_fields(reader)?;
+            pack_type = fields.0;
+            pixel_size = fields.1;
+            component_count = fields.2;
+            if !is_direct {
+                colors = Some(read_color_table(reader)?);
+            }
+        }
+
+        let source = reader.rect()?;
+        let destination = reader.rect()?;
+        reader.skip(2)?;
+        if opcode & 0x01 != 0 {
+            reader.region()?;
+        }
+
+        let is_packed = opcode != 0x0090 && opcode != 0x0091;
+        let format = row_format(row_bytes, pack_type, pixel_size, is_packed);
+        let rows = read_rows(reader, row_bytes, &bounds, format)?;
+
+        let width = width(bounds) as usize;
+        let pixels = match decode_pixels(
+            &rows,
+            width,
+            pixel_size,
+            component_count,
+            format,
+            colors.as_deref(),
+        ) {
+            Some(pixels) => pixels,
+            None => return Some(false),
+        };
+
+        if let Some(image) = crop(&pixels, width, &bounds, &source, destination) {
+            self.elements.push(Element::Image(image));
+        }
+        Some(true)
+    }
+}
+
+// Reads the pixmap fields after the bounds, returning the pack type, pixel
+// size and component count.
+fn read_pixmap_fields(reader: &mut Reader) -> Option<(u16, u16, u16)> {
+    reader.skip(2)?;
+    let pack_type = reader.u16()?;
+    reader.skip(12)?;
+    reader.skip(2)?;
+    let pixel_size = reader.u16()?;
+    let component_count = reader.u16()?;
+    reader.skip(14)?;
+
+    Some((pack_type, pixel_size, component_count))
+}
+
+fn read_color_table(reader: &mut Reader) -> Option<Vec<RGBColor>> {
+    reader.skip(4)?;
+    let flags = reader.u16()?;
+    let size = reader.u16()? as usize;
+
+    let mut colors = vec![BLACK; 256];
+    for i in 0..=size {
+        let value = reader.u16()? as usize;
+        let color = reader.rgb_color()?;
+        let index = if flags & DEVICE_COLOR_TABLE != 0 {
+            i
+        } else {
+            value
+        };
+        if let Some(entry) = colors.get_mut(index) {
+            *entry = color;
+        }
+    }
+
+    Some(colors)
+}
+
+fn row_format(row_bytes: usize, pack_type: u16, pixel_size: u16, is_packed: bool) -> RowFormat {
+    // Rows narrower than 8 bytes are never packed.
+    if !is_packed || row_bytes < 8 || pack_type == 1 {
+        return RowFormat::Unpacked;
+    }
+
+    match (pack_type, pixel_size) {
+        (2, 32) => RowFormat::Rgb,
+        (0, 16) | (3, _) => RowFormat::PackBits(2),
+        _ => RowFormat::PackBits(1),
+    }
+}
+
+fn read_rows(
+    reader: &mut Reader,
+    row_bytes: usize,
+    bounds: &Rect,
+    format: RowFormat,
+) -> Option<Vec<Vec<u8>>> {
+    let mut rows = vec![];
+
+    for _ in 0..height(*bounds) {
+        let row = match format {
+            RowFormat::Unpacked => reader.bytes(row_bytes)?.to_vec(),
+            RowFormat::Rgb => reader.bytes(width(*bounds) as usize * 3)?.to_vec(),
+            RowFormat::PackBits(unit) => {
+                let count = if row_bytes > 250 {
+                    reader.u16()? as usize
+                } else {
+                    reader.u8()? as usize
+                };
+                unpack_bits(reader.bytes(count)?, unit)?
+            }
+        };
+        rows.push(row);
+    }
+
+    Some(rows)
+}
+
+// Expands PackBits data. Each flag byte is followed either by a run of
+// literal units or by one unit to repeat.
+fn unpack_bits(data: &[u8], unit: usize) -> Option<Vec<u8>> {
+    let mut unpacked = vec![];
+    let mut index = 0;
+
+    while index < data.len() {
+        let flag = data[index] as i8;
+        index += 1;
+
+        if flag >= 0 {
+            let length = (flag as usize + 1) * unit;
+            unpacked.extend_from_slice(data.get(index..(index + length))?);
+            index += length;
+        } else if flag != -128 {
+            let item = data.get(index..(index + unit))?;
+            for _ in 0..(1 - flag as isize) {
+                unpacked.extend_from_slice(item);
+            }
+            index += unit;
+        }
+    }
+
+    Some(unpacked)
+}
+
+// Converts rows of pixels to colors. Returns None for pixel sizes that
+// aren't supported.
+fn decode_pixels(
+    rows: &[Vec<u8>],
+    width: usize,
+    pixel_size: u16,
+    component_count: u16,
+    format: RowFormat,
+    colors: Option<&[RGBColor]>,
+) -> Option<Vec<RGBColor>> {
+    let mut pixels = vec![];
+    let component = |byte: u8| byte as u16 * 0x0101;
+
+    for row in rows {
+        for x in 0..width {
+            let pixel = match pixel_size {
+                1 | 2 | 4 | 8 => {
+                    let size = pixel_size as usize;
+                    let bit = x * size;
+                    let byte = *row.get(bit / 8)?;
+                    let index = (byte >> (8 - size - bit % 8)) & ((1 << size) - 1) as u8;
+                    match colors {
+                        Some(colors) => colors[index as usize],
+                        None if index == 0 => WHITE,
+                        None => BLACK,
+                    }
+                }
+                16 => {
+                    let word = u16::from_be_bytes(row.get((x * 2)..(x * 2 + 2))?.try_into().ok()?);
+                    let scale = |value: u16| (value & 0x1f) * 0xffff / 0x1f;
+                    RGBColor {
+                        red: scale(word >> 10),
+                        green: scale(word >> 5),
+                        blue: scale(word),
+                    }
+                }
+                32 => {
+                    let (red, green, blue) = match format {
+                        RowFormat::PackBits(_) => {
+                            let start = (component_count as usize).saturating_sub(3) * width;
+                            (start + x, start + width + x, start + 2 * width + x)
+                        }
+                        RowFormat::Rgb => (x * 3, x * 3 + 1, x * 3 + 2),
+                        RowFormat::Unpacked => (x * 4 + 1, x * 4 + 2, x * 4 + 3),
+                    };
+                    RGBColor {
+                        red: component(*row.get(red)?),
+                        green: component(*row.get(green)?),
+                        blue: component(*row.get(blue)?),
+                    }
+                }
+                _ => return None,
+            };
+            pixels.push(pixel);
+        }
+    }
+
+    Some(pixels)
+}
+
+// Cuts the source rect out of the pixels, which cover the bounds.
+fn crop(
+    pixels: &[RGBColor],
+    row_width: usize,
+    bounds: &Rect,
+    source: &Rect,
+    destination: Rect,
+) -> Option<Image> {
+    // The difference between two coordinates doesn't always fit in an i16.
+    let left = (source.left.max(bounds.left) as i32 - bounds.left as i32) as usize;
+    let top = (source.top.max(bounds.top) as i32 - bounds.top as i32) as usize;
+    let right = (source.right.min(bounds.right) as i32 - bounds.left as i32).max(0) as usize;
+    let bottom = (source.bottom.min(bounds.bottom) as i32 - bounds.top as i32).max(0) as usize;
+    if right <= left || bottom <= top || width(destination) == 0 || height(destination) == 0 {
+        return None;
+    }
+
+    let mut cropped = vec![];
+    for y in top..bottom {
+        cropped.extend_from_slice(&pixels[(y * row_width + left)..(y * row_width + right)]);
+    }
+
+    Some(Image {
+        destination,
+        width: right - left,
+        height: bottom - top,
+        pixels: cropped,
+    })
+}
+
+// The eight colors of the original QuickDraw.
+fn old_style_color(value: u32) -> RGBColor {
+    let (red, green, blue) = match value {
+        30 => (0xffff, 0xffff, 0xffff),
+        69 => (0xffff, 0xffff, 0),
+        137 => (0xffff, 0, 0xffff),
+        205 => (0xffff, 0, 0),
+        273 => (0, 0xffff, 0xffff),
+        341 => (0, 0xffff, 0),
+        409 => (0, 0, 0xffff),
+        _ => (0, 0, 0),
+    };
+
+    RGBColor { red, green, blue }
+}
+
+fn offset_point(point: Point, dh: i8, dv: i8) -> Point {
+    Point {
+        v: point.v.wrapping_add(dv as i16),
+        h: point.h.wrapping_add(dh as i16),
+    }
+}
+
+fn width(rect: Rect) -> i32 {
+    (rect.right as i32 - rect.left as i32).max(0)
+}
+
+fn height(rect: Rect) -> i32 {
+    (rect.bottom as i32 - rect.top as i32).max(0)
+}
+
+fn hex_color(color: RGBColor) -> String {
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        color.red >> 8,
+        color.green >> 8,
+        color.blue >> 8
+    )
+}
+
+// QuickDraw pens hang below and to the right of the line they draw, so SVG
+// strokes are moved by half the pen size.
+fn svg_shape(shape: &Shape, paint: Paint, color: RGBColor) -> String {
+    let color = hex_color(color);
+    let (pen_h, pen_v) = match paint {
+        Paint::Frame(pen) => (pen.h as f64, pen.v as f64),
+        Paint::Fill => (0.0, 0.0),
+    };
+    let style = match paint {
+        Paint::Frame(_) => format!(
+            "fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"",
+            color,
+            (pen_h + pen_v) / 2.0
+        ),
+        Paint::Fill => format!("fill=\"{}\"", color),
+    };
+
+    let inset = |rect: &Rect| {
+        (
+            rect.left as f64 + pen_h / 2.0,
+            rect.top as f64 + pen_v / 2.0,
+            (width(*rect) as f64 - pen_h).max(0.0),
+            (height(*rect) as f64 - pen_v).max(0.0),
+        )
+    };
+
+    match shape {
+        Shape::Line(from, to) => format!(
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"{}\" stroke-linecap=\"square\"/>\n",
+            from.h as f64 + pen_h / 2.0,
+            from.v as f64 + pen_v / 2.0,
+            to.h as f64 + pen_h / 2.0,
+            to.v as f64 + pen_v / 2.0,
+            color,
+            (pen_h + pen_v) / 2.0
+        ),
+        Shape::Rect(rect) => {
+            let (x, y, width, height) = inset(rect);
+            format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" {}/>\n",
+                x, y, width, height, style
+            )
+        }
+        Shape::RoundRect(rect, corner) => {
+            let (x, y, width, height) = inset(rect);
+            format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"{}\" ry=\"{}\" {}/>\n",
+                x,
+                y,
+                width,
+                height,
+                corner.h as f64 / 2.0,
+                corner.v as f64 / 2.0,
+                style
+            )
+        }
+        Shape::Oval(rect) => {
+            let (x, y, width, height) = inset(rect);
+            format!(
+                "<ellipse cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\" {}/>\n",
+                x + width / 2.0,
+                y + height / 2.0,
+                width / 2.0,
+                height / 2.0,
+                style
+            )
+        }
+        Shape::Polygon(points) => {
+            let points = points
+                .iter()
+                .map(|point| {
+                    format!(
+                        "{},{}",
+                        point.h as f64 + pen_h / 2.0,
+                        point.v as f64 + pen_v / 2.0
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            let element = match paint {
+                Paint::Frame(_) => "polyline",
+                Paint::Fill => "polygon",
+            };
+            format!("<{} points=\"{}\" {}/>\n", element, points, style)
+        }
+    }
+}
+
+fn svg_text(text: &Text) -> String {
+    let mut style = String::new();
+    if text.font_style & 0x01 != 0 {
+        style.push_str(" font-weight=\"bold\"");
+    }
+    if text.font_style & 0x02 != 0 {
+        style.push_str(" font-style=\"italic\"");
+    }
+    if text.font_style & 0x04 != 0 {
+        style.push_str(" text-decoration=\"underline\"");
+    }
+
+    format!(
+        "<text x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"{}\"{} fill=\"{}\">{}</text>\n",
+        text.location.h,
+        text.location.v,
+        escape_xml(&text.font_name),
+        text.font_size,
+        style,
+        hex_color(text.color),
+        escape_xml(&text.text)
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// An RGB pixel buffer covering the picture's frame, with a white background.
+struct Canvas {
+    frame: Rect,
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(frame: Rect) -> Result<Self> {
+        let width = width(frame) as usize;
+        let height = height(frame) as usize;
+        if width * height > MAX_CANVAS_PIXELS {
+            return Err(Error::from(ODBError::PictureTooLarge { width, height }));
+        }
+
+        Ok(Self {
+            frame,
+            width,
+            height,
+            pixels: vec![0xff; width * height * 3],
+        })
+    }
+
+    fn set(&mut self, h: i32, v: i32, color: RGBColor) {
+        let x = h - self.frame.left as i32;
+        let y = v - self.frame.top as i32;
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+
+        let index = (y as usize * self.width + x as usize) * 3;
+        self.pixels[index] = (color.red >> 8) as u8;
+        self.pixels[index + 1] = (color.green >> 8) as u8;
+        self.pixels[index + 2] = (color.blue >> 8) as u8;
+    }
+
+    // Sets every pixel in the bounds whose center is inside the shape.
+    fn fill(&mut self, bounds: Rect, color: RGBColor, inside: impl Fn(f64, f64) -> bool) {
+        for v in bounds.top as i32..bounds.bottom as i32 {
+            for h in bounds.left as i32..bounds.right as i32 {
+                if inside(h as f64 + 0.5, v as f64 + 0.5) {
+                    self.set(h, v, color);
+                }
+            }
+        }
+    }
+
+    fn draw_shape(&mut self, shape: &Shape, paint: Paint, color: RGBColor) {
+        match (shape, paint) {
+            (Shape::Line(from, to), Paint::Frame(pen)) => self.draw_line(*from, *to, pen, color),
+            (Shape::Line(_, _), Paint::Fill) => {}
+            (Shape::Rect(rect), _) => {
+                self.draw_round_rect(*rect, Point { v: 0, h: 0 }, paint, color)
+            }
+            (Shape::RoundRect(rect, corner), _) => {
+                self.draw_round_rect(*rect, *corner, paint, color)
+            }
+            (Shape::Oval(rect), _) => {
+                let corner = Point {
+                    v: height(*rect) as i16,
+                    h: width(*rect) as i16,
+                };
+                self.draw_round_rect(*rect, corner, paint, color)
+            }
+            (Shape::Polygon(points), Paint::Fill) => {
+                if let Some(bounds) = bounding_rect(points) {
+                    self.fill(bounds, color, |x, y| in_polygon(x, y, points));
+                }
+            }
+            (Shape::Polygon(points), Paint::Frame(pen)) => {
+                for pair in points.windows(2) {
+                    self.draw_line(pair[0], pair[1], pen, color);
+                }
+            }
+        }
+    }
+
+    fn draw_round_rect(&mut self, rect: Rect, corner: Point, paint: Paint, color: RGBColor) {
+        match paint {
+            Paint::Fill => self.fill(rect, color, |x, y| in_round_rect(x, y, &rect, corner)),
+            Paint::Frame(pen) => {
+                let inner = Rect {
+                    top: rect.top.saturating_add(pen.v),
+                    left: rect.left.saturating_add(pen.h),
+                    bottom: rect.bottom.saturating_sub(pen.v),
+                    right: rect.right.saturating_sub(pen.h),
+                };
+                let inner_corner = Point {
+                    v: corner.v.saturating_sub(pen.v.saturating_mul(2)),
+                    h: corner.h.saturating_sub(pen.h.saturating_mul(2)),
+                };
+                self.fill(rect, color, |x, y| {
+                    in_round_rect(x, y, &rect, corner) && !in_round_rect(x, y, &inner, inner_corner)
+                });
+            }
+        }
+    }
+
+    // Stamps the pen at every point along the line.
+    fn draw_line(&mut self, from: Point, to: Point, pen: Point, color: RGBColor) {
+        let (mut h, mut v) = (from.h as i32, from.v as i32);
+        let (end_h, end_v) = (to.h as i32, to.v as i32);
+        let dh = (end_h - h).abs();
+        let dv = -(end_v - v).abs();
+        let step_h = if h < end_h { 1 } else { -1 };
+        let step_v = if v < end_v { 1 } else { -1 };
+        let mut error = dh + dv;
+
+        loop {
+            for pen_v in 0..pen.v as i32 {
+                for pen_h in 0..pen.h as i32 {
+                    self.set(h + pen_h, v + pen_v, color);
+                }
+            }
+
+            if h == end_h && v == end_v {
+                break;
+            }
+            let double_error = 2 * error;
+            if double_error >= dv {
+                error += dv;
+                h += step_h;
+            }
+            if double_error <= dh {
+                error += dh;
+                v += step_v;
+            }
+        }
+    }
+
+    // Stretches the image to its destination, picking the nearest pixel.
+    fn draw_image(&mut self, image: &Image) {
+        let destination = image.destination;
+        let (destination_width, destination_height) =
+            (width(destination) as usize, height(destination) as usize);
+
+        for y in 0..destination_height {
+            for x in 0..destination_width {
+                let source_x = x * image.width / destination_width;
+                let source_y = y * image.height / destination_height;
+                self.set(
+                    destination.left as i32 + x as i32,
+                    destination.top as i32 + y as i32,
+                    image.pixels[source_y * image.width + source_x],
+                );
+            }
+        }
+    }
+}
+
+fn in_round_rect(x: f64, y: f64, rect: &Rect, corner: Point) -> bool {
+    let (left, top, right, bottom) = (
+        rect.left as f64,
+        rect.top as f64,
+        rect.right as f64,
+        rect.bottom as f64,
+    );
+    if x < left || x >= right || y < top || y >= bottom {
+        return false;
+    }
+
+    let radius_h = (corner.h as f64 / 2.0).min((right - left) / 2.0);
+    let radius_v = (corner.v as f64 / 2.0).min((bottom - top) / 2.0);
+    if radius_h <= 0.0 || radius_v <= 0.0 {
+        return true;
+    }
+
+    let center_x = x.clamp(left + radius_h, right - radius_h);
+    let center_y = y.clamp(top + radius_v, bottom - radius_v);
+    let dx = (x - center_x) / radius_h;
+    let dy = (y - center_y) / radius_v;
+    dx * dx + dy * dy <= 1.0
+}
+
+// Uses the even-odd rule, as QuickDraw does.
+fn in_polygon(x: f64, y: f64, points: &[Point]) -> bool {
+    let mut inside = false;
+
+    for (i, point) in points.iter().enumerate() {
+        let previous = points[(i + points.len() - 1) % points.len()];
+        let (x1, y1) = (point.h as f64, point.v as f64);
+        let (x2, y2) = (previous.h as f64, previous.v as f64);
+        if (y1 > y) != (y2 > y) && x < (x2 - x1) * (y - y1) / (y2 - y1) + x1 {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+fn bounding_rect(points: &[Point]) -> Option<Rect> {
+    Some(Rect {
+        top: points.iter().map(|point| point.v).min()?,
+        left: points.iter().map(|point| point.h).min()?,
+        bottom: points.iter().map(|point| point.v).max()?,
+        right: points.iter().map(|point| point.h).max()?,
+    })
+}
+
+fn rgb_bytes(pixels: &[RGBColor]) -> Vec<u8> {
+    pixels
+        .iter()
+        .flat_map(|color| {
+            [
+                (color.red >> 8) as u8,
+                (color.green >> 8) as u8,
+                (color.blue >> 8) as u8,
+            ]
+        })
+        .collect()
+}
+
+fn encode_png(width: usize, height: usize, rgb: &[u8]) -> Result<Vec<u8>> {
+    let mut png = vec![];
+
+    let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgb)?;
+    writer.finish()?;
+
+    Ok(png)
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+
+    for chunk in bytes.chunks(3) {
+        let value = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(value >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> LoadContext {
+        LoadContext::for_platform(Platform::Mac)
+    }
+
+    fn version_2_picture(opcodes: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0, 0];
+        bytes.extend(
+            Rect {
+                top: 0,
+                left: 0,
+                bottom: 20,
+                right: 30,
+            }
+            .to_bytes(),
+        );
+        bytes.extend([0x00, 0x11, 0x02, 0xff, 0x0c, 0x00]);
+        bytes.extend([0; VERSION_2_HEADER_SIZE]);
+        bytes.extend(opcodes);
+        bytes.extend([0x00, 0xff]);
+        bytes
+    }
+
+    fn pixel(png: &[u8], x: usize, y: usize) -> [u8; 3] {
+        let decoder = png::Decoder::new(png);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).unwrap();
+        let index = (y * info.width as usize + x) * 3;
+        buffer[index..(index + 3)].try_into().unwrap()
+    }
+
+    #[rustfmt::skip]
+    fn drawing() -> Vec<u8> {
+        version_2_picture(&[
+            // RGBFgCol red, PaintRect
+            0x00, 0x1a, 0xff, 0xff, 0, 0, 0, 0,
+            0x00, 0x31, 0, 0, 0, 0, 0, 10, 0, 10,
+            // PnSize 2x2, RGBFgCol black, FrameOval
+            0x00, 0x07, 0, 2, 0, 2,
+            0x00, 0x1a, 0, 0, 0, 0, 0, 0,
+            0x00, 0x50, 0, 0, 0, 10, 0, 20, 0, 30,
+            // Line
+            0x00, 0x20, 0, 0, 0, 15, 0, 9, 0, 15,
+            // LongText, padded to a word
+            0x00, 0x28, 0, 18, 0, 2, 2, b'H', b'i', 0,
+            // FrameArc
+            0x00, 0x60, 0, 0, 0, 0, 0, 5, 0, 5, 0, 0, 0, 90,
+            // PackBitsRect, a 1 bit bitmap with unpacked rows
+            0x00, 0x98, 0, 2,
+            0, 0, 0, 0, 0, 2, 0, 8,
+            0, 0, 0, 0, 0, 2, 0, 8,
+            0, 10, 0, 0, 0, 12, 0, 8,
+            0, 0,
+            0xff, 0x00, 0x00, 0x00,
+            // PackBitsRect, an 8 bit pixmap with packed rows
+            0x00, 0x98, 0x80, 8,
+            0, 0, 0, 0, 0, 1, 0, 8,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0x48, 0, 0, 0, 0x48, 0, 0,
+            0, 0, 0, 8, 0, 1, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 1,
+            0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0, 1, 0, 0, 0, 0, 0xff, 0xff,
+            0, 0, 0, 0, 0, 1, 0, 8,
+            0, 12, 0, 0, 0, 13, 0, 8,
+            0, 0,
+            2, 0xf9, 1, 0,
+        ])
+    }
+
+    #[test]
+    fn decodes_and_renders_common_opcodes() {
+        let picture = Picture::unpack(&drawing(), &context()).unwrap();
+        assert_eq!(picture.version, 2);
+        assert_eq!(picture.elements().len(), 6);
+        assert_eq!(
+            picture.warnings(),
+            &[PictWarning::UnsupportedOpcode {
+                offset: 102,
+                opcode: 0x0060
+            }]
+        );
+        assert_eq!(picture.pack(), drawing());
+
+        let svg = picture.to_svg().output;
+        assert!(svg.contains("<rect x=\"0\" y=\"0\" width=\"10\" height=\"10\" fill=\"#ff0000\"/>"));
+        assert!(svg.contains("<ellipse cx=\"20\" cy=\"10\" rx=\"9\" ry=\"9\" fill=\"none\""));
+        assert!(svg.contains(">Hi</text>"));
+        assert_eq!(svg.matches("data:image/png;base64,").count(), 2);
+
+        let png = picture.to_png().unwrap();
+        assert_eq!(png.warnings.len(), 2);
+        assert_eq!(png.warnings[1], PictWarning::TextNotDrawn { count: 1 });
+        let png = png.output;
+        assert_eq!(pixel(&png, 5, 5), [0xff, 0, 0]);
+        assert_eq!(pixel(&png, 20, 0), [0, 0, 0]);
+        assert_eq!(pixel(&png, 20, 10), [0xff, 0xff, 0xff]);
+        assert_eq!(pixel(&png, 16, 5), [0, 0, 0]);
+        assert_eq!(pixel(&png, 7, 10), [0, 0, 0]);
+        assert_eq!(pixel(&png, 7, 11), [0xff, 0xff, 0xff]);
+        assert_eq!(pixel(&png, 3, 12), [0, 0, 0xff]);
+    }
+
+    #[test]
+    fn truncated_pictures_keep_what_was_read() {
+        let mut bytes = vec![0, 0, 0, 0, 0, 0, 0, 5, 0, 5, 0x11, 0x01];
+        bytes.extend([0x31, 0, 0, 0, 0, 0, 5, 0, 5]);
+        bytes.extend([0x31, 0, 0]);
+
+        let picture = Picture::unpack(&bytes, &context()).unwrap();
+        assert_eq!(picture.version, 1);
+        assert_eq!(picture.elements().len(), 1);
+        assert_eq!(picture.warnings(), &[PictWarning::Truncated { offset: 21 }]);
+        assert_eq!(
+            picture.warnings()[0].to_string(),
+            "The picture couldn't be read past offset 21."
+        );
+
+        assert!(Picture::unpack(&bytes[0..8], &context()).is_err());
+
+        let empty = Picture::default();
+        assert_eq!(Picture::unpack(&empty.pack(), &context()).unwrap(), empty);
+    }
+
+    #[test]
+    fn crops_images_at_the_edges_of_the_plane() {
+        let bounds = Rect {
+            top: 0,
+            left: i16::MIN,
+            bottom: 1,
+            right: i16::MAX,
+        };
+        let pixels = vec![WHITE; width(bounds) as usize];
+        let destination = Rect {
+            top: 0,
+            left: 0,
+            bottom: 1,
+            right: 2,
+        };
+
+        let image = crop(&pixels, pixels.len(), &bounds, &bounds, destination).unwrap();
+        assert_eq!(image.width, 65535);
+        assert_eq!(image.height, 1);
+    }
+
+    #[test]
+    fn huge_frames_are_not_rendered() {
+        let picture = Picture {
+            frame: Rect {
+                top: i16::MIN,
+                left: i16::MIN,
+                bottom: i16::MAX,
+                right: i16::MAX,
+            },
+            ..Picture::default()
+        };
+
+        assert!(matches!(
+            picture.to_png(),
+            Err(Error::ODB(ODBError::PictureTooLarge {
+                width: 65535,
+                height: 65535
+            }))
+        ));
+    }
+
+    #[test]
+    fn relative_text_wraps_at_the_edges_of_the_plane() {
+        #[rustfmt::skip]
+        let bytes = version_2_picture(&[
+            // LongText at the far right and bottom, then DHText, DVText
+            // and DHDVText one pixel further
+            0x00, 0x28, 0x7f, 0xff, 0x7f, 0xff, 1, b'A',
+            0x00, 0x29, 1, 1, b'B', 0,
+            0x00, 0x2a, 1, 1, b'C', 0,
+            0x00, 0x2b, 0xff, 0xff, 1, b'D',
+        ]);
+
+        let picture = Picture::unpack(&bytes, &context()).unwrap();
+        let locations: Vec<_> = picture
+            .elements()
+            .iter()
+            .map(|element| match element {
+                Element::Text(text) => (text.location.h, text.location.v),
+                element => panic!("unexpected element {:?}", element),
+            })
+            .collect();
+        assert_eq!(
+            locations,
+            vec![
+                (i16::MAX, i16::MAX),
+                (i16::MIN, i16::MAX),
+                (i16::MIN, i16::MIN),
+                (i16::MIN + 255, i16::MIN + 255)
+            ]
+        );
+    }
+
+    #[test]
+    fn unpacks_bits() {
+        assert_eq!(
+            unpack_bits(&[0xfe, 0xaa, 0x02, 1, 2, 3, 0x80], 1).unwrap(),
+            vec![0xaa, 0xaa, 0xaa, 1, 2, 3]
+        );
+        assert_eq!(
+            unpack_bits(&[0xff, 0x12, 0x34], 2).unwrap(),
+            vec![0x12, 0x34, 0x12, 0x34]
+        );
+        assert!(unpack_bits(&[0x05, 1], 1).is_none());
+        assert_eq!(base64(b"PICT!"), "UElDVCE=");
+    }
+}
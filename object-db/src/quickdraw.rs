@@ -0,0 +1,167 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::error::*;
+use crate::odb_error::*;
+use std::convert::TryInto;
+
+const POINT_SIZE: usize = 4;
+const RECT_SIZE: usize = 8;
+const RGB_COLOR_SIZE: usize = 6;
+const PATTERN_SIZE: usize = 8;
+
+// QuickDraw coordinates are signed 16-bit integers. Frontier stores -1 in
+// every field of a window rect that has never been positioned, so reading them
+// as unsigned values produces nonsense like 65535.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Point {
+    pub v: i16,
+    pub h: i16,
+}
+
+impl Point {
+    pub fn new(buffer: &[u8]) -> Result<Self> {
+        check_size(buffer, POINT_SIZE)?;
+
+        Ok(Self {
+            v: i16::from_be_bytes(buffer[0..2].try_into()?),
+            h: i16::from_be_bytes(buffer[2..4].try_into()?),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; POINT_SIZE] {
+        let mut bytes = [0; POINT_SIZE];
+        bytes[0..2].copy_from_slice(&self.v.to_be_bytes());
+        bytes[2..4].copy_from_slice(&self.h.to_be_bytes());
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub top: i16,
+    pub left: i16,
+    pub bottom: i16,
+    pub right: i16,
+}
+
+impl Rect {
+    pub fn new(buffer: &[u8]) -> Result<Self> {
+        check_size(buffer, RECT_SIZE)?;
+
+        Ok(Self {
+            top: i16::from_be_bytes(buffer[0..2].try_into()?),
+            left: i16::from_be_bytes(buffer[2..4].try_into()?),
+            bottom: i16::from_be_bytes(buffer[4..6].try_into()?),
+            right: i16::from_be_bytes(buffer[6..8].try_into()?),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; RECT_SIZE] {
+        let mut bytes = [0; RECT_SIZE];
+        bytes[0..2].copy_from_slice(&self.top.to_be_bytes());
+        bytes[2..4].copy_from_slice(&self.left.to_be_bytes());
+        bytes[4..6].copy_from_slice(&self.bottom.to_be_bytes());
+        bytes[6..8].copy_from_slice(&self.right.to_be_bytes());
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RGBColor {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+}
+
+impl RGBColor {
+    pub fn new(buffer: &[u8]) -> Result<Self> {
+        check_size(buffer, RGB_COLOR_SIZE)?;
+
+        Ok(Self {
+            red: u16::from_be_bytes(buffer[0..2].try_into()?),
+            green: u16::from_be_bytes(buffer[2..4].try_into()?),
+            blue: u16::from_be_bytes(buffer[4..6].try_into()?),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; RGB_COLOR_SIZE] {
+        let mut bytes = [0; RGB_COLOR_SIZE];
+        bytes[0..2].copy_from_slice(&self.red.to_be_bytes());
+        bytes[2..4].copy_from_slice(&self.green.to_be_bytes());
+        bytes[4..6].copy_from_slice(&self.blue.to_be_bytes());
+        bytes
+    }
+}
+
+// An 8x8 one bit per pixel QuickDraw pattern, one byte per row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pattern {
+    pub rows: [u8; PATTERN_SIZE],
+}
+
+impl Pattern {
+    pub fn new(buffer: &[u8]) -> Result<Self> {
+        check_size(buffer, PATTERN_SIZE)?;
+
+        Ok(Self {
+            rows: buffer[0..PATTERN_SIZE].try_into()?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; PATTERN_SIZE] {
+        self.rows
+    }
+}
+
+fn check_size(buffer: &[u8], size: usize) -> Result<()> {
+    if buffer.len() < size {
+        return Err(Error::from(ODBError::BadPackedValue));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coordinates_are_signed() {
+        let rect = Rect::new(&[0xff, 0xff, 0xff, 0xfe, 0x01, 0x81, 0x02, 0x08]).unwrap();
+        assert_eq!(
+            rect,
+            Rect {
+                top: -1,
+                left: -2,
+                bottom: 385,
+                right: 520
+            }
+        );
+
+        let point = Point::new(&[0x80, 0x00, 0x00, 0x0c]).unwrap();
+        assert_eq!(point, Point { v: -32768, h: 12 });
+    }
+
+    #[test]
+    fn short_buffers_are_rejected() {
+        assert!(Rect::new(&[0; 6]).is_err());
+        assert!(RGBColor::new(&[0; 4]).is_err());
+        assert!(Pattern::new(&[0; 7]).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::code_tree::*;
+use crate::error::*;
+use crate::outline::*;
+use crate::variable::*;
+
+const COMMENT_PREFIX: char = '«';
+
+// A UserTalk script. Its source is an outline with a line of code in each
+// headline, indented by its level. A script that has been compiled and
+// linked also carries the code tree, which is kept as it was read.
+//
+// Packed, the outline comes first, then the code tree, if there is any.
+// A script that doesn't unpack is kept as raw bytes by the table that holds
+// it.
+//
+// The source is only changed through set_source, so the code saved with the
+// script never belongs to an older version of it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Script {
+    source: Outline,
+    code: Option<Vec<u8>>,
+}
+
+impl Script {
+    pub fn new(source: Outline) -> Self {
+        Self { source, code: None }
+    }
+
+    pub fn source(&self) -> &Outline {
+        &self.source
+    }
+
+    // Replaces the source, throwing away the code compiled from the old one.
+    pub fn set_source(&mut self, source: Outline) {
+        self.source = source;
+        self.clear_code();
+    }
+
+    // The compiled code saved with the script.
+    pub fn code(&self) -> Option<&[u8]> {
+        self.code.as_deref()
+    }
+
+    pub fn is_compiled(&self) -> bool {
+        self.code.is_some()
+    }
+
+    // Throws away the compiled code, so the script is saved as source only.
+    pub fn clear_code(&mut self) {
+        self.code = None;
+    }
+
+    pub fn set_code(&mut self, code: Vec<u8>) {
+        self.code = Some(code);
+    }
+
+    pub fn code_tree(&self, context: &LoadContext) -> Result<Option<CodeTree>> {
+        match &self.code {
+            Some(code) => Ok(Some(CodeTree::unpack(code, context)?)),
+            None => Ok(None),
+        }
+    }
+
+    // The source decompiled from the code, if the script has been compiled.
+    pub fn decompile(&self, context: &LoadContext) -> Result<Option<Outline>> {
+        self.code_tree(context)?
+            .map(|tree| tree.decompile(context))
+            .transpose()
+    }
+
+    // Replaces a missing source with the decompiled code. Returns whether
+    // the source was recovered.
+    pub fn recover_source(&mut self, context: &LoadContext) -> Result<bool> {
+        if !self.source.is_empty() {
+            return Ok(false);
+        }
+
+        match self.decompile(context)? {
+            Some(source) => {
+                self.source = source;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // The script as tab-indented UserTalk, one line per headline, the way
+    // Frontier exports it. Comment headlines start with «.
+    pub fn to_source_text(&self) -> String {
+        let mut text = String::new();
+
+        for (level, headline) in self.source.iter() {
+            text.push_str(&"\t".repeat(level));
+            if headline.is_comment && !headline.text.starts_with(COMMENT_PREFIX) {
+                text.push(COMMENT_PREFIX);
+            }
+            text.push_str(&headline.text);
+            text.push('\n');
+        }
+
+        text
+    }
+
+    pub fn unpack(bytes: &[u8], context: &LoadContext) -> Result<Self> {
+        let (source, length) = Outline::unpack_prefix(bytes, context)?;
+        let code = &bytes[length..];
+
+        Ok(Self {
+            source,
+            code: if code.is_empty() {
+                None
+            } else {
+                Some(code.to_vec())
+            },
+        })
+    }
+
+    pub fn pack(&self, context: &LoadContext) -> Result<Vec<u8>> {
+        let mut bytes = self.source.pack(context)?;
+        if let Some(code) = &self.code {
+            bytes.extend_from_slice(code);
+        }
+        Ok(bytes)
+    }
+}
+
+impl LoadFromBytes for Script {
+    fn load_from_bytes(bytes: &[u8], context: &LoadContext) -> Result<Self> {
+        Self::unpack(bytes, context)
+    }
+}
+
+impl SaveToBytes for Script {
+    fn save_to_bytes(&self, context: &LoadContext) -> Result<Vec<u8>> {
+        self.pack(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A script that was saved compiled but without its source: an empty
+    // outline, then the code for msg ("Hi").
+    #[rustfmt::skip]
+    const COMPILED_SCRIPT: [u8; 71] = [
+        // the outline, with no display state and no headlines
+        0x00, 0x02, 0x00, 0x04,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        // the code tree, version 1, with one statement
+        0x00, 0x01, 0x00, 0x00, 0x00, 0x01,
+        // a function call on line 1, with no value and two parameters
+        0x00, 0x0b, 0x00, 0x01, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00,
+        0x02,
+        // the identifier msg
+        0x00, 0x00, 0x00, 0x01,
+        0x00, 0x06, 0x00, 0x01, 0x00, 0x00,
+        0x0c, 0x00, 0x00, 0x00, 0x03, b'm', b's', b'g',
+        0x00,
+        // the string "Hi"
+        0x00, 0x00, 0x00, 0x01,
+        0x00, 0x07, 0x00, 0x01, 0x00, 0x05,
+        0x0c, 0x00, 0x00, 0x00, 0x02, b'H', b'i',
+        0x00,
+    ];
+
+    fn hello_script() -> Script {
+        let mut comment = Headline::new("Says hello");
+        comment.is_comment = true;
+
+        let mut handler = Headline::new("on hello (name)");
+        handler.subheads.push(comment);
+        handler
+            .subheads
+            .push(Headline::new("msg (\"Hello, \" + name)"));
+
+        let mut source = Outline::new();
+        source.summits.push(handler);
+        source.summits.push(Headline::new("hello (\"Ted\")"));
+        Script::new(source)
+    }
+
+    #[test]
+    fn renders_tab_indented_source() {
+        assert_eq!(
+            hello_script().to_source_text(),
+            "on hello (name)\n\t«Says hello\n\tmsg (\"Hello, \" + name)\nhello (\"Ted\")\n"
+        );
+    }
+
+    #[test]
+    fn keeps_the_compiled_code() {
+        let context = LoadContext::for_platform(Platform::Mac);
+        let script = hello_script();
+        let mut bytes = script.pack(&context).unwrap();
+        assert!(!Script::unpack(&bytes, &context).unwrap().is_compiled());
+
+        bytes.extend_from_slice(&[0, 1, 2, 3]);
+        let compiled = Script::unpack(&bytes, &context).unwrap();
+        assert_eq!(compiled.source(), script.source());
+        assert_eq!(compiled.code(), Some(&[0, 1, 2, 3][..]));
+        assert_eq!(compiled.pack(&context).unwrap(), bytes);
+
+        assert!(Script::unpack(&bytes[0..10], &context).is_err());
+    }
+
+    #[test]
+    fn recovers_the_source_of_a_compiled_script() {
+        let context = LoadContext::for_platform(Platform::Mac);
+        let mut script = Script::unpack(&COMPILED_SCRIPT, &context).unwrap();
+        assert!(script.source().is_empty());
+        assert_eq!(script.code(), Some(&COMPILED_SCRIPT[16..]));
+
+        assert!(script.recover_source(&context).unwrap());
+        assert_eq!(script.to_source_text(), "msg (\"Hi\")\n");
+    }
+
+    #[test]
+    fn editing_the_source_drops_the_code() {
+        let context = LoadContext::for_platform(Platform::Mac);
+        let mut script = hello_script();
+        script.set_code(vec![0, 1, 2, 3]);
+
+        let mut source = script.source().clone();
+        source.summits.push(Headline::new("hello (\"Dave\")"));
+        script.set_source(source.clone());
+        assert!(!script.is_compiled());
+
+        let saved = Script::unpack(&script.pack(&context).unwrap(), &context).unwrap();
+        assert_eq!(saved.source(), &source);
+        assert!(!saved.is_compiled());
+    }
+}
@@ -0,0 +1,116 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::collation::*;
+use crate::table_node::*;
+use crate::value_record::*;
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    ByName = 0,
+    ByValue = 1,
+    ByKind = 2,
+}
+
+impl SortOrder {
+    pub fn from_u16(sort_order: u16) -> Self {
+        match sort_order {
+            1 => SortOrder::ByValue,
+            2 => SortOrder::ByKind,
+            _ => SortOrder::ByName,
+        }
+    }
+
+    // Items that compare equal by value or kind fall back to being sorted by
+    // name, as they are in Frontier.
+    pub fn compare(&self, a: &TableNode, b: &TableNode) -> Ordering {
+        let ordering = match self {
+            SortOrder::ByName => Ordering::Equal,
+            SortOrder::ByValue => compare_values(a, b),
+            SortOrder::ByKind => a.value_type().cmp(&b.value_type()),
+        };
+
+        ordering.then_with(|| compare_names(a.name(), b.name()))
+    }
+}
+
+// Numbers compare with each other whatever their type and sort before
+// everything else, followed by strings, which compare the way names do. Other
+// values are grouped by kind and then compared by their contents.
+fn compare_values(a: &TableNode, b: &TableNode) -> Ordering {
+    let group = |node: &TableNode| {
+        if as_number(node.value()).is_some() {
+            0
+        } else if as_text(node.value()).is_some() {
+            1
+        } else {
+            2 + node.value_type() as u16
+        }
+    };
+
+    let ordering = match (a.value(), b.value()) {
+        (x, y) if as_number(x).is_some() && as_number(y).is_some() => as_number(x)
+            .unwrap_or(0.0)
+            .total_cmp(&as_number(y).unwrap_or(0.0)),
+        (x, y) if as_text(x).is_some() && as_text(y).is_some() => {
+            compare_names(as_text(x).unwrap_or(""), as_text(y).unwrap_or(""))
+        }
+        (Value::DateValue(x), Value::DateValue(y)) => x.cmp(y),
+        (Value::OSTypeValue(x), Value::OSTypeValue(y))
+        | (Value::EnumValue(x), Value::EnumValue(y)) => x.0.cmp(&y.0),
+        (Value::PointValue(x), Value::PointValue(y)) => (x.v, x.h).cmp(&(y.v, y.h)),
+        (Value::RectValue(x), Value::RectValue(y)) => {
+            (x.top, x.left, x.bottom, x.right).cmp(&(y.top, y.left, y.bottom, y.right))
+        }
+        (Value::RGBValue(x), Value::RGBValue(y)) => {
+            (x.red, x.green, x.blue).cmp(&(y.red, y.green, y.blue))
+        }
+        (Value::PatternValue(x), Value::PatternValue(y)) => x.rows.cmp(&y.rows),
+        (Value::BinaryValue(x), Value::BinaryValue(y)) => x.cmp(y),
+        (Value::FileSpec(x), Value::FileSpec(y)) => {
+            compare_names(&x.to_posix_path(), &y.to_posix_path())
+        }
+        (Value::ObjSpec(x), Value::ObjSpec(y)) => compare_names(&x.to_string(), &y.to_string()),
+        (Value::TableValue(x), Value::TableValue(y)) => x.len().cmp(&y.len()),
+        _ => Ordering::Equal,
+    };
+
+    group(a).cmp(&group(b)).then(ordering)
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::BooleanValue(x) => Some(*x as u8 as f64),
+        Value::CharValue(x) => Some(*x as u32 as f64),
+        Value::IntValue(x) => Some(*x as f64),
+        Value::LongValue(x) => Some(*x as f64),
+        Value::DirectionValue(x) => Some(*x as f64),
+        Value::FixedValue(x) => Some(*x),
+        Value::SingleValue(x) => Some(*x as f64),
+        Value::DoubleValue(x) => Some(*x),
+        _ => None,
+    }
+}
+
+fn as_text(value: &Value) -> Option<&str> {
+    match value {
+        Value::StringValue(x) | Value::AddressValue(x) => Some(x),
+        _ => None,
+    }
+}
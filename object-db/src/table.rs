@@ -19,57 +19,74 @@ along with this program.  If not, see <http://www.gnu.org/licenses/>.
 // #[macro_use]
 // extern crate lazy_static;
 
+use crate::code_tree::*;
+use crate::collation::*;
 use crate::error::*;
+use crate::file_spec::*;
+use crate::obj_spec::*;
+use crate::odb_error::*;
+use crate::pack::*;
+use crate::password::*;
+use crate::quickdraw::*;
+use crate::sort_order::*;
 use crate::string_utils::*;
+use crate::table_formats::*;
 use crate::table_node::*;
-use crate::variable::*;
 use crate::value_record::*;
+use crate::variable::*;
 use db::{DBAddress, Database, NIL_DB_ADDRESS};
-use std::{collections::HashMap, convert::TryFrom};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::convert::TryInto;
-use std::io::prelude::*;
-use std::iter::Map;
-use std::ops::{Add, Sub};
-use std::rc::{Rc, Weak};
 use std::time;
 
-const NODE_BUCKET_COUNT: usize = 11;
 // https://www.epochconverter.com/mac
 const CLASSIC_MAC_EPOCH_OFFSET: time::Duration = time::Duration::from_secs(2082844800);
+pub(crate) const FIXED_ONE: f64 = 65536.0;
+const MAX_NAME_LENGTH: usize = 255;
+
+// The tables Frontier puts in every new root.
+const SYSTEM_TABLE_NAMES: [&str; 7] = [
+    "system",
+    "user",
+    "scratchpad",
+    "websites",
+    "workspace",
+    "suites",
+    "temp",
+];
+
+const DISK_HEADER_SIZE: usize = 16;
+const DISK_HEADER_VERSION: u16 = 3;
+const DISK_SYMBOL_SIZE: usize = 10;
 
 #[derive(Debug)]
 pub struct Table {
+    // Keyed by the case-folded name of each node.
     nodes: HashMap<String, TableNode>,
     sorted_keys: Vec<String>,
-    // node_buckets: Vec<Option<Rc<Box<TableNode>>>>,
-    // first_sorted_node: Option<Weak<Box<TableNode>>>,
-    // prev_table: Option<Weak<Box<Table>>>,
-    // parent_table: Option<Weak<Box<Table>>>,
-    my_node: Option<Weak<Box<TableNode>>>,
+    name_collisions: Vec<String>,
 
     is_dirty: bool,
     is_locked: bool,
-    is_window_open: bool,
     is_no_purge: bool,
-    is_local_table: bool,
-    is_chained: bool,
-    is_dispose_when_unchained: bool,
-    with_value_count: u8,
-    is_verbs_require_window: bool,
-    is_need_sort: bool,
-    is_may_affect_display: bool,
     is_subs_dirty: bool,
 
     //TODO: long hashtablerefcon;
     //TODO: long lexicalrefcon;
-
-    //TODO: struct tytableformats **hashtableformats;
-    sort_order: u16,
+    formats: Option<TableFormats>,
+    // Formats that couldn't be decoded, so they can be saved unchanged.
+    unread_formats: Option<Vec<u8>>,
+    sort_order: SortOrder,
     time_created: time::SystemTime,
     time_last_saved: time::SystemTime,
+    flags: u32,
+    converted_from: Option<u16>,
 
+    // Items that were deleted or replaced since the table was last saved.
+    // The blocks their values were stored in are released when it's saved.
+    released_nodes: Vec<TableNode>,
     //TODO: langvaluecallback valueroutine
-    temp_stack_count: u16,
     //TODO: tyvaluerecord tmpstack []
 }
 
@@ -80,90 +97,404 @@ impl Table {
         Self {
             nodes: HashMap::new(),
             sorted_keys: vec![],
-            // node_buckets: vec![None; NODE_BUCKET_COUNT],
-            // first_sorted_node: None,
-            // prev_table: None,
-            // parent_table: None,
-            my_node: None,
+            name_collisions: vec![],
             is_dirty: false,
             is_locked: false,
-            is_window_open: false,
             is_no_purge: false,
-            is_local_table: false,
-            is_chained: false,
-            is_dispose_when_unchained: false,
-            with_value_count: 0,
-            is_verbs_require_window: false,
-            is_need_sort: false,
-            is_may_affect_display: false,
             is_subs_dirty: false,
-            sort_order: 0,
+            formats: None,
+            unread_formats: None,
+            sort_order: SortOrder::ByName,
             time_created: now,
             time_last_saved: now,
-            temp_stack_count: 0,
+            flags: 0,
+            converted_from: None,
+            released_nodes: vec![],
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TableNode> {
+        self.nodes.get(&fold_case(name))
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut TableNode> {
+        self.nodes.get_mut(&fold_case(name))
+    }
+
+    // Indexes are one-based, as they are in UserTalk.
+    pub fn get_index_mut(&mut self, index: usize) -> Option<&mut TableNode> {
+        let key = self.sorted_keys.get(index.checked_sub(1)?)?;
+        self.nodes.get_mut(key)
+    }
+
+    pub fn insert(&mut self, name: &str, value: Value) -> Result<()> {
+        check_name(name)?;
+
+        match self.nodes.entry(fold_case(name)) {
+            Entry::Occupied(_) => {
+                return Err(Error::from(ODBError::NameExists {
+                    name: String::from(name),
+                }))
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(TableNode::new(String::from(name), value));
+            }
+        }
+
+        self.changed();
+        Ok(())
+    }
+
+    // Replaces the value of an item, or adds the item if there isn't one with
+    // that name, the way assigning to a table item in UserTalk does.
+    pub fn set_value(&mut self, name: &str, value: Value) -> Result<()> {
+        match self.nodes.get_mut(&fold_case(name)) {
+            Some(node) => {
+                let old_node = node.set_value(value);
+                self.released_nodes.push(old_node);
+                self.changed();
+                Ok(())
+            }
+            None => self.insert(name, value),
+        }
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        let node = self
+            .nodes
+            .remove(&fold_case(name))
+            .ok_or_else(|| name_not_found(name))?;
+
+        self.released_nodes.push(node);
+        self.changed();
+        Ok(())
+    }
+
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<()> {
+        check_name(new_name)?;
+
+        let key = fold_case(name);
+        let new_key = fold_case(new_name);
+
+        if !self.nodes.contains_key(&key) {
+            return Err(name_not_found(name));
+        }
+
+        if new_key != key && self.nodes.contains_key(&new_key) {
+            return Err(Error::from(ODBError::NameExists {
+                name: String::from(new_name),
+            }));
+        }
+
+        let mut node = self.nodes.remove(&key).unwrap();
+        node.set_name(String::from(new_name));
+        self.nodes.insert(new_key, node);
+
+        self.changed();
+        Ok(())
+    }
+
+    fn changed(&mut self) {
+        self.is_dirty = true;
+        self.sort_nodes();
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // Visits the nodes in the order Frontier displays them.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &TableNode)> {
+        self.sorted_keys
+            .iter()
+            .filter_map(move |key| self.nodes.get(key).map(|node| (node.name(), node)))
+    }
+
+    // Names that were dropped when the table was unpacked because they
+    // differed from an earlier item's name only in case.
+    pub fn name_collisions(&self) -> &[String] {
+        &self.name_collisions
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.is_dirty
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.is_locked
+    }
+
+    pub fn sort_order(&self) -> SortOrder {
+        self.sort_order
+    }
+
+    // Changing the sort order is saved with the table, so it makes the table
+    // dirty.
+    pub fn set_sort_order(&mut self, sort_order: SortOrder) {
+        if sort_order != self.sort_order {
+            self.sort_order = sort_order;
+            self.is_dirty = true;
+            self.sort_nodes();
         }
     }
 
+    pub fn formats(&self) -> Option<&TableFormats> {
+        self.formats.as_ref()
+    }
+
+    pub fn set_formats(&mut self, formats: TableFormats) {
+        self.formats = Some(formats);
+        self.unread_formats = None;
+        self.is_dirty = true;
+    }
+
+    pub fn time_created(&self) -> time::SystemTime {
+        self.time_created
+    }
+
+    pub fn time_last_saved(&self) -> time::SystemTime {
+        self.time_last_saved
+    }
+
     fn sort_nodes(&mut self) {
-        let mut keys: Vec<_> = self.nodes.keys().map(|s| s.clone()).collect();
-        keys.sort_by(|a, b| a.cmp(b));
+        let mut keys: Vec<_> = self.nodes.keys().cloned().collect();
+        keys.sort_by(|a, b| self.sort_order.compare(&self.nodes[a], &self.nodes[b]));
         self.sorted_keys = keys;
     }
 
-    pub fn load_system_table(db: &mut Database, address: DBAddress) -> Result<Self> {
+    // A root that hasn't been saved yet starts out empty.
+    pub fn load_system_table(
+        db: &mut Database,
+        context: &LoadContext,
+        address: DBAddress,
+    ) -> Result<Self> {
         if address == NIL_DB_ADDRESS {
-            // TODO: start an empty table
-        } else {
-            let mut variable = Variable::<Table>::new_on_disk(db, address);
-            variable.load_from_disk()?;
+            return Ok(Self::new());
+        }
+
+        let variable = Variable::<Table>::new_on_disk(db, context, address);
+        Ok(variable.into_in_memory()?.data)
+    }
+
+    // Adds an empty table for each of Frontier's standard tables that the
+    // root is missing, and returns their names. An item that has one of
+    // those names but isn't a table is left alone.
+    pub(crate) fn add_missing_system_tables(&mut self) -> Result<Vec<&'static str>> {
+        let mut added = vec![];
+        for name in SYSTEM_TABLE_NAMES.iter() {
+            if self.get(name).is_none() {
+                self.insert(name, Value::TableValue(Box::default()))?;
+                added.push(*name);
+            }
+        }
+
+        Ok(added)
+    }
+
+    // Returns the named node's value, reading it from the database first if
+    // it's an external value that hasn't been loaded yet.
+    pub fn load_value(
+        &mut self,
+        name: &str,
+        db: &mut Database,
+        context: &LoadContext,
+    ) -> Result<Option<&mut Value>> {
+        match self.nodes.get_mut(&fold_case(name)) {
+            Some(node) => Ok(Some(node.load_value(db, context)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Releases the memory held by every clean subtable and external value
+    // below this table. They're read back in the next time they're loaded.
+    pub fn purge(&mut self) {
+        for node in self.nodes.values_mut() {
+            node.purge();
+        }
+    }
+
+    pub fn is_purgeable(&self) -> bool {
+        !(self.is_no_purge || self.has_unsaved_changes())
+    }
+
+    // Reads every subtable and external value below this table into memory.
+    pub fn load_all(&mut self, db: &mut Database, context: &LoadContext) -> Result<()> {
+        for node in self.nodes.values_mut() {
+            if let Value::TableValue(table) = node.load_value(db, context)? {
+                table.load_all(db, context)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Forgets where everything below this table is stored, so the next save
+    // writes all of it. The whole table has to be loaded first.
+    pub(crate) fn detach(&mut self) {
+        for node in self.nodes.values_mut() {
+            node.detach();
+        }
+
+        self.released_nodes.clear();
+        self.is_dirty = true;
+    }
+
+    // The version of the header the table was unpacked from, if it was older
+    // than version 2 and had to be converted.
+    pub(crate) fn converted_from(&self) -> Option<u16> {
+        self.converted_from
+    }
 
-            if let VariableData::InMemory(mut tbl) = variable.data {
-                tbl.data.sort_nodes();
-                return Ok(tbl.data);
+    // Frontier marks every table above a change as having dirty subtables.
+    // Tables here don't point back to their parents, so it's worked out by
+    // looking down instead.
+    pub fn has_unsaved_changes(&self) -> bool {
+        self.is_dirty
+            || !self.released_nodes.is_empty()
+            || self.nodes.values().any(|node| node.has_unsaved_changes())
+    }
+
+    // Writes every changed subtable and external value below this table, then
+    // the table itself if anything in it changed. Changed blocks are replaced
+    // rather than rewritten, and the blocks that are no longer used are pushed
+    // onto the database's release stack. Returns the address of the table.
+    pub fn save(
+        &mut self,
+        db: &mut Database,
+        context: &LoadContext,
+        address: DBAddress,
+    ) -> Result<DBAddress> {
+        for node in self.nodes.values_mut() {
+            if node.save(db, context)? {
+                self.is_subs_dirty = true;
             }
         }
 
-        Ok(Self::new())
+        for node in std::mem::take(&mut self.released_nodes) {
+            node.release(db, context)?;
+        }
+
+        if !(self.is_dirty || self.is_subs_dirty) && address != NIL_DB_ADDRESS {
+            return Ok(address);
+        }
+
+        self.time_last_saved = time::SystemTime::now();
+
+        let address = save_value(db, context, self, address)?;
+
+        self.is_dirty = false;
+        self.is_subs_dirty = false;
+
+        Ok(address)
+    }
+
+    // Queues the blocks of everything in the table to be released.
+    pub(crate) fn release(self, db: &mut Database, context: &LoadContext) -> Result<()> {
+        for node in self.nodes.into_values().chain(self.released_nodes) {
+            node.release(db, context)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    let length = name.chars().count();
+    if length == 0 || length > MAX_NAME_LENGTH {
+        return Err(Error::from(ODBError::InvalidName {
+            name: String::from(name),
+        }));
+    }
+
+    Ok(())
+}
+
+// Names are checked in characters when they're inserted, but it's the encoded
+// name that has to fit after its length byte.
+pub(crate) fn encode_name(name: &str, context: &LoadContext) -> Result<Vec<u8>> {
+    let bytes = context.encode(name)?;
+    if bytes.len() > MAX_NAME_LENGTH {
+        return Err(Error::from(ODBError::InvalidName {
+            name: String::from(name),
+        }));
+    }
+
+    Ok(bytes)
+}
+
+fn name_not_found(name: &str) -> Error {
+    Error::from(ODBError::NameNotFound {
+        table: String::new(),
+        name: String::from(name),
+    })
+}
+
+impl Default for Table {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl LoadFromBytes for Table {
-    fn load_from_bytes(bytes: &[u8]) -> Result<Table> {
+    fn load_from_bytes(bytes: &[u8], context: &LoadContext) -> Result<Table> {
         let (packed_table, packed_formats) = split_buffer(bytes)?;
 
         let mut table = Table::new();
 
-        table.unpack_table(packed_table)?;
+        if !packed_formats.is_empty() {
+            match TableFormats::new(packed_formats, context.text_encoding) {
+                Ok(formats) => table.formats = Some(formats),
+                Err(_) => table.unread_formats = Some(packed_formats.to_vec()),
+            }
+        }
+
+        table.unpack_table(packed_table, context)?;
+        table.sort_nodes();
 
         Ok(table)
     }
 }
 
+impl SaveToBytes for Table {
+    fn save_to_bytes(&self, context: &LoadContext) -> Result<Vec<u8>> {
+        let packed_table = self.pack_table(context)?;
+
+        let mut bytes = (packed_table.len() as u32).to_be_bytes().to_vec();
+        bytes.extend(packed_table);
+
+        if let Some(formats) = &self.formats {
+            bytes.extend(formats.to_bytes(context.text_encoding, context.unmappable)?);
+        } else if let Some(unread_formats) = &self.unread_formats {
+            bytes.extend_from_slice(unread_formats);
+        }
+
+        Ok(bytes)
+    }
+}
+
 impl Table {
-    fn unpack_table(&mut self, packed_table: &[u8]) -> Result<()> {
+    fn unpack_table(&mut self, packed_table: &[u8], context: &LoadContext) -> Result<()> {
         let (records, strings) = split_buffer(packed_table)?;
 
         let mut index = 0;
-        let header_size = 16;
-        let mut header = DiskHeader::new(&records[index..(index + header_size)])?;
-        index += header_size;
-        let mut sorted = false;
+        let mut header = DiskHeader::new(records)?;
+        index += DISK_HEADER_SIZE;
 
         if header.version > 0 {
-            self.sort_order = header.sort_order;
-            let classic_mac_epoch = time::UNIX_EPOCH - CLASSIC_MAC_EPOCH_OFFSET;
-            self.time_created =
-                classic_mac_epoch + time::Duration::from_secs(header.time_created as u64);
-            self.time_last_saved =
-                classic_mac_epoch + time::Duration::from_secs(header.time_last_saved as u64);
+            self.sort_order = SortOrder::from_u16(header.sort_order);
+            self.time_created = date_from_mac_seconds(header.time_created);
+            self.time_last_saved = date_from_mac_seconds(header.time_last_saved);
 
             if header.version == 2 {
                 header.flags = 0;
             }
 
-            sorted = true;
+            self.flags = header.flags;
         } else {
-            header.version = 0;
             header.flags = 0;
             let now = time::SystemTime::now();
             self.time_created = now;
@@ -171,8 +502,13 @@ impl Table {
             index = 0;
         }
 
-        let disk_symbol_size = 10;
-        let chunks = records[index..].chunks(disk_symbol_size);
+        // Tables with old headers are written back with a current one.
+        if header.version < 2 {
+            self.converted_from = Some(header.version);
+            self.is_dirty = true;
+        }
+
+        let chunks = records[index..].chunks(DISK_SYMBOL_SIZE);
 
         for chunk in chunks {
             let mut rec = DiskSymbolRecord::new(chunk)?;
@@ -181,40 +517,107 @@ impl Table {
             }
 
             let strings_index = u32::from_be_bytes(rec.data.try_into()?);
-            let name = read_pascal_string(&strings[rec.index_key as usize..]);
+            let name =
+                read_pascal_string(heap_slice(strings, rec.index_key)?, context.text_encoding)?;
             if name.is_empty() {
                 continue;
             }
 
-            match rec.value_type {
-                x if x == DiskSymbolValueType::OldStringValue as u8 => {
-                    let string_value = read_pascal_string(&strings[strings_index as usize..]);
-                    self.nodes.insert(name, TableNode::new(Value::StringValue(string_value)));
-                },
-                x if x == DiskSymbolValueType::BooleanValue as u8 => {
-                    let bool_value = if header.version < 2 {
-                        u16::from_be_bytes(rec.data[0..2].try_into()?) != 0
-                    } else {
-                        rec.data[0] != 0
-                    };
-
-                    self.nodes.insert(name, TableNode::new(Value::BooleanValue(bool_value)));
-                },
-                x if x == DiskSymbolValueType::CharValue as u8 => {
-                    let char_value = rec.data[0] as char;
-                    self.nodes.insert(name, TableNode::new(Value::CharValue(char_value)));
-                },
-                _ => {
-                    self.nodes.insert(name, TableNode::new(Value::NoValue));
-                },
+            // The data of an external value is the address of the block it's
+            // stored in, and the version holds the kind of external.
+            if rec.value_type == DiskSymbolValueType::ExternalValue as u8 {
+                if let Some(external_type) = ExternalType::from_id(rec.version) {
+                    self.insert_unpacked_node(TableNode::new_on_disk(
+                        name,
+                        external_type,
+                        strings_index,
+                    ));
+                    continue;
+                }
             }
 
+            // A value that doesn't decode is kept as it was stored rather than
+            // stopping the rest of the table from loading.
+            let value = match unpack_value(&rec, strings, header.version, context) {
+                Ok(value) => value,
+                Err(_) => raw_value(&rec, strings)?,
+            };
 
-            // read_pascal_string(pstring: &[u8])
+            // Older headers store some values differently, so those are
+            // packed again from scratch.
+            if header.version >= 2 {
+                let packed = PackedValue {
+                    value_type: rec.value_type,
+                    version: rec.version,
+                    data: rec.data.try_into()?,
+                    heap_bytes: read_heap_bytes(&rec, strings)?,
+                };
+                self.insert_unpacked_node(TableNode::new_unpacked(name, value, packed));
+            } else {
+                self.insert_unpacked_node(TableNode::new(name, value));
+            }
         }
 
         Ok(())
     }
+
+    // Writes the header, a record for every item in display order and the
+    // heap the records point into, in the layout unpack_table reads.
+    fn pack_table(&self, context: &LoadContext) -> Result<Vec<u8>> {
+        let header = DiskHeader {
+            version: DISK_HEADER_VERSION,
+            sort_order: self.sort_order as u16,
+            time_created: mac_seconds_from_date(self.time_created),
+            time_last_saved: mac_seconds_from_date(self.time_last_saved),
+            flags: self.flags,
+        };
+
+        let mut records = header.to_bytes().to_vec();
+        let mut strings = vec![];
+
+        for (name, node) in self.iter() {
+            let packed = match node.packed_value() {
+                Some(packed) => packed.clone(),
+                None => pack_node(node, context)?,
+            };
+
+            let name = encode_name(name, context)?;
+            records.extend_from_slice(&(strings.len() as u32).to_be_bytes());
+            strings.push(name.len() as u8);
+            strings.extend(name);
+
+            records.push(packed.value_type);
+            records.push(packed.version);
+
+            match packed.heap_bytes {
+                Some(heap_bytes) => {
+                    records.extend_from_slice(&(strings.len() as u32).to_be_bytes());
+                    strings.extend(heap_bytes);
+                }
+                None => records.extend_from_slice(&packed.data),
+            }
+        }
+
+        let mut packed_table = (records.len() as u32).to_be_bytes().to_vec();
+        packed_table.extend(records);
+        packed_table.extend(strings);
+
+        Ok(packed_table)
+    }
+
+    // Frontier never allows two names in a table that differ only in case,
+    // but a table written by another tool might contain them. The first one
+    // wins and the rest are recorded.
+    fn insert_unpacked_node(&mut self, node: TableNode) {
+        let key = fold_case(node.name());
+
+        match self.nodes.entry(key) {
+            Entry::Occupied(_) => self.name_collisions.push(String::from(node.name())),
+            Entry::Vacant(entry) => {
+                entry.insert(node);
+            }
+        }
+    }
 }
 
 struct DiskHeader {
@@ -227,6 +630,10 @@ struct DiskHeader {
 
 impl DiskHeader {
     pub fn new(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < DISK_HEADER_SIZE {
+            return Err(Error::from(ODBError::BadPackedValue));
+        }
+
         let version = u16::from_be_bytes(bytes[0..2].try_into()?);
         let sort_order = u16::from_be_bytes(bytes[2..4].try_into()?);
         let time_created = u32::from_be_bytes(bytes[4..8].try_into()?);
@@ -241,9 +648,21 @@ impl DiskHeader {
             flags,
         })
     }
+
+    pub fn to_bytes(&self) -> [u8; DISK_HEADER_SIZE] {
+        let mut bytes = [0; DISK_HEADER_SIZE];
+        bytes[0..2].copy_from_slice(&self.version.to_be_bytes());
+        bytes[2..4].copy_from_slice(&self.sort_order.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.time_created.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.time_last_saved.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.flags.to_be_bytes());
+        bytes
+    }
 }
 
-enum DiskSymbolValueType {
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy)]
+pub(crate) enum DiskSymbolValueType {
     Uninitialized = -1,
     NoValue = 0,
     CharValue = 1,
@@ -252,6 +671,8 @@ enum DiskSymbolValueType {
     OldStringValue = 4,
     BinaryValue = 5,
     BooleanValue = 6,
+    // Never stored in a table, but kept so the numbering reads like Frontier's.
+    #[allow(dead_code)]
     TokenValue = 7,
     DateValue = 8,
     AddressValye = 9,
@@ -262,6 +683,7 @@ enum DiskSymbolValueType {
     DirectionValue = 14,
     PasswordValue = 15,
     OSTypeValue = 16,
+    #[allow(dead_code)]
     Unused2Value = 17,
     PointValue = 18,
     RectValue = 19,
@@ -285,6 +707,85 @@ enum DiskSymbolValueType {
     PictValue,
 }
 
+impl DiskSymbolValueType {
+    pub(crate) fn of(value: &Value) -> u8 {
+        let value_type = match value {
+            Value::RawValue(value_type, _) => return *value_type,
+            Value::Uninitialized => DiskSymbolValueType::Uninitialized,
+            Value::NoValue | Value::DiskValue(_) => DiskSymbolValueType::NoValue,
+            Value::CharValue(_) => DiskSymbolValueType::CharValue,
+            Value::IntValue(_) => DiskSymbolValueType::IntValue,
+            Value::LongValue(_) => DiskSymbolValueType::LongValue,
+            Value::BinaryValue(_) => DiskSymbolValueType::BinaryValue,
+            Value::CodeValue(_) => DiskSymbolValueType::CodeValue,
+            Value::BooleanValue(_) => DiskSymbolValueType::BooleanValue,
+            Value::DateValue(_) => DiskSymbolValueType::DateValue,
+            Value::AddressValue(_) => DiskSymbolValueType::AddressValye,
+            Value::DoubleValue(_) => DiskSymbolValueType::DoubleValue,
+            Value::StringValue(_) => DiskSymbolValueType::StringValue,
+            Value::Password(_) => DiskSymbolValueType::PasswordValue,
+            Value::DirectionValue(_) => DiskSymbolValueType::DirectionValue,
+            Value::OSTypeValue(_) => DiskSymbolValueType::OSTypeValue,
+            Value::PointValue(_) => DiskSymbolValueType::PointValue,
+            Value::RectValue(_) => DiskSymbolValueType::RectValue,
+            Value::PatternValue(_) => DiskSymbolValueType::PatternValue,
+            Value::RGBValue(_) => DiskSymbolValueType::RGBValue,
+            Value::FixedValue(_) => DiskSymbolValueType::FixedValue,
+            Value::SingleValue(_) => DiskSymbolValueType::SingleValue,
+            Value::EnumValue(_) => DiskSymbolValueType::EnumValue,
+            Value::FileSpec(spec) if spec.is_alias() => DiskSymbolValueType::AliasValue,
+            Value::FileSpec(_) => DiskSymbolValueType::FileSpecValue,
+            Value::ObjSpec(_) => DiskSymbolValueType::ObjSpecValue,
+            Value::ListValue(_) => DiskSymbolValueType::ListValue,
+            Value::RecordValue(_) => DiskSymbolValueType::RecordValue,
+            Value::TableValue(_) => DiskSymbolValueType::TableValue,
+            Value::ExternalValue(external_type, _) => Self::of_external(*external_type),
+            Value::Outline(_) => Self::of_external(ExternalType::Outline),
+            Value::Script(_) => Self::of_external(ExternalType::Script),
+            Value::WpText(_) => Self::of_external(ExternalType::WordProcessor),
+            Value::Menubar(_) => Self::of_external(ExternalType::Menubar),
+            Value::Picture(_) => Self::of_external(ExternalType::Picture),
+        };
+
+        value_type as u8
+    }
+
+    // External values are numbered after the built in types, in the order of
+    // their external processor IDs.
+    pub(crate) fn of_external(external_type: ExternalType) -> Self {
+        match external_type {
+            ExternalType::Outline => DiskSymbolValueType::OutlineValue,
+            ExternalType::WordProcessor => DiskSymbolValueType::WordValue,
+            ExternalType::Head => DiskSymbolValueType::HeadValue,
+            ExternalType::Table => DiskSymbolValueType::TableValue,
+            ExternalType::Script => DiskSymbolValueType::ScriptValue,
+            ExternalType::Menubar => DiskSymbolValueType::MenuValue,
+            ExternalType::Picture => DiskSymbolValueType::PictValue,
+        }
+    }
+
+    fn is_stored_in_heap(value_type: u8) -> bool {
+        [
+            DiskSymbolValueType::BinaryValue,
+            DiskSymbolValueType::AddressValye,
+            DiskSymbolValueType::CodeValue,
+            DiskSymbolValueType::DoubleValue,
+            DiskSymbolValueType::StringValue,
+            DiskSymbolValueType::PasswordValue,
+            DiskSymbolValueType::RectValue,
+            DiskSymbolValueType::PatternValue,
+            DiskSymbolValueType::RGBValue,
+            DiskSymbolValueType::OldDoubleValue,
+            DiskSymbolValueType::ObjSpecValue,
+            DiskSymbolValueType::FileSpecValue,
+            DiskSymbolValueType::AliasValue,
+            DiskSymbolValueType::ListValue,
+            DiskSymbolValueType::RecordValue,
+        ]
+        .iter()
+        .any(|heap_type| *heap_type as u8 == value_type)
+    }
+}
 
 struct DiskSymbolRecord<'a> {
     index_key: u32,
@@ -295,6 +796,10 @@ struct DiskSymbolRecord<'a> {
 
 impl<'a> DiskSymbolRecord<'a> {
     pub fn new(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < DISK_SYMBOL_SIZE {
+            return Err(Error::from(ODBError::BadPackedValue));
+        }
+
         let index_key = u32::from_be_bytes(bytes[0..4].try_into()?);
         let value_type = bytes[4];
         let version = bytes[5];
@@ -311,15 +816,640 @@ impl<'a> DiskSymbolRecord<'a> {
     }
 }
 
+// A DiskSymbolRecord as it's written, with the bytes its value takes up in
+// the heap if it's stored there.
+#[derive(Debug, Clone)]
+pub(crate) struct PackedValue {
+    value_type: u8,
+    version: u8,
+    data: [u8; 4],
+    heap_bytes: Option<Vec<u8>>,
+}
+
+impl PackedValue {
+    fn inline(value_type: u8, bytes: &[u8]) -> Self {
+        let mut data = [0; 4];
+        data[..bytes.len()].copy_from_slice(bytes);
+
+        Self {
+            value_type,
+            version: 0,
+            data,
+            heap_bytes: None,
+        }
+    }
+
+    fn heap(value_type: u8, bytes: &[u8]) -> Self {
+        let mut heap_bytes = (bytes.len() as u32).to_be_bytes().to_vec();
+        heap_bytes.extend_from_slice(bytes);
+
+        Self {
+            value_type,
+            version: 0,
+            data: [0; 4],
+            heap_bytes: Some(heap_bytes),
+        }
+    }
+}
+
+// Everything that doesn't fit in the four data bytes of a record is stored in
+// the heap with a four byte length, except the strings of the oldest tables,
+// which are pascal strings.
+fn read_heap_bytes(rec: &DiskSymbolRecord, strings: &[u8]) -> Result<Option<Vec<u8>>> {
+    let offset = u32::from_be_bytes(rec.data.try_into()?) as usize;
+
+    let length = match rec.value_type {
+        x if x == DiskSymbolValueType::OldStringValue as u8 => {
+            1 + *strings.get(offset).ok_or(ODBError::BadPackedValue)? as usize
+        }
+        x if DiskSymbolValueType::is_stored_in_heap(x) => {
+            4 + read_heap_value(strings, offset as u32)?.len()
+        }
+        _ => return Ok(None),
+    };
+
+    let bytes = strings
+        .get(offset..(offset + length))
+        .ok_or(ODBError::BadPackedValue)?;
+
+    Ok(Some(bytes.to_vec()))
+}
+
+fn pack_node(node: &TableNode, context: &LoadContext) -> Result<PackedValue> {
+    let external_type = match (node.value(), node.external_type()) {
+        (Value::TableValue(_), _) => ExternalType::Table,
+        (Value::ExternalValue(external_type, _), _) => *external_type,
+        (Value::Outline(_), _) => ExternalType::Outline,
+        (Value::Script(_), _) => ExternalType::Script,
+        (Value::WpText(_), _) => ExternalType::WordProcessor,
+        (Value::Menubar(_), _) => ExternalType::Menubar,
+        (Value::Picture(_), _) => ExternalType::Picture,
+        (Value::DiskValue(_), Some(external_type)) => external_type,
+        (value, _) => return pack_value(value, context),
+    };
+
+    // External values have to be saved before the table that holds them.
+    if node.old_address() == NIL_DB_ADDRESS {
+        return Err(Error::from(ODBError::BadPackedValue));
+    }
+
+    Ok(PackedValue {
+        value_type: DiskSymbolValueType::ExternalValue as u8,
+        version: external_type as u8,
+        data: node.old_address().to_be_bytes(),
+        heap_bytes: None,
+    })
+}
+
+fn pack_value(value: &Value, context: &LoadContext) -> Result<PackedValue> {
+    let value_type = DiskSymbolValueType::of(value);
+    let inline = |bytes: &[u8]| PackedValue::inline(value_type, bytes);
+    let heap = |bytes: &[u8]| PackedValue::heap(value_type, bytes);
+
+    let packed = match value {
+        Value::Uninitialized | Value::NoValue => {
+            PackedValue::inline(DiskSymbolValueType::NoValue as u8, &[])
+        }
+        Value::CharValue(c) => inline(&[context.encode_byte(*c)?]),
+        Value::IntValue(n) => inline(&n.to_be_bytes()),
+        Value::LongValue(n) => inline(&n.to_be_bytes()),
+        Value::BinaryValue(bytes) => heap(bytes),
+        Value::BooleanValue(b) => inline(&[*b as u8]),
+        Value::DateValue(date) => inline(&mac_seconds_from_date(*date).to_be_bytes()),
+        Value::AddressValue(s) | Value::StringValue(s) => heap(&context.encode(s)?),
+        Value::Password(password) => heap(&context.encode(password.reveal())?),
+        Value::CodeValue(tree) => heap(&tree.pack(context)?),
+        Value::DoubleValue(d) => heap(&d.to_be_bytes()),
+        Value::DirectionValue(direction) => inline(&[*direction]),
+        Value::OSTypeValue(code) | Value::EnumValue(code) => inline(&code.0),
+        Value::PointValue(point) => inline(&point.to_bytes()),
+        Value::RectValue(rect) => heap(&rect.to_bytes()),
+        Value::PatternValue(pattern) => heap(&pattern.to_bytes()),
+        Value::RGBValue(color) => heap(&color.to_bytes()),
+        Value::FixedValue(f) => inline(&((f * FIXED_ONE).round() as i32).to_be_bytes()),
+        Value::SingleValue(f) => inline(&f.to_bits().to_be_bytes()),
+        Value::ObjSpec(spec) => heap(&spec.to_bytes(context.text_encoding, context.unmappable)?),
+        Value::FileSpec(spec) => heap(&spec.to_bytes(context)?),
+        Value::ListValue(items) => heap(&pack_list(items, context)?),
+        Value::RecordValue(items) => heap(&pack_record(
+            items.iter().map(|(name, value)| (name.as_str(), value)),
+            items.len(),
+            context,
+        )?),
+        Value::RawValue(value_type, bytes)
+            if DiskSymbolValueType::is_stored_in_heap(*value_type) =>
+        {
+            heap(bytes)
+        }
+        Value::RawValue(_, bytes) if bytes.len() <= 4 => inline(bytes),
+        Value::RawValue(_, _)
+        | Value::TableValue(_)
+        | Value::ExternalValue(_, _)
+        | Value::Outline(_)
+        | Value::Script(_)
+        | Value::WpText(_)
+        | Value::Menubar(_)
+        | Value::Picture(_)
+        | Value::DiskValue(_) => return Err(Error::from(ODBError::BadPackedValue)),
+    };
+
+    Ok(packed)
+}
+
+fn unpack_value(
+    rec: &DiskSymbolRecord,
+    strings: &[u8],
+    header_version: u16,
+    context: &LoadContext,
+) -> Result<Value> {
+    let data = u32::from_be_bytes(rec.data.try_into()?);
+    let heap_value = || read_heap_value(strings, data);
+
+    let value = match rec.value_type {
+        x if x == DiskSymbolValueType::CharValue as u8 => {
+            Value::CharValue(context.text_encoding.decode_byte(rec.data[0]))
+        }
+        x if x == DiskSymbolValueType::IntValue as u8 => {
+            Value::IntValue(i16::from_be_bytes(rec.data[0..2].try_into()?))
+        }
+        x if x == DiskSymbolValueType::LongValue as u8 => Value::LongValue(data as i32),
+        x if x == DiskSymbolValueType::OldStringValue as u8 => Value::StringValue(
+            read_pascal_string(heap_slice(strings, data)?, context.text_encoding)?,
+        ),
+        x if x == DiskSymbolValueType::BinaryValue as u8 => {
+            Value::BinaryValue(heap_value()?.to_vec())
+        }
+        x if x == DiskSymbolValueType::BooleanValue as u8 => {
+            let bool_value = if header_version < 2 {
+                u16::from_be_bytes(rec.data[0..2].try_into()?) != 0
+            } else {
+                rec.data[0] != 0
+            };
+
+            Value::BooleanValue(bool_value)
+        }
+        x if x == DiskSymbolValueType::DateValue as u8 => {
+            Value::DateValue(date_from_mac_seconds(data))
+        }
+        x if x == DiskSymbolValueType::AddressValye as u8 => {
+            Value::AddressValue(read_fixed_string(heap_value()?, context.text_encoding))
+        }
+        x if x == DiskSymbolValueType::CodeValue as u8 => {
+            Value::CodeValue(CodeTree::unpack(heap_value()?, context)?)
+        }
+        x if x == DiskSymbolValueType::DoubleValue as u8
+            || x == DiskSymbolValueType::OldDoubleValue as u8 =>
+        {
+            Value::DoubleValue(read_double(heap_value()?)?)
+        }
+        x if x == DiskSymbolValueType::StringValue as u8 => {
+            Value::StringValue(read_fixed_string(heap_value()?, context.text_encoding))
+        }
+        x if x == DiskSymbolValueType::PasswordValue as u8 => Value::Password(Password::new(
+            read_fixed_string(heap_value()?, context.text_encoding),
+        )),
+        x if x == DiskSymbolValueType::DirectionValue as u8 => Value::DirectionValue(rec.data[0]),
+        x if x == DiskSymbolValueType::OSTypeValue as u8 => {
+            Value::OSTypeValue(OSType(rec.data.try_into()?))
+        }
+        x if x == DiskSymbolValueType::PointValue as u8 => Value::PointValue(Point::new(rec.data)?),
+        x if x == DiskSymbolValueType::RectValue as u8 => {
+            Value::RectValue(Rect::new(heap_value()?)?)
+        }
+        x if x == DiskSymbolValueType::PatternValue as u8 => {
+            Value::PatternValue(Pattern::new(heap_value()?)?)
+        }
+        x if x == DiskSymbolValueType::RGBValue as u8 => {
+            Value::RGBValue(RGBColor::new(heap_value()?)?)
+        }
+        x if x == DiskSymbolValueType::FixedValue as u8 => {
+            Value::FixedValue(data as i32 as f64 / FIXED_ONE)
+        }
+        x if x == DiskSymbolValueType::SingleValue as u8 => {
+            Value::SingleValue(f32::from_bits(data))
+        }
+        x if x == DiskSymbolValueType::ObjSpecValue as u8 => {
+            Value::ObjSpec(ObjSpec::new(heap_value()?, context.text_encoding)?)
+        }
+        x if x == DiskSymbolValueType::FileSpecValue as u8 => {
+            Value::FileSpec(FileSpec::new(heap_value()?, context)?)
+        }
+        x if x == DiskSymbolValueType::AliasValue as u8 => {
+            Value::FileSpec(FileSpec::from_alias(heap_value()?, context.text_encoding)?)
+        }
+        x if x == DiskSymbolValueType::EnumValue as u8 => {
+            Value::EnumValue(OSType(rec.data.try_into()?))
+        }
+        x if x == DiskSymbolValueType::ListValue as u8 => {
+            Value::ListValue(unpack_list(heap_value()?, context)?)
+        }
+        x if x == DiskSymbolValueType::RecordValue as u8 => {
+            Value::RecordValue(unpack_record(heap_value()?, context)?)
+        }
+        _ => Value::NoValue,
+    };
+
+    Ok(value)
+}
+
+// Only values stored in the heap have contents that can fail to decode.
+fn raw_value(rec: &DiskSymbolRecord, strings: &[u8]) -> Result<Value> {
+    if !DiskSymbolValueType::is_stored_in_heap(rec.value_type) {
+        return Err(Error::from(ODBError::BadPackedValue));
+    }
+
+    let bytes = read_heap_value(strings, u32::from_be_bytes(rec.data.try_into()?))?;
+    Ok(Value::RawValue(rec.value_type, bytes.to_vec()))
+}
+
+pub(crate) fn date_from_mac_seconds(seconds: u32) -> time::SystemTime {
+    let classic_mac_epoch = time::UNIX_EPOCH - CLASSIC_MAC_EPOCH_OFFSET;
+    classic_mac_epoch + time::Duration::from_secs(seconds as u64)
+}
+
+// Dates outside the range a classic Mac can represent are pinned to its ends.
+pub(crate) fn mac_seconds_from_date(date: time::SystemTime) -> u32 {
+    let classic_mac_epoch = time::UNIX_EPOCH - CLASSIC_MAC_EPOCH_OFFSET;
+    date.duration_since(classic_mac_epoch)
+        .map_or(0, |duration| duration.as_secs().min(u32::MAX as u64) as u32)
+}
+
+// Doubles were written by 68K Macs as 80-bit SANE extended values, and as
+// ordinary 64-bit IEEE doubles everywhere else.
+pub(crate) fn read_double(bytes: &[u8]) -> Result<f64> {
+    match bytes.len() {
+        8 => Ok(f64::from_be_bytes(bytes.try_into()?)),
+        10 => {
+            let sign = if bytes[0] & 0x80 != 0 { -1.0 } else { 1.0 };
+            let exponent = (u16::from_be_bytes(bytes[0..2].try_into()?) & 0x7fff) as i32;
+            let mantissa = u64::from_be_bytes(bytes[2..10].try_into()?);
+
+            let magnitude = match exponent {
+                0 if mantissa == 0 => 0.0,
+                0x7fff if mantissa << 1 == 0 => f64::INFINITY,
+                0x7fff => f64::NAN,
+                // The mantissa has an explicit integer bit, so it's scaled by
+                // 2^63 on top of the exponent bias.
+                _ => (mantissa as f64) * 2f64.powi(exponent - 16383 - 63),
+            };
+
+            Ok(sign * magnitude)
+        }
+        _ => Err(Error::from(ODBError::BadPackedValue)),
+    }
+}
+
 fn split_buffer(buffer: &[u8]) -> Result<(&[u8], &[u8])> {
     let u32_size = std::mem::size_of::<u32>();
-    let first_buffer_size = u32::from_be_bytes(buffer[0..u32_size].try_into()?) as usize;
+    let size_bytes = buffer.get(0..u32_size).ok_or(ODBError::BadPackedValue)?;
+    let first_buffer_size = u32::from_be_bytes(size_bytes.try_into()?) as usize;
     let index = u32_size;
-    let first = &buffer[index..(index + first_buffer_size)];
+    let first = buffer
+        .get(index..(index + first_buffer_size))
+        .ok_or(ODBError::BadPackedValue)?;
 
     let index = index + first_buffer_size;
-    let second_buffer_size = buffer.len() - (first_buffer_size + u32_size);
-    let second = &buffer[index..(index + second_buffer_size)];
+    let second = &buffer[index..];
 
     Ok((first, second))
 }
+
+fn heap_slice(strings: &[u8], offset: u32) -> Result<&[u8]> {
+    Ok(strings
+        .get(offset as usize..)
+        .ok_or(ODBError::BadPackedValue)?)
+}
+
+// Values that don't fit in the four data bytes of a DiskSymbolRecord are
+// stored in the string heap as a four byte length followed by the contents of
+// the value's handle.
+fn read_heap_value(strings: &[u8], offset: u32) -> Result<&[u8]> {
+    let u32_size = std::mem::size_of::<u32>();
+    let index = offset as usize;
+
+    let length_bytes = strings
+        .get(index..(index + u32_size))
+        .ok_or(ODBError::BadPackedValue)?;
+    let length = u32::from_be_bytes(length_bytes.try_into()?) as usize;
+
+    let index = index + u32_size;
+    let value = strings
+        .get(index..(index + length))
+        .ok_or(ODBError::BadPackedValue)?;
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text_encoding::TextEncoding;
+
+    // Packs boolean items into the layout unpack_table reads.
+    fn pack_booleans(names: &[&str]) -> Vec<u8> {
+        let mut records = vec![0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut strings = vec![];
+
+        for name in names {
+            records.extend_from_slice(&(strings.len() as u32).to_be_bytes());
+            records.extend_from_slice(&[DiskSymbolValueType::BooleanValue as u8, 0, 1, 0, 0, 0]);
+            strings.push(name.len() as u8);
+            strings.extend_from_slice(name.as_bytes());
+        }
+
+        let mut packed = (records.len() as u32).to_be_bytes().to_vec();
+        packed.extend(records);
+        packed.extend(strings);
+        packed
+    }
+
+    // Packs items stored in the heap into a table block with no formats.
+    fn pack_heap_values(items: &[(&str, DiskSymbolValueType, &[u8])]) -> Vec<u8> {
+        let mut records = vec![0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut strings = vec![];
+
+        for (name, value_type, bytes) in items {
+            records.extend_from_slice(&(strings.len() as u32).to_be_bytes());
+            strings.push(name.len() as u8);
+            strings.extend_from_slice(name.as_bytes());
+
+            records.extend_from_slice(&[*value_type as u8, 0]);
+            records.extend_from_slice(&(strings.len() as u32).to_be_bytes());
+            strings.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+            strings.extend_from_slice(bytes);
+        }
+
+        let mut packed = (records.len() as u32).to_be_bytes().to_vec();
+        packed.extend(records);
+        packed.extend(strings);
+
+        let mut block = (packed.len() as u32).to_be_bytes().to_vec();
+        block.extend(packed);
+        block
+    }
+
+    const SAMPLE_ROOT_TABLE: DBAddress = 0x2a9;
+
+    fn sample_path() -> &'static str {
+        concat!(env!("CARGO_MANIFEST_DIR"), "/../Sample.root")
+    }
+
+    fn mac_context() -> LoadContext {
+        LoadContext::for_platform(Platform::Mac)
+    }
+
+    fn debug_values(table: &Table) -> Vec<String> {
+        table
+            .iter()
+            .map(|(name, node)| format!("{} = {:?}", name, node.value().dump(Redaction::Reveal)))
+            .collect()
+    }
+
+    #[test]
+    fn packs_what_it_unpacks() {
+        let file = std::fs::File::open(sample_path()).unwrap();
+        let mut db = Database::open_file(file, true).unwrap();
+        let block = db.read_block(SAMPLE_ROOT_TABLE).unwrap();
+
+        let table = Table::load_from_bytes(&block, &mac_context()).unwrap();
+        assert_eq!(table.save_to_bytes(&mac_context()).unwrap(), block);
+    }
+
+    #[test]
+    fn edits_survive_packing() {
+        let file = std::fs::File::open(sample_path()).unwrap();
+        let mut db = Database::open_file(file, true).unwrap();
+        let block = db.read_block(SAMPLE_ROOT_TABLE).unwrap();
+        let mut table = Table::load_from_bytes(&block, &mac_context()).unwrap();
+
+        table.insert("age", Value::LongValue(1)).unwrap_err();
+        table.insert("", Value::NoValue).unwrap_err();
+        table.rename("firstName", "MiddleInitial").unwrap_err();
+        table.delete("nothing").unwrap_err();
+        assert!(!table.is_dirty());
+
+        table.set_value("age", Value::LongValue(39)).unwrap();
+        table.rename("lastName", "surname").unwrap();
+        table.delete("pi").unwrap();
+        table
+            .insert(
+                "window",
+                Value::RectValue(Rect::new(&[0, 40, 0, 2, 1, 0, 2, 0]).unwrap()),
+            )
+            .unwrap();
+        table.insert("scale", Value::FixedValue(-1.5)).unwrap();
+        table.insert("ratio", Value::DoubleValue(0.25)).unwrap();
+        table
+            .insert("note", Value::StringValue(String::from("Café")))
+            .unwrap();
+        table.insert("ligature", Value::CharValue('Œ')).unwrap();
+        table
+            .insert(
+                "secret",
+                Value::Password(Password::new(String::from("sésame"))),
+            )
+            .unwrap();
+        table
+            .insert(
+                "code",
+                Value::CodeValue(CodeTree::new(vec![Node::identifier("beep")])),
+            )
+            .unwrap();
+        assert!(table.is_dirty());
+
+        let bytes = table.save_to_bytes(&mac_context()).unwrap();
+        let unpacked = Table::load_from_bytes(&bytes, &mac_context()).unwrap();
+
+        assert_eq!(debug_values(&unpacked), debug_values(&table));
+        assert_eq!(unpacked.get("surname").unwrap().name(), "surname");
+        assert!(unpacked.get("pi").is_none());
+        assert_eq!(unpacked.formats(), table.formats());
+    }
+
+    #[test]
+    fn names_must_fit_once_encoded() {
+        let name = "名".repeat(200);
+        let mut table = Table::new();
+        table.insert(&name, Value::LongValue(1)).unwrap();
+
+        let mut context = mac_context();
+        context.text_encoding = TextEncoding::MacJapanese;
+        match table.save_to_bytes(&context) {
+            Err(Error::ODB(ODBError::InvalidName { name: invalid })) => {
+                assert_eq!(invalid, name)
+            }
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn saving_writes_changed_subtables() {
+        let path =
+            std::env::temp_dir().join(format!("brave-flea-table-save-{}.root", std::process::id()));
+        std::fs::copy(sample_path(), &path).unwrap();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let mut db = Database::open_file(file, false).unwrap();
+        let context = mac_context();
+
+        let mut root = Table::load_system_table(&mut db, &context, SAMPLE_ROOT_TABLE).unwrap();
+        let address = root.save(&mut db, &context, SAMPLE_ROOT_TABLE).unwrap();
+        assert_eq!(address, SAMPLE_ROOT_TABLE);
+
+        let mut prefs = Table::new();
+        prefs
+            .insert("color", Value::StringValue(String::from("red")))
+            .unwrap();
+        root.insert("prefs", Value::TableValue(Box::new(prefs)))
+            .unwrap();
+
+        let address = root.save(&mut db, &context, SAMPLE_ROOT_TABLE).unwrap();
+        assert_ne!(address, SAMPLE_ROOT_TABLE);
+        assert!(!root.has_unsaved_changes());
+        db.flush_release_stack().unwrap();
+        db.flush().unwrap();
+
+        let mut root = Table::load_system_table(&mut db, &context, address).unwrap();
+        match root.load_value("prefs", &mut db, &context).unwrap() {
+            Some(Value::TableValue(prefs)) => {
+                assert!(
+                    matches!(prefs.get("color").unwrap().value(), Value::StringValue(s) if s == "red")
+                );
+            }
+            value => panic!("{:?}", value),
+        }
+
+        // Only the subtable changes, but the root has to be written again
+        // because the subtable moves.
+        if let Some(Value::TableValue(prefs)) = root.load_value("prefs", &mut db, &context).unwrap()
+        {
+            prefs
+                .set_value("color", Value::StringValue(String::from("blue")))
+                .unwrap();
+        }
+        assert!(root.has_unsaved_changes());
+        assert!(!root.is_purgeable());
+        assert_ne!(root.save(&mut db, &context, address).unwrap(), address);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        let packed = pack_booleans(&["Zed", "alpha", "ALPHA", "Beta"]);
+        let mut table = Table::new();
+        table.unpack_table(&packed, &mac_context()).unwrap();
+        table.sort_nodes();
+
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.get("Alpha").unwrap().name(), "alpha");
+        assert_eq!(table.name_collisions(), &[String::from("ALPHA")]);
+
+        let names: Vec<_> = table.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["alpha", "Beta", "Zed"]);
+    }
+
+    #[test]
+    fn undecodable_values_are_kept_raw() {
+        let objspec = DiskSymbolValueType::ObjSpecValue;
+        let block = pack_heap_values(&[("empty", objspec, &[]), ("garbage", objspec, &[1, 2, 3])]);
+
+        let table = Table::load_from_bytes(&block, &mac_context()).unwrap();
+        assert!(
+            matches!(table.get("empty").unwrap().value(), Value::RawValue(25, bytes) if bytes.is_empty())
+        );
+        assert!(
+            matches!(table.get("garbage").unwrap().value(), Value::RawValue(25, bytes) if bytes == &[1, 2, 3])
+        );
+        assert_eq!(table.save_to_bytes(&mac_context()).unwrap(), block);
+
+        // Raw values keep their type when they're packed from scratch.
+        let mut copy = Table::new();
+        copy.insert("garbage", Value::RawValue(25, vec![1, 2, 3]))
+            .unwrap();
+        let bytes = copy.save_to_bytes(&mac_context()).unwrap();
+        let copy = Table::load_from_bytes(&bytes, &mac_context()).unwrap();
+        assert!(
+            matches!(copy.get("garbage").unwrap().value(), Value::RawValue(25, bytes) if bytes == &[1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn damaged_code_is_kept_raw() {
+        let code = DiskSymbolValueType::CodeValue;
+        let mut tree = CodeTree::new(vec![Node::identifier("beep")])
+            .pack(&mac_context())
+            .unwrap();
+        tree.truncate(tree.len() - 1);
+        let block = pack_heap_values(&[("name", code, &[0, 1]), ("script", code, &tree)]);
+
+        let table = Table::load_from_bytes(&block, &mac_context()).unwrap();
+        assert!(
+            matches!(table.get("script").unwrap().value(), Value::RawValue(10, bytes) if *bytes == tree)
+        );
+        assert!(
+            matches!(table.get("name").unwrap().value(), Value::RawValue(10, bytes) if bytes == &[0, 1])
+        );
+        assert_eq!(table.save_to_bytes(&mac_context()).unwrap(), block);
+    }
+
+    #[test]
+    fn damaged_lists_and_records_are_kept_raw() {
+        let list = DiskSymbolValueType::ListValue;
+        let record = DiskSymbolValueType::RecordValue;
+        // Each claims one item but doesn't have it.
+        let block = pack_heap_values(&[
+            ("list", list, &[0, 0, 0, 1]),
+            ("record", record, &[0, 0, 0, 1, 1]),
+        ]);
+
+        let table = Table::load_from_bytes(&block, &mac_context()).unwrap();
+        assert!(
+            matches!(table.get("list").unwrap().value(), Value::RawValue(29, bytes) if bytes == &[0, 0, 0, 1])
+        );
+        assert!(
+            matches!(table.get("record").unwrap().value(), Value::RawValue(30, bytes) if bytes == &[0, 0, 0, 1, 1])
+        );
+        assert_eq!(table.save_to_bytes(&mac_context()).unwrap(), block);
+    }
+
+    #[test]
+    fn short_formats_are_kept_raw() {
+        let mut block = pack_heap_values(&[("name", DiskSymbolValueType::StringValue, b"text")]);
+        block.extend_from_slice(&[0, 0x10, 0, 4]);
+
+        let table = Table::load_from_bytes(&block, &mac_context()).unwrap();
+        assert!(table.formats().is_none());
+        assert_eq!(table.save_to_bytes(&mac_context()).unwrap(), block);
+    }
+
+    #[test]
+    fn corrupt_offsets_are_errors() {
+        let is_bad =
+            |result: Result<Table>| matches!(result, Err(Error::ODB(ODBError::BadPackedValue)));
+
+        let mut block = pack_heap_values(&[("name", DiskSymbolValueType::StringValue, b"text")]);
+        assert!(Table::load_from_bytes(&block, &mac_context()).is_ok());
+
+        // The name's offset, then its length, then the value's offset.
+        let mut bad_name = block.clone();
+        bad_name[24..28].copy_from_slice(&1000u32.to_be_bytes());
+        assert!(is_bad(Table::load_from_bytes(&bad_name, &mac_context())));
+
+        let strings = 4 + 4 + 26;
+        let mut bad_length = block.clone();
+        bad_length[strings] = 200;
+        assert!(is_bad(Table::load_from_bytes(&bad_length, &mac_context())));
+
+        block[30..34].copy_from_slice(&1000u32.to_be_bytes());
+        assert!(is_bad(Table::load_from_bytes(&block, &mac_context())));
+        assert!(is_bad(Table::load_from_bytes(&[0, 0, 1], &mac_context())));
+        assert!(is_bad(Table::load_from_bytes(
+            &[0, 0, 0, 9, 0],
+            &mac_context()
+        )));
+    }
+}
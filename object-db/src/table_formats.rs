@@ -0,0 +1,211 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::error::*;
+use crate::mac_roman::Unmappable;
+use crate::odb_error::*;
+use crate::quickdraw::Rect;
+use crate::string_utils::*;
+use crate::text_encoding::TextEncoding;
+use std::convert::TryInto;
+
+const FONT_NAME_SIZE: usize = 34;
+const MAX_COLUMN_COUNT: usize = 3;
+const TABLE_FORMATS_SIZE: usize = 86;
+
+// How a table looks when it's opened in a window: its font, which row and
+// column have the focus, how wide the name, value and kind columns are, and
+// where the window goes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableFormats {
+    pub version: u16,
+    pub font_name: String,
+    pub font_size: u16,
+    pub font_style: u16,
+    pub selected_row: i16,
+    pub top_row: i16,
+    pub focus_column: i16,
+    // Only the first three columns' widths fit in the record.
+    pub column_widths: Vec<i16>,
+    pub window_rect: Rect,
+    // The column count as it was read, which can be more than the number of
+    // widths there's room for.
+    column_count: u16,
+    reserved: Vec<u8>,
+}
+
+impl TableFormats {
+    pub fn new(buffer: &[u8], encoding: TextEncoding) -> Result<Self> {
+        if buffer.len() < TABLE_FORMATS_SIZE {
+            return Err(Error::from(ODBError::BadPackedValue));
+        }
+
+        let version = u16::from_be_bytes(buffer[0..2].try_into()?);
+        // ignore bytes 2 - 3 (short recordsize)
+        let font_name = read_pascal_string(&buffer[4..(4 + FONT_NAME_SIZE)], encoding)?;
+        let font_size = u16::from_be_bytes(buffer[38..40].try_into()?);
+        let font_style = u16::from_be_bytes(buffer[40..42].try_into()?);
+        let selected_row = i16::from_be_bytes(buffer[42..44].try_into()?);
+        let top_row = i16::from_be_bytes(buffer[44..46].try_into()?);
+        let column_count = u16::from_be_bytes(buffer[46..48].try_into()?);
+        let focus_column = i16::from_be_bytes(buffer[48..50].try_into()?);
+
+        // bytes 50 - 55
+        let mut column_widths = vec![];
+        for i in 0..(column_count as usize).min(MAX_COLUMN_COUNT) {
+            let index = 50 + (i * 2);
+            column_widths.push(i16::from_be_bytes(buffer[index..(index + 2)].try_into()?));
+        }
+
+        let window_rect = Rect::new(&buffer[56..64])?;
+
+        // bytes 64 - 85 are unused, but they're kept so they can be written
+        // back exactly as they were read.
+        let reserved = buffer[64..].to_vec();
+
+        Ok(Self {
+            version,
+            font_name,
+            font_size,
+            font_style,
+            selected_row,
+            top_row,
+            focus_column,
+            column_widths,
+            window_rect,
+            column_count,
+            reserved,
+        })
+    }
+
+    pub fn to_bytes(&self, encoding: TextEncoding, unmappable: Unmappable) -> Result<Vec<u8>> {
+        let size = 64 + self.reserved.len();
+        let mut bytes = Vec::with_capacity(size);
+
+        bytes.extend_from_slice(&self.version.to_be_bytes());
+        bytes.extend_from_slice(&(size as u16).to_be_bytes());
+        bytes.extend(write_pascal_string(
+            &self.font_name,
+            FONT_NAME_SIZE,
+            encoding,
+            unmappable,
+        )?);
+        bytes.extend_from_slice(&self.font_size.to_be_bytes());
+        bytes.extend_from_slice(&self.font_style.to_be_bytes());
+        bytes.extend_from_slice(&self.selected_row.to_be_bytes());
+        bytes.extend_from_slice(&self.top_row.to_be_bytes());
+
+        // Keep the count that was read unless the widths were changed.
+        let column_count = self.column_widths.len().min(MAX_COLUMN_COUNT);
+        let stored_count = if column_count == (self.column_count as usize).min(MAX_COLUMN_COUNT) {
+            self.column_count
+        } else {
+            column_count as u16
+        };
+        bytes.extend_from_slice(&stored_count.to_be_bytes());
+        bytes.extend_from_slice(&self.focus_column.to_be_bytes());
+        for i in 0..MAX_COLUMN_COUNT {
+            let width = if i < column_count {
+                self.column_widths[i]
+            } else {
+                0
+            };
+            bytes.extend_from_slice(&width.to_be_bytes());
+        }
+
+        bytes.extend_from_slice(&self.window_rect.to_bytes());
+        bytes.extend_from_slice(&self.reserved);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const SAMPLE_FORMATS: [u8; 86] = [
+        0x00, 0x10, 0x00, 0x56, 0x06, b'G', b'e', b'n', b'e', b'v', b'a', 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x03,
+        0x00, 0x01, 0x00, 0xfb, 0x00, 0xa6, 0x00, 0x32, 0x01, 0x81, 0x02, 0x08, 0x02, 0x4a, 0x04, 0x09,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn decodes_sample_formats() {
+        let formats = TableFormats::new(&SAMPLE_FORMATS, TextEncoding::MacRoman).unwrap();
+        assert_eq!(formats.font_name, "Geneva");
+        assert_eq!(formats.font_size, 12);
+        assert_eq!(formats.selected_row, 6);
+        assert_eq!(formats.focus_column, 1);
+        assert_eq!(formats.column_widths, vec![251, 166, 50]);
+        assert_eq!(
+            formats.window_rect,
+            Rect {
+                top: 385,
+                left: 520,
+                bottom: 586,
+                right: 1033
+            }
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut formats = TableFormats::new(&SAMPLE_FORMATS, TextEncoding::MacRoman).unwrap();
+        assert_eq!(
+            formats
+                .to_bytes(TextEncoding::MacRoman, Unmappable::Fail)
+                .unwrap(),
+            SAMPLE_FORMATS.to_vec()
+        );
+
+        formats.column_widths[1] = 200;
+        let edited = TableFormats::new(
+            &formats
+                .to_bytes(TextEncoding::MacRoman, Unmappable::Fail)
+                .unwrap(),
+            TextEncoding::MacRoman,
+        )
+        .unwrap();
+        assert_eq!(edited, formats);
+    }
+
+    #[test]
+    fn extra_columns_are_kept() {
+        let mut bytes = SAMPLE_FORMATS;
+        bytes[47] = 5;
+
+        let mut formats = TableFormats::new(&bytes, TextEncoding::MacRoman).unwrap();
+        assert_eq!(formats.column_widths, vec![251, 166, 50]);
+        assert_eq!(
+            formats
+                .to_bytes(TextEncoding::MacRoman, Unmappable::Fail)
+                .unwrap(),
+            bytes.to_vec()
+        );
+
+        formats.column_widths.truncate(2);
+        let edited = formats
+            .to_bytes(TextEncoding::MacRoman, Unmappable::Fail)
+            .unwrap();
+        assert_eq!(u16::from_be_bytes(edited[46..48].try_into().unwrap()), 2);
+    }
+}
@@ -16,52 +16,295 @@ You should have received a copy of the GNU General Public License
 along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use crate::error::*;
+use crate::menubar::*;
+use crate::odb_error::*;
+use crate::table::*;
 use crate::value_record::*;
-use std::rc::{Rc, Weak};
+use crate::variable::*;
+use db::{DBAddress, Database, NIL_DB_ADDRESS};
 
 #[derive(Debug)]
 pub struct TableNode {
     // next_node: Option<Rc<Box<TableNode>>>,
     // next_sorted_node: Option<Weak<Box<TableNode>>>,
-    // value: ValueRecord, // TODO: make value type
     value: Value,
-    dont_save: bool,
     is_locked: bool,
     is_protected: bool,
-    is_unresolved_address: bool,
-    is_dispose_when_unlocked: bool,
-    locks_count: u8,
-    hash_key: &'static str,
+    hash_key: String,
+    external_type: Option<ExternalType>,
+    old_address: DBAddress,
+    packed: Option<PackedValue>,
 }
 
 impl TableNode {
-    pub fn new(value: Value) -> Self {
+    pub fn new(name: String, value: Value) -> Self {
         Self {
             value,
-            dont_save: false,
             is_locked: false,
             is_protected: false,
-            is_unresolved_address: false,
-            is_dispose_when_unlocked: false,
-            locks_count: 0,
-            hash_key: "",
+            hash_key: name,
+            external_type: None,
+            old_address: NIL_DB_ADDRESS,
+            packed: None,
         }
     }
-    fn test(&mut self, node: Rc<Box<TableNode>>) {
-        // let node = TableNode {
-        //     next_node: None,
-        //     next_sorted_node: None,
-        //     value: 0,
-        //     dont_save: false,
-        //     is_locked: false,
-        //     is_protected: false,
-        //     is_unresolved_address: false,
-        //     is_dispose_when_unlocked: false,
-        //     locks_count: 0,
-        //     hash_key: [0; 255],
-        // };
-
-        // self.next_node = Some(Rc::clone(&node));
-        // self.next_sorted_node = Some(Rc::downgrade(&node));
+
+    // Keeps the record the value was unpacked from so it can be written back
+    // unchanged, including values of types that can't be decoded yet.
+    pub(crate) fn new_unpacked(name: String, value: Value, packed: PackedValue) -> Self {
+        let mut node = Self::new(name, value);
+        node.packed = Some(packed);
+        node
+    }
+
+    pub fn new_on_disk(name: String, external_type: ExternalType, address: DBAddress) -> Self {
+        let mut node = Self::new(name, Value::DiskValue(address));
+        node.external_type = Some(external_type);
+        node.old_address = address;
+        node
+    }
+
+    // The item's name as it was typed, in its original case.
+    pub fn name(&self) -> &str {
+        &self.hash_key
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    // Tables keep track of their own changes, so only they are changed in
+    // place. Other values are replaced through Table::set_value.
+    pub(crate) fn value_mut(&mut self) -> &mut Value {
+        &mut self.value
+    }
+
+    pub(crate) fn set_name(&mut self, name: String) {
+        self.hash_key = name;
+    }
+
+    // Replaces the value, returning a node that holds the old one so the
+    // blocks it was stored in can be released when the table is saved.
+    pub(crate) fn set_value(&mut self, value: Value) -> TableNode {
+        let mut old_node = TableNode::new(
+            self.hash_key.clone(),
+            std::mem::replace(&mut self.value, value),
+        );
+        old_node.external_type = self.external_type.take();
+        old_node.old_address = std::mem::replace(&mut self.old_address, NIL_DB_ADDRESS);
+        self.packed = None;
+        old_node
+    }
+
+    pub(crate) fn packed_value(&self) -> Option<&PackedValue> {
+        self.packed.as_ref()
+    }
+
+    pub(crate) fn old_address(&self) -> DBAddress {
+        self.old_address
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.is_locked
+    }
+
+    pub fn is_protected(&self) -> bool {
+        self.is_protected
+    }
+
+    pub fn external_type(&self) -> Option<ExternalType> {
+        self.external_type
+    }
+
+    // Frontier's number for the type of the node's value, taking the kind of
+    // external values that haven't been loaded yet into account.
+    pub fn value_type(&self) -> u8 {
+        match (&self.value, self.external_type) {
+            (Value::DiskValue(_), Some(external_type)) => {
+                DiskSymbolValueType::of_external(external_type) as u8
+            }
+            (value, _) => DiskSymbolValueType::of(value),
+        }
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        !matches!(self.value, Value::DiskValue(_))
+    }
+
+    // External values are read from the database the first time they're
+    // needed.
+    pub fn load_value(&mut self, db: &mut Database, context: &LoadContext) -> Result<&mut Value> {
+        if let Value::DiskValue(address) = self.value {
+            self.value = match self.external_type {
+                Some(ExternalType::Table) => {
+                    let variable = Variable::<Table>::new_on_disk(db, context, address);
+                    Value::TableValue(Box::new(variable.into_in_memory()?.data))
+                }
+                Some(external_type @ ExternalType::Outline) => {
+                    load_external(db, context, address, external_type, Value::Outline)?
+                }
+                Some(external_type @ ExternalType::Script) => {
+                    load_external(db, context, address, external_type, Value::Script)?
+                }
+                Some(ExternalType::Menubar) => load_menubar(db, context, address)?,
+                Some(external_type @ ExternalType::Picture) => {
+                    load_external(db, context, address, external_type, Value::Picture)?
+                }
+                Some(external_type) => {
+                    let variable = Variable::<Vec<u8>>::new_on_disk(db, context, address);
+                    Value::ExternalValue(external_type, variable.into_in_memory()?.data)
+                }
+                None => return Ok(&mut self.value),
+            };
+        }
+
+        Ok(&mut self.value)
+    }
+
+    pub(crate) fn detach(&mut self) {
+        self.old_address = NIL_DB_ADDRESS;
+
+        if let Value::TableValue(table) = &mut self.value {
+            table.detach();
+        }
+    }
+
+    pub(crate) fn has_unsaved_changes(&self) -> bool {
+        match &self.value {
+            Value::TableValue(table) => {
+                self.old_address == NIL_DB_ADDRESS || table.has_unsaved_changes()
+            }
+            Value::ExternalValue(_, _)
+            | Value::Outline(_)
+            | Value::Script(_)
+            | Value::WpText(_)
+            | Value::Menubar(_)
+            | Value::Picture(_) => self.old_address == NIL_DB_ADDRESS,
+            _ => false,
+        }
+    }
+
+    // Writes a table or external value that has changed to a new block.
+    // Returns true if the value was moved, which means the table holding the
+    // node has to be written too.
+    pub(crate) fn save(&mut self, db: &mut Database, context: &LoadContext) -> Result<bool> {
+        let address = match &mut self.value {
+            Value::TableValue(table) => table.save(db, context, self.old_address)?,
+            Value::ExternalValue(_, data) if self.old_address == NIL_DB_ADDRESS => {
+                save_external(db, context, data)?
+            }
+            Value::Outline(outline) if self.old_address == NIL_DB_ADDRESS => {
+                save_external(db, context, outline)?
+            }
+            Value::Script(script) if self.old_address == NIL_DB_ADDRESS => {
+                save_external(db, context, script)?
+            }
+            Value::WpText(_) if self.old_address == NIL_DB_ADDRESS => {
+                return Err(Error::from(ODBError::CantSaveWpText));
+            }
+            Value::Menubar(menubar) if self.old_address == NIL_DB_ADDRESS => {
+                menubar.save_scripts(db, context)?;
+                save_external(db, context, menubar)?
+            }
+            Value::Picture(picture) if self.old_address == NIL_DB_ADDRESS => {
+                save_external(db, context, picture)?
+            }
+            _ => return Ok(false),
+        };
+
+        let is_moved = address != self.old_address;
+        self.old_address = address;
+        Ok(is_moved)
+    }
+
+    // Queues the blocks the value is stored in to be released, along with the
+    // blocks of everything in it if it's a table, or of its scripts if it's a
+    // menubar.
+    pub(crate) fn release(mut self, db: &mut Database, context: &LoadContext) -> Result<()> {
+        if matches!(
+            self.external_type,
+            Some(ExternalType::Table) | Some(ExternalType::Menubar)
+        ) {
+            self.load_value(db, context)?;
+        }
+
+        match self.value {
+            Value::TableValue(table) => table.release(db, context)?,
+            // A menubar that was never saved may still point at the scripts
+            // of the one it was copied from.
+            Value::Menubar(menubar) if self.old_address != NIL_DB_ADDRESS => {
+                menubar.release_scripts(db)
+            }
+            _ => {}
+        }
+
+        db.push_release_stack(self.old_address);
+        Ok(())
+    }
+
+    // Drops a loaded external value that hasn't changed since it was read,
+    // leaving just its address behind so it can be loaded again.
+    pub fn purge(&mut self) {
+        let is_purgeable = match &mut self.value {
+            Value::TableValue(table) => {
+                table.purge();
+                table.is_purgeable()
+            }
+            Value::ExternalValue(_, _)
+            | Value::Outline(_)
+            | Value::Script(_)
+            | Value::WpText(_)
+            | Value::Menubar(_)
+            | Value::Picture(_) => true,
+            _ => false,
+        };
+
+        if is_purgeable && self.old_address != NIL_DB_ADDRESS {
+            self.value = Value::DiskValue(self.old_address);
+        }
     }
 }
+
+// Reads an external value and decodes it. If it can't be decoded, its bytes
+// are kept as they were read, so the rest of the table still loads and the
+// value is written back unchanged.
+fn load_external<T: LoadFromBytes>(
+    db: &mut Database,
+    context: &LoadContext,
+    address: DBAddress,
+    external_type: ExternalType,
+    to_value: impl FnOnce(T) -> Value,
+) -> Result<Value> {
+    let variable = Variable::<Vec<u8>>::new_on_disk(db, context, address);
+    let bytes = variable.into_in_memory()?.data;
+    match T::load_from_bytes(&bytes, context) {
+        Ok(data) => Ok(to_value(data)),
+        Err(_) => Ok(Value::ExternalValue(external_type, bytes)),
+    }
+}
+
+// Reads a menubar along with the scripts of its commands, which are kept in
+// blocks of their own. If either can't be decoded, the menubar's bytes are
+// kept as they were read.
+fn load_menubar(db: &mut Database, context: &LoadContext, address: DBAddress) -> Result<Value> {
+    let variable = Variable::<Vec<u8>>::new_on_disk(db, context, address);
+    let bytes = variable.into_in_memory()?.data;
+    let menubar = Menubar::unpack(&bytes, context).and_then(|mut menubar| {
+        menubar.load_scripts(db, context)?;
+        Ok(menubar)
+    });
+    match menubar {
+        Ok(menubar) => Ok(Value::Menubar(menubar)),
+        Err(_) => Ok(Value::ExternalValue(ExternalType::Menubar, bytes)),
+    }
+}
+
+// Writes an external value to a new block, returning its address.
+fn save_external<T: SaveToBytes>(
+    db: &mut Database,
+    context: &LoadContext,
+    data: &mut T,
+) -> Result<DBAddress> {
+    save_value(db, context, data, NIL_DB_ADDRESS)
+}
@@ -0,0 +1,242 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::error::*;
+use crate::mac_roman::*;
+use crate::odb_error::*;
+use crate::variable::Platform;
+use encoding_rs::{Encoding, SHIFT_JIS, WINDOWS_1252};
+use std::convert::TryFrom;
+
+// Single bytes that Apple's MacJapanese encoding adds to Shift-JIS. None of
+// them can start or end a two byte character. The vendor characters Apple
+// added in rows 0x85, 0x86 and 0xEB - 0xED aren't supported and decode as
+// U+FFFD.
+const MAC_JAPANESE_EXTRAS: [(u8, char); 4] = [
+    (0xA0, '\u{00A0}'),
+    (0xFD, '\u{00A9}'),
+    (0xFE, '\u{2122}'),
+    (0xFF, '\u{2026}'),
+];
+
+// The character set strings are stored in. Frontier always used the system's
+// encoding, so it depends on the platform and language of the build that
+// wrote the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextEncoding {
+    #[default]
+    MacRoman,
+    Windows1252,
+    Latin1,
+    MacJapanese,
+}
+
+impl TextEncoding {
+    // The encoding an English build of Frontier on the platform used. Roots
+    // written by other builds have to be opened with their encoding.
+    pub fn from_platform(platform: Platform) -> Self {
+        match platform {
+            Platform::Mac => TextEncoding::MacRoman,
+            Platform::Windows => TextEncoding::Windows1252,
+        }
+    }
+
+    pub fn from_system_id(system_id: u8) -> Self {
+        Self::from_platform(Platform::from_system_id(system_id))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextEncoding::MacRoman => "MacRoman",
+            TextEncoding::Windows1252 => "Windows-1252",
+            TextEncoding::Latin1 => "Latin-1",
+            TextEncoding::MacJapanese => "MacJapanese",
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            TextEncoding::MacRoman => decode_mac_roman(bytes),
+            TextEncoding::Windows1252 => decode_with(WINDOWS_1252, bytes),
+            TextEncoding::Latin1 => bytes.iter().map(|byte| *byte as char).collect(),
+            TextEncoding::MacJapanese => decode_mac_japanese(bytes),
+        }
+    }
+
+    // Frontier's char values are a single byte, whatever the encoding.
+    pub fn decode_byte(&self, byte: u8) -> char {
+        self.decode(&[byte]).chars().next().unwrap_or('\u{FFFD}')
+    }
+
+    pub fn encode_byte(&self, c: char) -> Option<u8> {
+        let mut bytes = vec![];
+        match self.encode_char(c, &mut bytes) && bytes.len() == 1 {
+            true => Some(bytes[0]),
+            false => None,
+        }
+    }
+
+    pub fn encode(&self, string: &str, unmappable: Unmappable) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+
+        for c in string.chars() {
+            if self.encode_char(c, &mut bytes) {
+                continue;
+            }
+
+            match unmappable {
+                Unmappable::Replace(byte) => bytes.push(byte),
+                Unmappable::Fail => {
+                    return Err(Error::from(ODBError::UnmappableCharacter {
+                        character: c,
+                        encoding: *self,
+                    }))
+                }
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    // Appends the bytes for the character, returning false if the encoding
+    // doesn't have it.
+    fn encode_char(&self, c: char, bytes: &mut Vec<u8>) -> bool {
+        let encoded = match self {
+            TextEncoding::MacRoman => encode_mac_roman_char(c).map(|byte| vec![byte]),
+            TextEncoding::Windows1252 => encode_with(WINDOWS_1252, c),
+            TextEncoding::Latin1 => u8::try_from(c as u32).ok().map(|byte| vec![byte]),
+            TextEncoding::MacJapanese => MAC_JAPANESE_EXTRAS
+                .iter()
+                .find(|(_, extra)| *extra == c)
+                .map(|(byte, _)| vec![*byte])
+                .or_else(|| encode_with(SHIFT_JIS, c)),
+        };
+
+        match encoded {
+            Some(encoded) => {
+                bytes.extend(encoded);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn decode_with(encoding: &'static Encoding, bytes: &[u8]) -> String {
+    encoding.decode_without_bom_handling(bytes).0.into_owned()
+}
+
+fn encode_with(encoding: &'static Encoding, c: char) -> Option<Vec<u8>> {
+    let mut buffer = [0; 4];
+    let (bytes, _, had_errors) = encoding.encode(c.encode_utf8(&mut buffer));
+
+    if had_errors {
+        None
+    } else {
+        Some(bytes.into_owned())
+    }
+}
+
+fn decode_mac_japanese(bytes: &[u8]) -> String {
+    let mut string = String::new();
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let byte = bytes[index];
+        let length = match byte {
+            0x81..=0x9F | 0xE0..=0xFC => 2,
+            _ => 1,
+        };
+
+        match MAC_JAPANESE_EXTRAS.iter().find(|(extra, _)| *extra == byte) {
+            Some((_, c)) => string.push(*c),
+            None => {
+                let end = (index + length).min(bytes.len());
+                string.push_str(&decode_with(SHIFT_JIS, &bytes[index..end]));
+            }
+        }
+
+        index += length;
+    }
+
+    string
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_each_encoding() {
+        let bytes = [b'C', b'a', b'f', 0x8E, 0x80, 0xA0];
+        assert_eq!(TextEncoding::MacRoman.decode(&bytes), "Café\u{C4}\u{2020}");
+        assert_eq!(
+            TextEncoding::Windows1252.decode(&bytes),
+            "Caf\u{17D}\u{20AC}\u{A0}"
+        );
+        assert_eq!(TextEncoding::Latin1.decode(&bytes), "Caf\u{8E}\u{80}\u{A0}");
+
+        let bytes = [0x93, 0xFA, 0x96, 0x7B, b' ', 0xFD, 0xFF];
+        assert_eq!(TextEncoding::MacJapanese.decode(&bytes), "日本 ©…");
+        assert_eq!(TextEncoding::MacJapanese.decode_byte(0xB1), 'ｱ');
+        assert_eq!(TextEncoding::MacJapanese.encode_byte('日'), None);
+    }
+
+    #[test]
+    fn every_single_byte_round_trips() {
+        let bytes: Vec<u8> = (0..=255).collect();
+
+        for encoding in [
+            TextEncoding::MacRoman,
+            TextEncoding::Windows1252,
+            TextEncoding::Latin1,
+        ] {
+            let string = encoding.decode(&bytes);
+            assert_eq!(encoding.encode(&string, Unmappable::Fail).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn japanese_text_round_trips() {
+        let string = "フロンティアのルート™";
+        let bytes = TextEncoding::MacJapanese
+            .encode(string, Unmappable::Fail)
+            .unwrap();
+        assert_eq!(&bytes[0..2], [0x83, 0x74]);
+        assert_eq!(*bytes.last().unwrap(), 0xFE);
+        assert_eq!(TextEncoding::MacJapanese.decode(&bytes), string);
+    }
+
+    #[test]
+    fn unmappable_characters_name_the_encoding() {
+        assert_eq!(
+            TextEncoding::Latin1
+                .encode("€1", Unmappable::default())
+                .unwrap(),
+            b"?1"
+        );
+
+        match TextEncoding::Windows1252.encode("日", Unmappable::Fail) {
+            Err(Error::ODB(error)) => assert_eq!(
+                error.to_string(),
+                "\"日\" (U+65E5) can't be written as a Windows-1252 character."
+            ),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
@@ -16,41 +16,133 @@ You should have received a copy of the GNU General Public License
 along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use crate::variable::*;
-use db;
+use crate::code_tree::*;
+use crate::file_spec::*;
+use crate::menubar::*;
+use crate::obj_spec::*;
+use crate::outline::*;
+use crate::password::*;
+use crate::pict::*;
+use crate::quickdraw::*;
+use crate::script::*;
+use crate::table::*;
+use crate::wptext::*;
+use std::fmt;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Value {
     Uninitialized,
     NoValue,
     CharValue(char),
-    IntValue(i32),
+    IntValue(i16),
+    LongValue(i32),
     BinaryValue(Vec<u8>),
     BooleanValue(bool),
     DateValue(std::time::SystemTime),
-    DoubleValue(f32),
+    AddressValue(String),
+    CodeValue(CodeTree),
+    DoubleValue(f64),
     StringValue(String),
-    ExternalValue,
+    Password(Password),
+    DirectionValue(u8),
+    OSTypeValue(OSType),
+    PointValue(Point),
+    RectValue(Rect),
+    PatternValue(Pattern),
+    RGBValue(RGBColor),
+    FixedValue(f64),
+    SingleValue(f32),
+    EnumValue(OSType),
+    FileSpec(FileSpec),
+    ListValue(Vec<Value>),
+    RecordValue(Vec<(String, Value)>),
+    ObjSpec(ObjSpec),
+    TableValue(Box<Table>),
+    Outline(Outline),
+    Script(Script),
+    WpText(WpText),
+    Menubar(Menubar),
+    Picture(Picture),
+    ExternalValue(ExternalType, Vec<u8>),
+    // A value that couldn't be decoded, as its type number and the bytes it
+    // was stored as, so it can be saved unchanged.
+    RawValue(u8, Vec<u8>),
     DiskValue(db::DBAddress),
 }
 
-#[derive(Debug)]
-pub struct ValueRecord {
-    value: Value,
-    is_tmp_data: bool,
-    is_tmp_stack: bool,
-    is_formal_val: bool,
-    is_disk_val: bool,
+impl Value {
+    // Dumps the value for reading, with its Debug formatting, except that
+    // passwords are only shown when the redaction policy reveals them.
+    pub fn dump(&self, redaction: Redaction) -> Dump<'_> {
+        Dump {
+            value: self,
+            redaction,
+        }
+    }
+}
+
+pub struct Dump<'a> {
+    value: &'a Value,
+    redaction: Redaction,
+}
+
+impl fmt::Debug for Dump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redaction = self.redaction;
+        match self.value {
+            Value::Password(password) if redaction == Redaction::Reveal => {
+                f.debug_tuple("Password").field(&password.reveal()).finish()
+            }
+            Value::ListValue(items) => f
+                .debug_list()
+                .entries(items.iter().map(|item| item.dump(redaction)))
+                .finish(),
+            Value::RecordValue(items) => f
+                .debug_map()
+                .entries(
+                    items
+                        .iter()
+                        .map(|(name, item)| (name, item.dump(redaction))),
+                )
+                .finish(),
+            Value::TableValue(table) => f
+                .debug_map()
+                .entries(
+                    table
+                        .iter()
+                        .map(|(name, node)| (name, node.value().dump(redaction))),
+                )
+                .finish(),
+            value => fmt::Debug::fmt(value, f),
+        }
+    }
+}
+
+// The kinds of external values, numbered the way Frontier numbers its external
+// processors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExternalType {
+    Outline = 0,
+    WordProcessor = 1,
+    Head = 2,
+    Table = 3,
+    Script = 4,
+    Menubar = 5,
+    Picture = 6,
 }
 
-impl ValueRecord {
-    pub fn new() -> Self {
-        Self {
-            value: Value::Uninitialized,
-            is_tmp_data: false,
-            is_tmp_stack: false,
-            is_formal_val: false,
-            is_disk_val: false,
+impl ExternalType {
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(ExternalType::Outline),
+            1 => Some(ExternalType::WordProcessor),
+            2 => Some(ExternalType::Head),
+            3 => Some(ExternalType::Table),
+            4 => Some(ExternalType::Script),
+            5 => Some(ExternalType::Menubar),
+            6 => Some(ExternalType::Picture),
+            _ => None,
         }
     }
 }
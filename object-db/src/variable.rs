@@ -17,17 +17,97 @@ along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
 use crate::error::*;
+use crate::mac_roman::Unmappable;
+use crate::odb_error::*;
+use crate::text_encoding::TextEncoding;
 use db::{DBAddress, Database};
 
+// Database::system_id records which platform's build of Frontier created the
+// file.
+const WINDOWS_SYSTEM_ID: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Mac,
+    Windows,
+}
+
+impl Platform {
+    pub fn from_system_id(system_id: u8) -> Self {
+        match system_id {
+            WINDOWS_SYSTEM_ID => Platform::Windows,
+            _ => Platform::Mac,
+        }
+    }
+}
+
+// Information about the database a value is being loaded from that affects how
+// its bytes are interpreted.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadContext {
+    pub platform: Platform,
+    pub text_encoding: TextEncoding,
+    // What to do when a string being saved has characters the text encoding
+    // doesn't have. Saving fails by default, rather than losing them.
+    pub unmappable: Unmappable,
+}
+
+impl LoadContext {
+    pub fn new(db: &Database) -> Self {
+        Self::for_platform(Platform::from_system_id(db.get_system_id()))
+    }
+
+    pub fn for_platform(platform: Platform) -> Self {
+        Self {
+            platform,
+            text_encoding: TextEncoding::from_platform(platform),
+            unmappable: Unmappable::Fail,
+        }
+    }
+
+    pub fn encode(&self, string: &str) -> Result<Vec<u8>> {
+        self.text_encoding.encode(string, self.unmappable)
+    }
+
+    // Char values are a single byte, so a character that takes two bytes in
+    // the encoding is as unmappable as one it doesn't have.
+    pub fn encode_byte(&self, c: char) -> Result<u8> {
+        match (self.text_encoding.encode_byte(c), self.unmappable) {
+            (Some(byte), _) => Ok(byte),
+            (None, Unmappable::Replace(byte)) => Ok(byte),
+            (None, Unmappable::Fail) => Err(Error::from(ODBError::UnmappableCharacter {
+                character: c,
+                encoding: self.text_encoding,
+            })),
+        }
+    }
+}
+
 pub trait LoadFromBytes {
-    fn load_from_bytes(bytes: &[u8]) -> Result<Self>
+    fn load_from_bytes(bytes: &[u8], context: &LoadContext) -> Result<Self>
     where
         Self: std::marker::Sized;
 }
 
+pub trait SaveToBytes {
+    fn save_to_bytes(&self, context: &LoadContext) -> Result<Vec<u8>>;
+}
+
+// External values we don't know how to decode are kept as their packed bytes.
+impl LoadFromBytes for Vec<u8> {
+    fn load_from_bytes(bytes: &[u8], _context: &LoadContext) -> Result<Self> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl SaveToBytes for Vec<u8> {
+    fn save_to_bytes(&self, _context: &LoadContext) -> Result<Vec<u8>> {
+        Ok(self.clone())
+    }
+}
+
 pub struct InMemoryValue<T: LoadFromBytes> {
     pub data: T,
-    old_address: DBAddress,
 }
 
 pub enum VariableData<T: LoadFromBytes> {
@@ -37,28 +117,17 @@ pub enum VariableData<T: LoadFromBytes> {
 
 pub struct Variable<'a, T: LoadFromBytes> {
     // id: u16,
-    may_affect_display: bool,
-    is_system_table: bool,
     pub data: VariableData<T>,
     db: &'a mut Database,
+    context: LoadContext,
 }
 
 impl<'a, T: LoadFromBytes> Variable<'a, T> {
-    pub fn new_on_disk(db: &'a mut Database, address: DBAddress) -> Self {
+    pub fn new_on_disk(db: &'a mut Database, context: &LoadContext, address: DBAddress) -> Self {
         Self {
-            may_affect_display: false,
-            is_system_table: false,
             data: VariableData::OnDisk(address),
             db,
-        }
-    }
-
-    pub fn new_in_memory(db: &'a mut Database, data: T, old_address: DBAddress) -> Self {
-        Self {
-            may_affect_display: false,
-            is_system_table: false,
-            data: VariableData::InMemory(InMemoryValue { data, old_address }),
-            db,
+            context: *context,
         }
     }
 
@@ -66,11 +135,8 @@ impl<'a, T: LoadFromBytes> Variable<'a, T> {
         match self.data {
             VariableData::OnDisk(address) => {
                 let block = self.db.read_block(address)?;
-                let data = T::load_from_bytes(&block)?;
-                self.data = VariableData::InMemory(InMemoryValue {
-                    data,
-                    old_address: address,
-                });
+                let data = T::load_from_bytes(&block, &self.context)?;
+                self.data = VariableData::InMemory(InMemoryValue { data });
             }
             VariableData::InMemory(_) => {
                 // nothing to do, it's already in memrory
@@ -80,6 +146,15 @@ impl<'a, T: LoadFromBytes> Variable<'a, T> {
         Ok(())
     }
 
+    pub fn into_in_memory(mut self) -> Result<InMemoryValue<T>> {
+        self.load_from_disk()?;
+
+        match self.data {
+            VariableData::InMemory(value) => Ok(value),
+            VariableData::OnDisk(_) => unreachable!("load_from_disk leaves the variable in memory"),
+        }
+    }
+
     // pub fn load_from_disk(&mut self) -> Result<()> {
     //     match self.data {
     //         VariableData::OnDisk(address) => {
@@ -99,6 +174,22 @@ impl<'a, T: LoadFromBytes> Variable<'a, T> {
     // }
 }
 
+// Writes the value to a new block. The block it was loaded from is pushed onto
+// the release stack rather than released, so the data on disk stays intact
+// until the save is committed. The value is only borrowed, so it's still there
+// if it can't be packed.
+pub fn save_value<T: SaveToBytes>(
+    db: &mut Database,
+    context: &LoadContext,
+    value: &T,
+    old_address: DBAddress,
+) -> Result<DBAddress> {
+    let bytes = value.save_to_bytes(context)?;
+    let address = db.allocate(&bytes)?;
+    db.push_release_stack(old_address);
+    Ok(address)
+}
+
 // impl<'a> Variable<'a, Vec<u8>> {
 //     pub fn load_from_disk(&mut self) -> Result<()> {
 //         match self.data {
@@ -15,61 +15,227 @@ GNU General Public License for more details.
 You should have received a copy of the GNU General Public License
 along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
-
 use crate::error::*;
+use crate::mac_roman::Unmappable;
+use crate::odb_error::*;
+use crate::quickdraw::Rect;
 use crate::string_utils::*;
+use crate::text_encoding::TextEncoding;
 use std::convert::TryInto;
 
 pub const WINDOW_INFO_SIZE: u32 = 62;
 
-#[derive(Debug)]
-struct Rect {
-    pub top: u16,
-    pub left: u16,
-    pub bottom: u16,
-    pub right: u16,
-}
+const FONT_NAME_SIZE: usize = 34;
 
-#[derive(Debug)]
+// Where one of the root's windows goes and the font it's displayed in.
+#[derive(Debug, Clone, PartialEq)]
 pub struct WindowInfo {
     window_rect: Rect,
-    font_string: String,
+    font_name: String,
     font_number: u16,
     font_size: u16,
     font_style: u16,
+    // The WindowPtr of the window when the record was saved. It means nothing
+    // once the file is closed, but it's kept so it can be written back.
+    window_ref: u32,
     is_hidden: bool,
     is_unused: bool,
+    reserved: [u8; 8],
 }
 
 impl WindowInfo {
-    pub fn new(buffer: &[u8]) -> Result<WindowInfo> {
-        let top = u16::from_be_bytes(buffer[0..2].try_into()?);
-        let left = u16::from_be_bytes(buffer[2..4].try_into()?);
-        let bottom = u16::from_be_bytes(buffer[4..6].try_into()?);
-        let right = u16::from_be_bytes(buffer[6..8].try_into()?);
-        let font_string = read_pascal_string(&buffer[8..41]); // 33 byte pascal string starting at 8
-
-        // ignore bytes 42 - 43
-        let font_number = 0;
+    pub fn new(buffer: &[u8], encoding: TextEncoding) -> Result<WindowInfo> {
+        if buffer.len() < WINDOW_INFO_SIZE as usize {
+            return Err(Error::from(ODBError::BadPackedValue));
+        }
+
+        let window_rect = Rect::new(&buffer[0..8])?;
+        let font_name = read_pascal_string(&buffer[8..(8 + FONT_NAME_SIZE)], encoding)?; // bytes 8 - 41
+        let font_number = u16::from_be_bytes(buffer[42..44].try_into()?);
         let font_size = u16::from_be_bytes(buffer[44..46].try_into()?);
         let font_style = u16::from_be_bytes(buffer[46..48].try_into()?);
-        // ignore bytes 48 - 51
+        let window_ref = u32::from_be_bytes(buffer[48..52].try_into()?);
         let is_hidden = buffer[52] != 0;
         let is_unused = buffer[53] != 0;
+        let reserved = buffer[54..62].try_into()?; // short waste[4]
 
         Ok(WindowInfo {
-            window_rect: Rect {
-                top,
-                left,
-                bottom,
-                right,
-            },
-            font_string,
+            window_rect,
+            font_name,
             font_number,
             font_size,
             font_style,
+            window_ref,
             is_hidden,
             is_unused,
+            reserved,
         })
     }
+
+    pub fn window_rect(&self) -> Rect {
+        self.window_rect
+    }
+
+    pub fn font_name(&self) -> &str {
+        &self.font_name
+    }
+
+    // The font's number on the Mac that saved the record.
+    pub fn font_number(&self) -> u16 {
+        self.font_number
+    }
+
+    pub fn font_size(&self) -> u16 {
+        self.font_size
+    }
+
+    pub fn font_style(&self) -> u16 {
+        self.font_style
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.is_hidden
+    }
+
+    // The WindowPtr the window had when the record was saved.
+    pub fn window_ref(&self) -> u32 {
+        self.window_ref
+    }
+
+    pub fn is_unused(&self) -> bool {
+        self.is_unused
+    }
+
+    pub fn reserved(&self) -> &[u8; 8] {
+        &self.reserved
+    }
+
+    pub fn set_window_rect(&mut self, window_rect: Rect) {
+        self.window_rect = window_rect;
+    }
+
+    pub fn set_font_name(&mut self, font_name: &str) {
+        self.font_name = String::from(font_name);
+    }
+
+    pub fn set_font_number(&mut self, font_number: u16) {
+        self.font_number = font_number;
+    }
+
+    pub fn set_font_size(&mut self, font_size: u16) {
+        self.font_size = font_size;
+    }
+
+    pub fn set_font_style(&mut self, font_style: u16) {
+        self.font_style = font_style;
+    }
+
+    pub fn set_hidden(&mut self, is_hidden: bool) {
+        self.is_hidden = is_hidden;
+    }
+
+    pub fn set_window_ref(&mut self, window_ref: u32) {
+        self.window_ref = window_ref;
+    }
+
+    pub fn set_unused(&mut self, is_unused: bool) {
+        self.is_unused = is_unused;
+    }
+
+    pub fn set_reserved(&mut self, reserved: [u8; 8]) {
+        self.reserved = reserved;
+    }
+
+    pub fn to_bytes(
+        &self,
+        encoding: TextEncoding,
+        unmappable: Unmappable,
+    ) -> Result<[u8; WINDOW_INFO_SIZE as usize]> {
+        let mut bytes = [0; WINDOW_INFO_SIZE as usize];
+        bytes[0..8].copy_from_slice(&self.window_rect.to_bytes());
+        bytes[8..42].copy_from_slice(&write_pascal_string(
+            &self.font_name,
+            FONT_NAME_SIZE,
+            encoding,
+            unmappable,
+        )?);
+        bytes[42..44].copy_from_slice(&self.font_number.to_be_bytes());
+        bytes[44..46].copy_from_slice(&self.font_size.to_be_bytes());
+        bytes[46..48].copy_from_slice(&self.font_style.to_be_bytes());
+        bytes[48..52].copy_from_slice(&self.window_ref.to_be_bytes());
+        bytes[52] = self.is_hidden as u8;
+        bytes[53] = self.is_unused as u8;
+        bytes[54..62].copy_from_slice(&self.reserved);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The first window info in Sample.root's ODB record.
+    const SAMPLE_WINDOW_INFO: usize = 0x92;
+
+    #[test]
+    fn decodes_every_field() {
+        let sample = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/../Sample.root")).unwrap();
+        let buffer = &sample[SAMPLE_WINDOW_INFO..(SAMPLE_WINDOW_INFO + WINDOW_INFO_SIZE as usize)];
+
+        let info = WindowInfo::new(buffer, TextEncoding::MacRoman).unwrap();
+        assert_eq!(
+            info.window_rect,
+            Rect {
+                top: 385,
+                left: 520,
+                bottom: 535,
+                right: 920
+            }
+        );
+        assert_eq!(info.font_name, "Chicago");
+        assert_eq!(info.font_number, 0x3fff);
+        assert_eq!(info.font_size, 12);
+        assert!(!info.is_hidden);
+        assert!(!info.is_unused);
+
+        assert_eq!(
+            &info
+                .to_bytes(TextEncoding::MacRoman, Unmappable::Fail)
+                .unwrap()[..],
+            buffer
+        );
+    }
+
+    #[test]
+    fn every_field_can_be_changed() {
+        let sample = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/../Sample.root")).unwrap();
+        let buffer = &sample[SAMPLE_WINDOW_INFO..(SAMPLE_WINDOW_INFO + WINDOW_INFO_SIZE as usize)];
+
+        let mut info = WindowInfo::new(buffer, TextEncoding::MacRoman).unwrap();
+        info.set_window_rect(Rect {
+            top: 1,
+            left: 2,
+            bottom: 3,
+            right: 4,
+        });
+        info.set_font_name("Monaco");
+        info.set_font_number(4);
+        info.set_font_size(9);
+        info.set_font_style(1);
+        info.set_hidden(true);
+        info.set_window_ref(0x1234_5678);
+        info.set_unused(true);
+        info.set_reserved([1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let bytes = info
+            .to_bytes(TextEncoding::MacRoman, Unmappable::Fail)
+            .unwrap();
+        let saved = WindowInfo::new(&bytes, TextEncoding::MacRoman).unwrap();
+        assert_eq!(saved, info);
+        assert_eq!(saved.font_number(), 4);
+        assert_eq!(saved.font_style(), 1);
+        assert_eq!(saved.window_ref(), 0x1234_5678);
+        assert!(saved.is_unused());
+        assert_eq!(saved.reserved(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+    }
 }
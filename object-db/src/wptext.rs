@@ -0,0 +1,538 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::error::*;
+use crate::odb_error::*;
+use crate::string_utils::*;
+use crate::variable::*;
+use std::convert::TryInto;
+
+const WPTEXT_VERSION: u16 = 2;
+const WPTEXT_HEADER_SIZE: usize = 4;
+const FONT_NAME_SIZE: usize = 32;
+const RUN_RECORD_SIZE: usize = 10;
+const PARAGRAPH_SEPARATOR: char = '\r';
+
+// QuickDraw's text style bits.
+pub const BOLD: u16 = 0x01;
+pub const ITALIC: u16 = 0x02;
+pub const UNDERLINE: u16 = 0x04;
+
+// A word processing document: paragraphs of styled runs of text.
+//
+// Frontier packs word processing text in WS-Engine's format, which isn't
+// decoded yet, so roots keep it as an external value of packed bytes. This
+// is the form it takes in Brave Flea's own interchange format, and what it's
+// exported from.
+//
+// Packed, it starts with a version number and the size of the header,
+// followed by display state that's kept as it was read. Then comes the text,
+// a 4 byte length followed by the paragraphs separated by returns. The
+// formatting follows the text: a table of font names, then the runs, each
+// one the offset of its first character, the index of its font and its size
+// and style.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WpText {
+    pub version: u16,
+    pub paragraphs: Vec<Paragraph>,
+    display_state: Vec<u8>,
+    // Formatting that couldn't be read. The text is still loaded, as plain
+    // text, and the formatting is written back as it was.
+    unread_formatting: Option<UnreadFormatting>,
+}
+
+// The run offsets in formatting that couldn't be read only fit the text they
+// were read with, so the formatting is only written back with that text.
+#[derive(Debug, Clone, PartialEq)]
+struct UnreadFormatting {
+    text: Vec<u8>,
+    formatting: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Paragraph {
+    pub runs: Vec<Run>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Run {
+    pub text: String,
+    pub font_name: String,
+    pub font_size: u16,
+    pub font_style: u16,
+}
+
+impl WpText {
+    pub fn new() -> Self {
+        Self {
+            version: WPTEXT_VERSION,
+            paragraphs: vec![],
+            display_state: vec![],
+            unread_formatting: None,
+        }
+    }
+
+    // True if the formatting couldn't be decoded and the text was loaded
+    // without it.
+    pub fn is_formatting_lost(&self) -> bool {
+        self.unread_formatting.is_some()
+    }
+
+    pub fn to_plain_text(&self) -> String {
+        self.paragraphs
+            .iter()
+            .map(|paragraph| paragraph.text())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+
+        for paragraph in self.paragraphs.iter() {
+            html.push_str("<p>");
+            for run in paragraph.runs.iter() {
+                html.push_str(&run.to_html());
+            }
+            html.push_str("</p>\n");
+        }
+
+        html
+    }
+
+    // Markdown has no underline, so underlined text is left plain. Fonts and
+    // sizes are dropped too.
+    pub fn to_markdown(&self) -> String {
+        self.paragraphs
+            .iter()
+            .map(|paragraph| {
+                paragraph
+                    .runs
+                    .iter()
+                    .map(|run| run.to_markdown())
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    pub fn unpack(bytes: &[u8], context: &LoadContext) -> Result<Self> {
+        let encoding = context.text_encoding;
+
+        let header = bytes
+            .get(0..WPTEXT_HEADER_SIZE)
+            .ok_or(ODBError::BadPackedValue)?;
+        let version = u16::from_be_bytes(header[0..2].try_into()?);
+        let header_size = u16::from_be_bytes(header[2..4].try_into()?) as usize;
+        let display_state = bytes
+            .get(WPTEXT_HEADER_SIZE..header_size)
+            .ok_or(ODBError::BadPackedValue)?
+            .to_vec();
+
+        let length_bytes = bytes
+            .get(header_size..(header_size + 4))
+            .ok_or(ODBError::BadPackedValue)?;
+        let length = u32::from_be_bytes(length_bytes.try_into()?) as usize;
+        let index = header_size + 4;
+        let text = bytes
+            .get(index..(index + length))
+            .ok_or(ODBError::BadPackedValue)?;
+        let formatting = &bytes[(index + length)..];
+
+        let mut wptext = Self {
+            version,
+            paragraphs: vec![],
+            display_state,
+            unread_formatting: None,
+        };
+
+        match unpack_runs(text, formatting, context) {
+            Ok(runs) => wptext.paragraphs = split_paragraphs(runs),
+            Err(_) => {
+                wptext.paragraphs = encoding
+                    .decode(text)
+                    .split(PARAGRAPH_SEPARATOR)
+                    .map(|text| Paragraph {
+                        runs: vec![Run {
+                            text: String::from(text),
+                            ..Run::default()
+                        }],
+                    })
+                    .collect();
+                wptext.unread_formatting = Some(UnreadFormatting {
+                    text: text.to_vec(),
+                    formatting: formatting.to_vec(),
+                });
+            }
+        }
+
+        Ok(wptext)
+    }
+
+    pub fn pack(&self, context: &LoadContext) -> Result<Vec<u8>> {
+        let header_size = WPTEXT_HEADER_SIZE + self.display_state.len();
+
+        let mut bytes = self.version.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&(header_size as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.display_state);
+
+        let mut text = vec![];
+        let mut fonts: Vec<&str> = vec![];
+        let mut runs = vec![];
+
+        for (i, paragraph) in self.paragraphs.iter().enumerate() {
+            if i > 0 {
+                text.push(PARAGRAPH_SEPARATOR as u8);
+            }
+
+            for run in paragraph.runs.iter() {
+                let font_index = match fonts.iter().position(|name| *name == run.font_name) {
+                    Some(index) => index,
+                    None => {
+                        fonts.push(&run.font_name);
+                        fonts.len() - 1
+                    }
+                };
+
+                runs.extend_from_slice(&(text.len() as u32).to_be_bytes());
+                runs.extend_from_slice(&(font_index as u16).to_be_bytes());
+                runs.extend_from_slice(&run.font_size.to_be_bytes());
+                runs.extend_from_slice(&run.font_style.to_be_bytes());
+                text.extend(context.encode(&run.text)?);
+            }
+        }
+
+        bytes.extend_from_slice(&(text.len() as u32).to_be_bytes());
+
+        // Once the text has been edited, the plain runs it was loaded into
+        // are written instead.
+        if let Some(unread) = &self.unread_formatting {
+            if unread.text == text {
+                bytes.extend(text);
+                bytes.extend_from_slice(&unread.formatting);
+                return Ok(bytes);
+            }
+        }
+        bytes.extend(text);
+
+        bytes.extend_from_slice(&(fonts.len() as u16).to_be_bytes());
+        for name in fonts {
+            bytes.extend(write_pascal_string(
+                name,
+                FONT_NAME_SIZE,
+                context.text_encoding,
+                context.unmappable,
+            )?);
+        }
+        bytes.extend_from_slice(&((runs.len() / RUN_RECORD_SIZE) as u32).to_be_bytes());
+        bytes.extend(runs);
+
+        Ok(bytes)
+    }
+}
+
+impl Default for WpText {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Paragraph {
+    pub fn text(&self) -> String {
+        self.runs.iter().map(|run| run.text.as_str()).collect()
+    }
+}
+
+impl Run {
+    pub fn is_bold(&self) -> bool {
+        (self.font_style & BOLD) != 0
+    }
+
+    pub fn is_italic(&self) -> bool {
+        (self.font_style & ITALIC) != 0
+    }
+
+    pub fn is_underline(&self) -> bool {
+        (self.font_style & UNDERLINE) != 0
+    }
+
+    fn to_html(&self) -> String {
+        let mut html = escape_html(&self.text);
+
+        for (is_set, tag) in [
+            (self.is_underline(), "u"),
+            (self.is_italic(), "i"),
+            (self.is_bold(), "b"),
+        ] {
+            if is_set {
+                html = format!("<{}>{}</{}>", tag, html, tag);
+            }
+        }
+
+        if !self.font_name.is_empty() {
+            html = format!(
+                "<span style=\"font-family: '{}'; font-size: {}pt\">{}</span>",
+                escape_html(&self.font_name),
+                self.font_size,
+                html
+            );
+        }
+
+        html
+    }
+
+    fn to_markdown(&self) -> String {
+        let marker = match (self.is_bold(), self.is_italic()) {
+            (true, true) => "***",
+            (true, false) => "**",
+            (false, true) => "*",
+            (false, false) => "",
+        };
+
+        // Emphasis can't start or end with a space, so the spaces go outside
+        // the markers.
+        let trimmed = self.text.trim();
+        if marker.is_empty() || trimmed.is_empty() {
+            return escape_markdown(&self.text);
+        }
+
+        let start = self.text.len() - self.text.trim_start().len();
+        let end = self.text.trim_end().len();
+        format!(
+            "{}{}{}{}{}",
+            &self.text[..start],
+            marker,
+            escape_markdown(trimmed),
+            marker,
+            &self.text[end..]
+        )
+    }
+}
+
+// Reads the font table and runs, and slices the text into the runs.
+fn unpack_runs(text: &[u8], formatting: &[u8], context: &LoadContext) -> Result<Vec<Run>> {
+    let encoding = context.text_encoding;
+
+    let count_bytes = formatting.get(0..2).ok_or(ODBError::BadPackedValue)?;
+    let font_count = u16::from_be_bytes(count_bytes.try_into()?) as usize;
+    let mut index = 2;
+    let mut fonts = vec![];
+    for _ in 0..font_count {
+        let field = formatting
+            .get(index..(index + FONT_NAME_SIZE))
+            .ok_or(ODBError::BadPackedValue)?;
+        fonts.push(read_pascal_string(field, encoding)?);
+        index += FONT_NAME_SIZE;
+    }
+
+    let count_bytes = formatting
+        .get(index..(index + 4))
+        .ok_or(ODBError::BadPackedValue)?;
+    let run_count = u32::from_be_bytes(count_bytes.try_into()?) as usize;
+    index += 4;
+
+    let mut records = vec![];
+    for _ in 0..run_count {
+        let record = formatting
+            .get(index..(index + RUN_RECORD_SIZE))
+            .ok_or(ODBError::BadPackedValue)?;
+        records.push(record);
+        index += RUN_RECORD_SIZE;
+    }
+
+    let mut runs = vec![];
+    for (i, record) in records.iter().enumerate() {
+        let start = u32::from_be_bytes(record[0..4].try_into()?) as usize;
+        let end = match records.get(i + 1) {
+            Some(next) => u32::from_be_bytes(next[0..4].try_into()?) as usize,
+            None => text.len(),
+        };
+        let font_index = u16::from_be_bytes(record[4..6].try_into()?) as usize;
+
+        runs.push(Run {
+            text: encoding.decode(text.get(start..end).ok_or(ODBError::BadPackedValue)?),
+            font_name: fonts
+                .get(font_index)
+                .cloned()
+                .ok_or(ODBError::BadPackedValue)?,
+            font_size: u16::from_be_bytes(record[6..8].try_into()?),
+            font_style: u16::from_be_bytes(record[8..10].try_into()?),
+        });
+    }
+
+    // Text that isn't covered by a run can't be given its formatting.
+    let covered = match records.first() {
+        Some(record) => u32::from_be_bytes(record[0..4].try_into()?) == 0,
+        None => text.is_empty(),
+    };
+    if !covered {
+        return Err(Error::from(ODBError::BadPackedValue));
+    }
+
+    Ok(runs)
+}
+
+// Breaks runs at the returns between paragraphs.
+fn split_paragraphs(runs: Vec<Run>) -> Vec<Paragraph> {
+    let mut paragraphs = vec![Paragraph::default()];
+
+    for run in runs {
+        for (i, text) in run.text.split(PARAGRAPH_SEPARATOR).enumerate() {
+            if i > 0 {
+                paragraphs.push(Paragraph::default());
+            }
+            if !text.is_empty() {
+                paragraphs.last_mut().unwrap().runs.push(Run {
+                    text: String::from(text),
+                    ..run.clone()
+                });
+            }
+        }
+    }
+
+    paragraphs
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if "\\`*_[]#<>".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> LoadContext {
+        LoadContext::for_platform(Platform::Mac)
+    }
+
+    fn run(text: &str, font_style: u16) -> Run {
+        Run {
+            text: String::from(text),
+            font_name: String::from("Geneva"),
+            font_size: 12,
+            font_style,
+        }
+    }
+
+    fn note() -> WpText {
+        let mut wptext = WpText::new();
+        wptext.paragraphs.push(Paragraph {
+            runs: vec![
+                run("Frontier is ", 0),
+                run("fast ", BOLD),
+                run("& fun", ITALIC),
+            ],
+        });
+        wptext.paragraphs.push(Paragraph {
+            runs: vec![run("Read the *docs*", UNDERLINE)],
+        });
+        wptext
+    }
+
+    #[test]
+    fn exports_text_html_and_markdown() {
+        let wptext = note();
+
+        assert_eq!(
+            wptext.to_plain_text(),
+            "Frontier is fast & fun\nRead the *docs*"
+        );
+        assert_eq!(
+            wptext.to_markdown(),
+            "Frontier is **fast** *& fun*\n\nRead the \\*docs\\*"
+        );
+        assert!(wptext.to_html().starts_with(
+            "<p><span style=\"font-family: 'Geneva'; font-size: 12pt\">Frontier is </span>"
+        ));
+        assert!(wptext.to_html().contains("<b>fast </b>"));
+        assert!(wptext.to_html().contains("<i>&amp; fun</i>"));
+    }
+
+    #[test]
+    fn packs_what_it_unpacks() {
+        let wptext = note();
+        let bytes = wptext.pack(&context()).unwrap();
+        let unpacked = WpText::unpack(&bytes, &context()).unwrap();
+
+        assert_eq!(unpacked, wptext);
+        assert!(!unpacked.is_formatting_lost());
+        assert_eq!(unpacked.pack(&context()).unwrap(), bytes);
+    }
+
+    #[test]
+    fn bad_formatting_falls_back_to_plain_text() {
+        let mut bytes = vec![0, 2, 0, 4, 0, 0, 0, 11];
+        bytes.extend_from_slice(b"Hello\rWorld");
+        bytes.extend_from_slice(&[0, 9, 0]);
+
+        let wptext = WpText::unpack(&bytes, &context()).unwrap();
+        assert!(wptext.is_formatting_lost());
+        assert_eq!(wptext.paragraphs.len(), 2);
+        assert_eq!(wptext.to_plain_text(), "Hello\nWorld");
+        assert_eq!(wptext.pack(&context()).unwrap(), bytes);
+
+        assert!(WpText::unpack(&bytes[0..10], &context()).is_err());
+    }
+
+    #[test]
+    fn edited_text_drops_the_unread_formatting() {
+        let mut bytes = vec![0, 2, 0, 4, 0, 0, 0, 11];
+        bytes.extend_from_slice(b"Hello\rWorld");
+        bytes.extend_from_slice(&[0, 9, 0]);
+
+        let mut wptext = WpText::unpack(&bytes, &context()).unwrap();
+        wptext.paragraphs[0].runs[0].text = String::from("Goodbye");
+
+        let edited = WpText::unpack(&wptext.pack(&context()).unwrap(), &context()).unwrap();
+        assert!(!edited.is_formatting_lost());
+        assert_eq!(edited.to_plain_text(), "Goodbye\nWorld");
+    }
+
+    #[test]
+    fn html_escapes_quotes() {
+        let mut wptext = WpText::new();
+        wptext.paragraphs.push(Paragraph {
+            runs: vec![Run {
+                text: String::from("It's"),
+                font_name: String::from("O'Hare"),
+                font_size: 12,
+                font_style: 0,
+            }],
+        });
+
+        assert_eq!(
+            wptext.to_html(),
+            "<p><span style=\"font-family: 'O&#39;Hare'; font-size: 12pt\">It&#39;s</span></p>\n"
+        );
+    }
+}
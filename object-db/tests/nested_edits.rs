@@ -0,0 +1,95 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use object_db::{ObjectDB, Table, Value};
+use std::fs;
+use std::path::PathBuf;
+
+// A copy of Sample.root that's deleted when it goes out of scope.
+struct TempRoot(PathBuf);
+
+impl TempRoot {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("brave-flea-{}-{}.root", name, std::process::id()));
+        fs::copy(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/../Sample.root"),
+            &path,
+        )
+        .unwrap();
+        Self(path)
+    }
+
+    fn open(&self) -> ObjectDB {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.0)
+            .unwrap();
+        ObjectDB::load_file(file).unwrap()
+    }
+}
+
+impl Drop for TempRoot {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn string(value: &Value) -> &str {
+    match value {
+        Value::StringValue(s) => s,
+        other => panic!("expected a string, found {:?}", other),
+    }
+}
+
+#[test]
+fn nested_items_are_edited_saved_and_reopened() {
+    let root = TempRoot::new("nested-edits");
+
+    let mut b = Table::new();
+    b.insert("c", Value::StringValue(String::from("before")))
+        .unwrap();
+    b.insert("d", Value::LongValue(4)).unwrap();
+    let mut a = Table::new();
+    a.insert("b", Value::TableValue(Box::new(b))).unwrap();
+
+    let mut odb = root.open();
+    odb.root_mut()
+        .insert("a", Value::TableValue(Box::new(a)))
+        .unwrap();
+    odb.save().unwrap();
+    drop(odb);
+
+    // a and b are still on disk when they're reached through the path.
+    let mut odb = root.open();
+    odb.lookup_table_mut("a.b")
+        .unwrap()
+        .set_value("c", Value::StringValue(String::from("after")))
+        .unwrap();
+    odb.save().unwrap();
+    drop(odb);
+
+    let mut odb = root.open();
+    assert_eq!(string(odb.lookup("a.b.c").unwrap().value()), "after");
+    assert!(matches!(
+        odb.lookup("a.b.d").unwrap().value(),
+        Value::LongValue(4)
+    ));
+    assert_eq!(string(odb.lookup("firstName").unwrap().value()), "Ted");
+}
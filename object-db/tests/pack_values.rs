@@ -0,0 +1,64 @@
+/*
+Brave Flea
+Copyright (C) 2020  Ted C. Howard
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use object_db::{pack_value, unpack_value, Error, LoadContext, ODBError, Platform, Table, Value};
+
+#[test]
+fn values_are_packed_and_unpacked_outside_the_crate() {
+    let context = LoadContext::for_platform(Platform::Mac);
+
+    let mut table = Table::new();
+    table
+        .insert("name", Value::StringValue(String::from("Café")))
+        .unwrap();
+    let value = Value::RecordValue(vec![
+        (
+            String::from("items"),
+            Value::ListValue(vec![Value::LongValue(1)]),
+        ),
+        (String::from("prefs"), Value::TableValue(Box::new(table))),
+    ]);
+
+    let bytes = pack_value(&value, &context).unwrap();
+    let unpacked = unpack_value(&bytes, &context).unwrap();
+    match &unpacked {
+        Value::RecordValue(items) => {
+            assert_eq!(items[0].0, "items");
+            assert!(matches!(&items[0].1, Value::ListValue(list) if list.len() == 1));
+        }
+        value => panic!("{:?}", value),
+    }
+    assert_eq!(pack_value(&unpacked, &context).unwrap(), bytes);
+
+    // The encoding comes from the context.
+    let windows = LoadContext::for_platform(Platform::Windows);
+    match unpack_value(&bytes, &windows).unwrap() {
+        Value::RecordValue(items) => match &items[1].1 {
+            Value::TableValue(table) => assert!(
+                matches!(table.get("name").unwrap().value(), Value::StringValue(s) if s != "Café")
+            ),
+            value => panic!("{:?}", value),
+        },
+        value => panic!("{:?}", value),
+    }
+
+    assert!(matches!(
+        pack_value(&Value::StringValue(String::from("日本")), &context),
+        Err(Error::ODB(ODBError::UnmappableCharacter { .. }))
+    ));
+}